@@ -1,14 +1,16 @@
 pub trait BonDriver {
+    #[allow(unused_attributes)]
     #[no_mangle]
     extern "system" fn open_tuner(&self) -> bool { false }
     extern "system" fn close_tuner(&self) -> bool { false }
 
-    extern "system" fn set_channel(&self, channel: u8) -> bool { false }
+    extern "system" fn set_channel(&self, _channel: u8) -> bool { false }
     extern "system" fn get_signal_level(&self) -> f32 { 0f32 }
 
-    extern "system" fn wait_ts_stream(&self, timeout: u32) -> u32 { 0 }
+    extern "system" fn wait_ts_stream(&self, _timeout: u32) -> u32 { 0 }
     extern "system" fn get_ready_count(&self) -> u32 { 0 }
 
+    #[allow(improper_ctypes_definitions)]
     extern "system" fn get_ts_stream(&self) -> Vec<u8> { vec![] }
 }
 
@@ -18,6 +20,8 @@ impl BonDriver for TestBonDriver {
 
 }
 
+/// # Safety
+/// 返されたポインタは Box::from_raw で解放する必要があります
 #[no_mangle]
 pub unsafe extern "system" fn CreateBonDriver() -> *const TestBonDriver {
     let instance = TestBonDriver{};
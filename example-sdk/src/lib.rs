@@ -1,8 +1,11 @@
+// TVTest のプラグインは Windows でのみビルドされます
+#![cfg(windows)]
+
 use std::cell::Cell;
 use std::sync::atomic::{AtomicI32, Ordering};
 use tvtest::enumflags2::BitFlag;
 use tvtest::api::PluginApi;
-use tvtest::{export_plugin, TVTestEventHandler, TVTestPlugin};
+use tvtest::{export_plugin, wstr, TVTestEventHandler, TVTestPlugin};
 use tvtest::plugin::{PluginFlag, PluginInfo, PluginKind};
 use tvtest::version::{DEFAULT_API_VERSION, Version};
 use tvtest::win32::{IntoRustString, UnsafeIntoRustString, WideStringPtr};
@@ -46,15 +49,15 @@ impl TVTestPlugin for ExamplePlugin {
 
     /// **必須**
     /// プラグインの情報を返します
-    /// null 終端ワイド文字列 (`WideString` 構造体) と Rust 文字列 (`String`) との変換は内部で行われます
+    /// 文字列は TVTest が読み取るまで生存している必要があるため、`wstr!` でコンパイル時に null 終端ワイド文字列に変換します
     fn get_info() -> PluginInfo {
-        PluginInfo {
-            kind: PluginKind::Normal,
-            flags: PluginFlag::empty(),
-            name: "Example".into(),
-            copyright: "© 2021 @SlashNephy <spica@starry.blue>".into(),
-            description: "TVTestSDK-rs のサンプルプラグイン".into(),
-        }
+        PluginInfo::new(
+            PluginKind::Normal,
+            PluginFlag::empty(),
+            wstr!("Example"),
+            wstr!("© 2021 @SlashNephy <spica@starry.blue>"),
+            wstr!("TVTestSDK-rs のサンプルプラグイン"),
+        )
     }
 
     /// 初期化を行います
@@ -1,23 +1,48 @@
 use std::cmp::min;
 use std::ffi::c_void;
+use std::mem::size_of;
 use std::ptr;
 use std::ptr::NonNull;
 use std::sync::Arc;
 
-use windows::Win32::Foundation::{HWND, LPARAM};
+use enumflags2::BitFlags;
+use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, RECT, WPARAM};
 use windows::Win32::Foundation::HINSTANCE;
+use windows::Win32::Graphics::Gdi::{HBITMAP, HDC};
 
-use crate::channel::ChannelInfo;
+use crate::channel::{ChannelInfo, ChannelSelectError, ChannelSelectInfo, ChannelSelector};
+use crate::controller::{ControllerInfo, ControllerSettings};
 use crate::{ClientData};
+use crate::dialog::{DialogBuilder, DialogHandler, ModelessDialog, ShowDialogInfo};
+use crate::dpi::{DpiTarget, GetDpiInfo};
 use crate::event::EventCallbackFunc;
+use crate::favorite::{FavoriteList, Favorites};
+use crate::font::{Font, GetFontInfo};
+use crate::host::{GetHostInfo, HostInfo};
 use crate::log::LogKind;
+use crate::logo::{Logo, LogoType};
+use crate::memory::HostWideString;
 use crate::message::Message;
+use crate::panel::{PanelItemGetInfo, PanelItemGetInfoMask, PanelItemInfo, PanelItemSetInfo, PanelItemSetInfoMask, PanelItemState};
 use crate::plugin::PluginParam;
+use crate::program_guide::{ProgramGuideCommandInfo, ProgramGuideEventFlag};
 use crate::service::{GetServiceInfo, ServiceInfo};
+use crate::setting::{FromSetting, SettingInfo};
+use crate::record::{RecordStatusFlag, RecordStatusInfo};
+use crate::stereo_mode::StereoMode;
+use crate::status::StatusInfo;
+use crate::status_item::{StatusItemGetInfo, StatusItemGetInfoMask, StatusItemInfo, StatusItemNotify, StatusItemSetInfo, StatusItemSetInfoMask, StatusItemState};
+use crate::style::{StyleUnit, StyleValueInfo};
+use crate::time::{ConvertTimeInfo, TVTestTime};
+use crate::theme::{ThemeDrawBackgroundFlag, ThemeDrawBackgroundInfo, ThemeDrawIconInfo, ThemeDrawTextInfo};
+use crate::ts_processor::TsProcessorInfo;
 use crate::tuning_space::GetTuningSpaceNameInfo;
 use crate::version::Version;
-use crate::win32::{IntoRustString, IntoWideString, make_long, make_lparam, UnsafePtr};
+use crate::window_message::WindowMessageHook;
+use crate::variable::{RegisterVariableInfo, VarStringContext, VarStringFormat, VarStringFormatInfo};
+use crate::win32::{IntoRustString, IntoWideString, make_long, make_lparam, UnsafePtr, WideString, WideStringBuffer, WideStringPtr};
 
+#[derive(Clone)]
 pub struct PluginApi {
     pub dll: Arc<HINSTANCE>,
     pub param: Arc<PluginParam>,
@@ -64,27 +89,27 @@ impl PluginApi  {
     }
 
     // 文字列複製
-    // pub unsafe fn string_duplicate(&self, string: PWSTR) -> PWSTR {
-    //     let size = (lstrlenW(string) + 1) as usize * size_of::<u16>();
-    //     let dup = self.memory_alloc(size as isize);
-    //     if !dup.is_null() {
-    //         let ptr = string.0 as *mut c_void;
-    //         copy(ptr, dup, size);
-    //     }
-    //
-    //     unimplemented!("TODO")
-    //     // return dup;
-    // }
+    // TVTest のメモリ確保関数で確保するため、TVTest に解放させる文字列に使用します。
+    // 確保に失敗した場合はぬるぽを返します。
+    pub fn string_duplicate(&self, string: &str) -> WideStringPtr {
+        HostWideString::new(self, string)
+            .map(HostWideString::into_raw)
+            .unwrap_or_default()
+    }
 
     // イベントハンドル用コールバックの設定
     // pClientData はコールバックの呼び出し時に渡されます。
     // 一つのプラグインで設定できるコールバック関数は一つだけです。
     // Callback に nullptr を渡すと設定が解除されます。
+    /// # Safety
+    /// callback は設定が解除されるまで、TVTest から任意のイベントで呼び出せる必要があります
     pub unsafe fn set_event_callback(&self, callback: EventCallbackFunc) -> bool {
         let ptr = NonNull::new_unchecked(callback as *mut EventCallbackFunc);
         let ptr2 = ptr::null::<c_void>();
         self.param.send_message_bool(Message::SetEventCallback, LPARAM(ptr.as_ptr() as isize), LPARAM(ptr2 as isize))
     }
+    /// # Safety
+    /// set_event_callback と同じ条件に加え、client_data は設定が解除されるまで有効である必要があります
     pub unsafe fn set_event_callback_with_client_data(&self, callback: EventCallbackFunc, client_data: &ClientData) -> bool {
         let ptr = NonNull::new_unchecked(callback as *mut EventCallbackFunc);
         let ptr2 = NonNull::from(client_data);
@@ -98,8 +123,8 @@ impl PluginApi  {
 
     // 現在のチャンネルの情報を取得する
     pub fn get_current_channel_info(&self) -> Option<ChannelInfo> {
-        let info = ChannelInfo::default();
-        let ptr = &info as *const ChannelInfo;
+        let mut info = ChannelInfo::default();
+        let ptr = &mut info as *mut ChannelInfo;
         let result = self.param.send_message_bool(Message::GetCurrentChannelInfo, LPARAM(ptr as isize), LPARAM(0));
 
         if result {
@@ -134,9 +159,9 @@ impl PluginApi  {
         }
     }
     pub fn get_service(&self) -> Option<GetServiceInfo> {
-        let num = 0;
-        let ptr = NonNull::from(&num);
-        let index = self.param.send_message(Message::GetService, LPARAM(ptr.as_ptr() as isize), LPARAM(0)).0;
+        let mut num = 0;
+        let ptr = &mut num as *mut i32;
+        let index = self.param.send_message(Message::GetService, LPARAM(ptr as isize), LPARAM(0)).0;
 
         if index != -1 {
             GetServiceInfo {
@@ -209,9 +234,9 @@ impl PluginApi  {
     // 現在のチャンネルのサービスの情報を取得します。
     // 事前に ServiceInfo の Size メンバを設定しておきます。
     pub fn get_service_info(&self, index: i32) -> Option<ServiceInfo> {
-        let info = ServiceInfo::default();
-        let ptr = NonNull::from(&info);
-        let result = self.param.send_message_bool(Message::GetServiceInfo, LPARAM(index as isize), LPARAM(ptr.as_ptr() as isize));
+        let mut info = ServiceInfo::default();
+        let ptr = &mut info as *mut ServiceInfo;
+        let result = self.param.send_message_bool(Message::GetServiceInfo, LPARAM(index as isize), LPARAM(ptr as isize));
 
         if result {
             info.into()
@@ -225,32 +250,23 @@ impl PluginApi  {
     // pszName を nullptr で呼べば長さだけを取得できます。
     // 取得されるのは、ディレクトリを含まないファイル名のみか、相対パスの場合もあります。
     // フルパスを取得したい場合は MsgGetDriverFullPathName を使用してください。
-    // pub fn get_driver_name_length(&self) -> Option<usize> {
-    //     let ptr = ptr::null::<u16>();
-    //     let param = make_lparam(index as u16,  0xFFFF);
-    //     let result = self.param.send_message(Message::GetTuningSpaceName, LPARAM(ptr as isize), param).0;
-    //
-    //     if result > 0 {
-    //         (result as usize).into()
-    //     } else {
-    //         None
-    //     }
-    // }
-    // pub fn get_driver_name_name(&self, index: i32, max_length: u16) -> Option<GetTuningSpaceNameInfo> {
-    //     let vec: Vec<u16> = Vec::with_capacity(max_length as usize);
-    //     let ptr = vec.as_ptr();
-    //     let param = make_lparam(index as u16, min(max_length, 0xFFFF));
-    //     let result = self.param.send_message(Message::GetTuningSpaceName, LPARAM(ptr as isize), param).0;
-    //
-    //     if result > 0 {
-    //         GetTuningSpaceNameInfo {
-    //             length: result as usize,
-    //             name: vec.into_string(),
-    //         }.into()
-    //     } else {
-    //         None
-    //     }
-    // }
+    pub fn get_driver_name(&self) -> Option<String> {
+        let length = self.param.send_message(Message::GetDriverName, LPARAM(0), LPARAM(0)).0;
+        if length <= 0 {
+            return None;
+        }
+
+        let mut buffer = vec![0u16; length as usize + 1];
+        let ptr = buffer.as_mut_ptr();
+        let result = self.param.send_message(Message::GetDriverName, LPARAM(ptr as isize), LPARAM(buffer.len() as isize)).0;
+
+        if result > 0 {
+            buffer.truncate(min(result, length) as usize);
+            buffer.into_string().into()
+        } else {
+            None
+        }
+    }
 
     // ログを記録する
     // 設定のログの項目に表示されます。
@@ -267,4 +283,624 @@ impl PluginApi  {
 
         self.param.send_message_bool(Message::AddLog, LPARAM(ptr as isize), LPARAM(log_type))
     }
+
+    // ホストプログラムの情報を取得する
+    // 文字列はコピーされるので、TVTest 側のメモリの寿命を気にせず扱えます。
+    pub fn host_info(&self) -> Option<GetHostInfo> {
+        let mut info = HostInfo::default();
+        let ptr = &mut info as *mut HostInfo;
+        let result = self.param.send_message_bool(Message::GetHostInfo, LPARAM(ptr as isize), LPARAM(0));
+
+        if result {
+            GetHostInfo::from(&info).into()
+        } else {
+            None
+        }
+    }
+
+    // 設定を取得する
+    // 呼び出す前に SettingInfo のメンバを設定しておきます。
+    // 文字列とデータの場合、ValueSize に設定の格納に必要なバイト数が返されます。
+    // 通常は型を指定できる get_setting を使用した方が便利です。
+    pub fn get_setting_info(&self, info: &mut SettingInfo) -> bool {
+        let ptr = info as *mut SettingInfo;
+
+        self.param.send_message_bool(Message::GetSetting, LPARAM(ptr as isize), LPARAM(0))
+    }
+
+    // 型を指定して設定を取得する
+    // i32, u32, String, Vec<u8> を取得できます。
+    // 設定名は setting::INI_FILE_PATH などの定数を使用できます。設定名の大文字と小文字は区別されません。
+    // 取得された値の型が一致しない場合は None が返ります。
+    pub fn get_setting<T: FromSetting>(&self, name: &str) -> Option<T> {
+        T::get_setting(self, name)
+    }
+
+    // ロゴの画像を取得する
+    // 画像が取得できた場合はビットマップ(DIBセクション)が返ります。
+    // ビットマップは Logo の Drop 時に破棄されます。
+    pub fn get_logo(&self, network_id: u16, service_id: u16, logo_type: LogoType) -> Option<Logo> {
+        let result = self.param.send_message(Message::GetLogo, make_lparam(network_id, service_id), LPARAM(logo_type as isize));
+
+        Logo::from_raw(HBITMAP(result.0))
+    }
+
+    // 利用可能なロゴの種類を取得する
+    pub fn get_available_logo_types(&self, network_id: u16, service_id: u16) -> Vec<LogoType> {
+        let result = self.param.send_message(Message::GetAvailableLogoType, make_lparam(network_id, service_id), LPARAM(0)).0 as u32;
+
+        LogoType::from_available_bits(result)
+    }
+
+    // 利用可能なすべてのロゴの画像を取得する
+    pub fn get_logos(&self, network_id: u16, service_id: u16) -> Vec<(LogoType, Logo)> {
+        self.get_available_logo_types(network_id, service_id)
+            .into_iter()
+            .filter_map(|logo_type| {
+                self.get_logo(network_id, service_id, logo_type).map(|logo| (logo_type, logo))
+            })
+            .collect()
+    }
+
+    // ウィンドウメッセージコールバックの設定
+    // メインウィンドウのメッセージを処理した場合は Some(戻り値) を返します。
+    // 一つのプラグインで設定できるコールバックは一つだけです。
+    // 返された WindowMessageHook を破棄すると設定が解除されます。
+    pub fn set_window_message_callback<F>(&self, handler: F) -> Option<WindowMessageHook>
+        where F: Fn(HWND, u32, WPARAM, LPARAM) -> Option<LRESULT> + 'static
+    {
+        WindowMessageHook::register(Arc::clone(&self.param), Box::new(handler))
+    }
+
+    // コントローラを登録する
+    // 通常は ControllerBuilder を使用した方が便利です。
+    // ControllerInfo が参照する文字列やボタンのリストはプラグインの終了まで保持する必要があります。
+    pub fn register_controller(&self, info: &ControllerInfo) -> bool {
+        let ptr = info as *const ControllerInfo;
+
+        self.param.send_message_bool(Message::RegisterController, LPARAM(ptr as isize), LPARAM(0))
+    }
+
+    // コントローラのボタンが押されたことを通知する
+    pub fn on_controller_button_down(&self, name: &str, button: i32) -> bool {
+        let name = name.into_wide_string();
+        let ptr = name.0.as_ptr();
+
+        self.param.send_message_bool(Message::OnControllerButtonDown, LPARAM(ptr as isize), LPARAM(button as isize))
+    }
+
+    // コントローラの設定を取得する
+    pub fn get_controller_settings(&self, name: &str) -> Option<ControllerSettings> {
+        let name = name.into_wide_string();
+        let mut settings = ControllerSettings::default();
+        let ptr = &mut settings as *mut ControllerSettings;
+        let result = self.param.send_message_bool(Message::GetControllerSettings, LPARAM(name.0.as_ptr() as isize), LPARAM(ptr as isize));
+
+        if result {
+            settings.into()
+        } else {
+            None
+        }
+    }
+
+    // 番組表のイベントの有効/無効を設定する
+    // 番組表のイベント(on_program_guide_*)の通知が必要な場合に設定します。
+    pub fn enable_program_guide_event(&self, flags: BitFlags<ProgramGuideEventFlag>) -> bool {
+        self.param.send_message_bool(Message::EnableProgramGuideEvent, LPARAM(flags.bits() as isize), LPARAM(0))
+    }
+
+    // 番組表のコマンドを登録する
+    // 通常は ProgramGuideCommands を使用した方が便利です。
+    // コマンドが実行されると on_program_guide_command が呼ばれます。
+    pub fn register_program_guide_command(&self, commands: &[ProgramGuideCommandInfo]) -> bool {
+        self.param.send_message_bool(Message::RegisterProgramGuideCommand, LPARAM(commands.as_ptr() as isize), LPARAM(commands.len() as isize))
+    }
+
+    // スタイル値を取得する
+    // 通常は get_style_value / get_style_value_pixels を使用した方が簡単です。
+    pub fn get_style_value_info(&self, info: &mut StyleValueInfo) -> bool {
+        let ptr = info as *mut StyleValueInfo;
+
+        self.param.send_message_bool(Message::GetStyleValue, LPARAM(ptr as isize), LPARAM(0))
+    }
+
+    // 指定された単位のスタイル値を取得する
+    // TVTest.style.ini で設定された "status-bar.item.padding.left" などのスタイル値を取得します。
+    // StyleUnit::Undefined を指定するとオリジナルの単位の値が取得されます。
+    pub fn get_style_value(&self, name: &str, unit: StyleUnit, dpi: i32) -> Option<i32> {
+        let name = name.into_wide_string();
+        let mut info = StyleValueInfo::new(name.as_wstr()?, unit, dpi);
+
+        if self.get_style_value_info(&mut info) {
+            info.value.into()
+        } else {
+            None
+        }
+    }
+
+    // 物理ピクセル単位のスタイル値を取得する
+    // dpi に 0 を指定するとメインウィンドウの DPI で変換されます。
+    pub fn get_style_value_pixels(&self, name: &str, dpi: i32) -> Option<i32> {
+        self.get_style_value(name, StyleUnit::PhysicalPixel, dpi)
+    }
+
+    // テーマの背景を描画する
+    pub fn theme_draw_background_info(&self, info: &mut ThemeDrawBackgroundInfo) -> bool {
+        info.size = size_of::<ThemeDrawBackgroundInfo>() as u32;
+        let ptr = info as *mut ThemeDrawBackgroundInfo;
+
+        self.param.send_message_bool(Message::ThemeDrawBackground, LPARAM(ptr as isize), LPARAM(0))
+    }
+
+    // テーマの背景を描画する
+    // dpi に 0 を指定するとメインウィンドウと同じ DPI で描画されます。
+    pub fn theme_draw_background(&self, style: &str, hdc: HDC, rect: &RECT, dpi: i32) -> bool {
+        let strings = WideStringBuffer::new();
+        let mut info = ThemeDrawBackgroundInfo::new(strings.push(style), hdc, *rect, dpi);
+
+        self.theme_draw_background_info(&mut info)
+    }
+
+    // テーマの背景を描画し、内側のクライアント領域を取得する
+    // 背景の上に文字列などを描画する際の領域として利用できます。
+    pub fn theme_draw_background_client_rect(&self, style: &str, hdc: HDC, rect: &RECT, dpi: i32) -> Option<RECT> {
+        let strings = WideStringBuffer::new();
+        let mut info = ThemeDrawBackgroundInfo::new(strings.push(style), hdc, *rect, dpi);
+        info.flags = ThemeDrawBackgroundFlag::AdjustRect.into();
+
+        if self.theme_draw_background_info(&mut info) {
+            info.draw_rect.into()
+        } else {
+            None
+        }
+    }
+
+    // テーマの文字列を描画する
+    pub fn theme_draw_text_info(&self, info: &mut ThemeDrawTextInfo) -> bool {
+        info.size = size_of::<ThemeDrawTextInfo>() as u32;
+        let ptr = info as *mut ThemeDrawTextInfo;
+
+        self.param.send_message_bool(Message::ThemeDrawText, LPARAM(ptr as isize), LPARAM(0))
+    }
+
+    // テーマの文字列を描画する
+    // draw_flags には DrawText API の DT_* を指定します。
+    // color に None を指定するとテーマの色で描画されます。
+    pub fn theme_draw_text(&self, style: &str, hdc: HDC, text: &str, rect: &RECT, draw_flags: u32, color: Option<u32>) -> bool {
+        let strings = WideStringBuffer::new();
+        let mut info = ThemeDrawTextInfo::new(strings.push(style), hdc, strings.push(text), *rect, draw_flags, color);
+
+        self.theme_draw_text_info(&mut info)
+    }
+
+    // テーマのアイコンを描画する
+    pub fn theme_draw_icon_info(&self, info: &mut ThemeDrawIconInfo) -> bool {
+        info.size = size_of::<ThemeDrawIconInfo>() as u32;
+        let ptr = info as *mut ThemeDrawIconInfo;
+
+        self.param.send_message_bool(Message::ThemeDrawIcon, LPARAM(ptr as isize), LPARAM(0))
+    }
+
+    // テーマのアイコンを描画する
+    // hbm の src_rect の領域を dest_rect に描画します。
+    // color に None を指定するとテーマの色で描画されます。opacity は 1-255 で指定します。
+    #[allow(clippy::too_many_arguments)]
+    pub fn theme_draw_icon(&self, style: &str, hdc: HDC, dest_rect: &RECT, hbm: HBITMAP, src_rect: &RECT, color: Option<u32>, opacity: u8) -> bool {
+        let strings = WideStringBuffer::new();
+        let mut info = ThemeDrawIconInfo::new(strings.push(style), hdc, *dest_rect, hbm, *src_rect, color, opacity);
+
+        self.theme_draw_icon_info(&mut info)
+    }
+
+    // ステータス項目を登録する
+    // 通常は StatusItems を使用した方が便利です。
+    pub fn register_status_item(&self, info: &StatusItemInfo) -> bool {
+        let ptr = info as *const StatusItemInfo;
+
+        self.param.send_message_bool(Message::RegisterStatusItem, LPARAM(ptr as isize), LPARAM(0))
+    }
+
+    // ステータス項目を設定する
+    // StatusItemSetInfo の mask に設定したい情報を、id に設定したい項目の識別子を指定して呼び出します。
+    pub fn set_status_item(&self, info: &StatusItemSetInfo) -> bool {
+        let ptr = info as *const StatusItemSetInfo;
+
+        self.param.send_message_bool(Message::SetStatusItem, LPARAM(ptr as isize), LPARAM(0))
+    }
+
+    // ステータス項目の表示状態を設定する
+    pub fn show_status_item(&self, id: i32, visible: bool) -> bool {
+        let mut info = StatusItemSetInfo::new(id);
+        info.mask = StatusItemSetInfoMask::State.into();
+        info.state_mask = StatusItemState::Visible.into();
+        if visible {
+            info.state = StatusItemState::Visible.into();
+        }
+
+        self.set_status_item(&info)
+    }
+
+    // ステータス項目の情報を取得する
+    // StatusItemGetInfo の mask に取得したい情報を、id に取得したい項目の識別子を指定して呼び出します。
+    pub fn get_status_item_info(&self, info: &mut StatusItemGetInfo) -> bool {
+        let ptr = info as *mut StatusItemGetInfo;
+
+        self.param.send_message_bool(Message::GetStatusItemInfo, LPARAM(ptr as isize), LPARAM(0))
+    }
+
+    // ステータス項目が表示されているか取得する
+    pub fn is_status_item_visible(&self, id: i32) -> bool {
+        let mut info = StatusItemGetInfo::new(id, StatusItemGetInfoMask::State.into());
+
+        self.get_status_item_info(&mut info) && info.state.contains(StatusItemState::Visible)
+    }
+
+    // ステータス項目の通知を行う
+    pub fn status_item_notify(&self, id: i32, notify: StatusItemNotify) -> bool {
+        self.param.send_message_bool(Message::StatusItemNotify, LPARAM(id as isize), LPARAM(notify as isize))
+    }
+
+    // パネル項目を登録する
+    // 通常は PanelItems を使用した方が便利です。
+    pub fn register_panel_item(&self, info: &PanelItemInfo) -> bool {
+        let ptr = info as *const PanelItemInfo;
+
+        self.param.send_message_bool(Message::RegisterPanelItem, LPARAM(ptr as isize), LPARAM(0))
+    }
+
+    // パネル項目を設定する
+    // PanelItemSetInfo の mask に設定したい情報を、id に設定したい項目の識別子を指定して呼び出します。
+    pub fn set_panel_item(&self, info: &PanelItemSetInfo) -> bool {
+        let ptr = info as *const PanelItemSetInfo;
+
+        self.param.send_message_bool(Message::SetPanelItem, LPARAM(ptr as isize), LPARAM(0))
+    }
+
+    // パネル項目の有効状態を設定する
+    // 有効にするとパネルのタブに表示されます。
+    pub fn enable_panel_item(&self, id: i32, enable: bool) -> bool {
+        let mut info = PanelItemSetInfo::new(id);
+        info.mask = PanelItemSetInfoMask::State.into();
+        info.state_mask = PanelItemState::Enabled.into();
+        if enable {
+            info.state = PanelItemState::Enabled.into();
+        }
+
+        self.set_panel_item(&info)
+    }
+
+    // パネル項目の情報を取得する
+    // PanelItemGetInfo の mask に取得したい情報を、id に取得したい項目の識別子を指定して呼び出します。
+    pub fn get_panel_item_info(&self, info: &mut PanelItemGetInfo) -> bool {
+        let ptr = info as *mut PanelItemGetInfo;
+
+        self.param.send_message_bool(Message::GetPanelItemInfo, LPARAM(ptr as isize), LPARAM(0))
+    }
+
+    // パネル項目の状態を取得する
+    pub fn get_panel_item_state(&self, id: i32) -> Option<BitFlags<PanelItemState>> {
+        let mut info = PanelItemGetInfo::new(id, PanelItemGetInfoMask::State.into());
+
+        if self.get_panel_item_info(&mut info) {
+            info.state.into()
+        } else {
+            None
+        }
+    }
+
+    // TSプロセッサを登録する
+    // 通常は TsProcessorRegistration を使用した方が便利です。
+    pub fn register_ts_processor(&self, info: &TsProcessorInfo) -> bool {
+        let ptr = info as *const TsProcessorInfo;
+
+        self.param.send_message_bool(Message::RegisterTSProcessor, LPARAM(ptr as isize), LPARAM(0))
+    }
+
+    // チャンネルを選択する
+    // 通常は select_channel を使用した方が簡単です。
+    pub fn select_channel_info(&self, info: &ChannelSelectInfo) -> bool {
+        let ptr = info as *const ChannelSelectInfo;
+
+        self.param.send_message_bool(Message::SelectChannel, LPARAM(ptr as isize), LPARAM(0))
+    }
+
+    // チャンネルを選択する
+    // ChannelSelector::from_ids を使うと EPG の (ネットワークID, TSID, サービスID) で選択できます。
+    // 条件に一致するチャンネルがない場合は ChannelSelectError::NotFound を返します。
+    pub fn select_channel(&self, selector: &ChannelSelector) -> Result<(), ChannelSelectError> {
+        if selector.is_empty() {
+            return Err(ChannelSelectError::NoCondition);
+        }
+
+        if !selector.ignore_current {
+            if let Some(channel) = self.get_current_channel_info() {
+                let service_id = self.get_service_index()
+                    .and_then(|index| self.get_service_info(index))
+                    .map(|service| service.service_id);
+                if selector.matches(&channel, service_id) {
+                    return Ok(());
+                }
+            }
+        }
+
+        let strings = WideStringBuffer::new();
+        let info = selector.to_info(selector.tuner.as_deref().map(|tuner| strings.push(tuner)));
+        if self.select_channel_info(&info) {
+            Ok(())
+        } else {
+            Err(ChannelSelectError::NotFound)
+        }
+    }
+
+    // お気に入りチャンネルを取得する
+    // 取得したリストは Favorites に変換した後に自動で解放されます。
+    pub fn favorites(&self) -> Option<Favorites> {
+        let mut list = FavoriteList::default();
+        let ptr = &mut list as *mut FavoriteList;
+        if !self.param.send_message_bool(Message::GetFavoriteList, LPARAM(ptr as isize), LPARAM(0)) {
+            return None;
+        }
+
+        // 変換中にパニックした場合も解放する
+        struct FreeGuard<'a>(&'a PluginApi, *mut FavoriteList);
+        impl Drop for FreeGuard<'_> {
+            fn drop(&mut self) {
+                self.0.param.send_message(Message::FreeFavoriteList, LPARAM(self.1 as isize), LPARAM(0));
+            }
+        }
+        let _guard = FreeGuard(self, ptr);
+
+        unsafe { Favorites::from_raw(&*ptr) }.into()
+    }
+
+    // DPI を取得する
+    // 通常は dpi を使用した方が簡単です。
+    pub fn get_dpi(&self, info: &GetDpiInfo) -> Option<i32> {
+        let ptr = info as *const GetDpiInfo;
+        let dpi = self.param.send_message(Message::GetDPI, LPARAM(ptr as isize), LPARAM(0)).0 as i32;
+
+        if dpi != 0 {
+            dpi.into()
+        } else {
+            None
+        }
+    }
+
+    // システム・ウィンドウ・モニタなどの DPI を取得する
+    // TVTest の設定で DPI に応じたスケーリングが無効になっている場合は 96 が返ります。
+    // 設定に関わらず取得する場合は get_dpi で DpiFlag::Forced を指定します。
+    pub fn dpi(&self, target: DpiTarget) -> Option<i32> {
+        self.get_dpi(&GetDpiInfo::new(target, BitFlags::empty()))
+    }
+
+    // フォントを取得する
+    // 通常は font を使用した方が簡単です。
+    pub fn get_font_info(&self, info: &mut GetFontInfo) -> bool {
+        let ptr = info as *mut GetFontInfo;
+
+        self.param.send_message_bool(Message::GetFont, LPARAM(ptr as isize), LPARAM(0))
+    }
+
+    // TVTest で設定されているフォントを取得する
+    // name には font::FONT_NAME_OSD などを指定します。
+    // dpi に 0 を指定するとメインウィンドウの DPI に合わせた大きさで取得されます。
+    pub fn font(&self, name: &str, dpi: i32) -> Option<Font> {
+        let name = name.into_wide_string();
+        let mut info = GetFontInfo::new(name.as_wstr()?, dpi);
+
+        if self.get_font_info(&mut info) {
+            Font::from(&info.log_font).into()
+        } else {
+            None
+        }
+    }
+
+    // ダイアログを表示する
+    // 通常は show_dialog / create_dialog を使用した方が簡単です。
+    // モーダルの場合は EndDialog で指定された値、モードレスの場合はウィンドウハンドルが返ります。
+    pub fn show_dialog_info(&self, info: &ShowDialogInfo) -> isize {
+        let ptr = info as *const ShowDialogInfo;
+
+        self.param.send_message(Message::ShowDialog, LPARAM(ptr as isize), LPARAM(0)).0
+    }
+
+    // モーダルダイアログを表示する
+    // handler がダイアログプロシージャとして呼ばれ、EndDialog で指定された値が返ります。
+    // TVTest が表示するため、テーマや DPI に応じたスケーリングが適用されます。
+    pub fn show_dialog<F>(&self, dialog: &DialogBuilder, handler: F) -> isize
+        where F: Fn(HWND, u32, WPARAM, LPARAM) -> isize
+    {
+        let handler: Box<DialogHandler> = Box::new(handler);
+        let name = dialog.template_name();
+        let mut info = dialog.to_info(*self.dll, name.as_ref().and_then(WideString::as_wstr), false);
+        info.client_data = NonNull::new(&handler as *const Box<DialogHandler> as *mut c_void);
+
+        self.show_dialog_info(&info)
+    }
+
+    // モードレスダイアログを作成する
+    // 返された ModelessDialog を破棄するとダイアログも破棄されます。
+    pub fn create_dialog<F>(&self, dialog: &DialogBuilder, handler: F) -> Option<ModelessDialog>
+        where F: Fn(HWND, u32, WPARAM, LPARAM) -> isize + 'static
+    {
+        let handler: Box<Box<DialogHandler<'static>>> = Box::new(Box::new(handler));
+        let name = dialog.template_name();
+        let mut info = dialog.to_info(*self.dll, name.as_ref().and_then(WideString::as_wstr), true);
+        info.client_data = NonNull::new(&*handler as *const Box<DialogHandler> as *mut c_void);
+
+        match self.show_dialog_info(&info) {
+            0 => None,
+            hwnd => ModelessDialog::new(HWND(hwnd), handler).into(),
+        }
+    }
+
+    // 日時を変換する
+    // 通常は convert_time を使用した方が簡単です。
+    pub fn convert_time_info(&self, info: &mut ConvertTimeInfo) -> bool {
+        let ptr = info as *mut ConvertTimeInfo;
+
+        self.param.send_message_bool(Message::ConvertTime, LPARAM(ptr as isize), LPARAM(0))
+    }
+
+    // 日時を別の種類に変換する
+    // 例えば EpgTime から LocalTime に変換するには api.convert_time::<_, LocalTime>(&time) とします。
+    // TVTest が MESSAGE_CONVERTTIME に対応していない場合は、UTC と EPG 日時の間のみ SDK 内で変換されます。
+    pub fn convert_time<F: TVTestTime, T: TVTestTime>(&self, from: &F) -> Option<T> {
+        if self.query_message(Message::ConvertTime) {
+            let mut info = ConvertTimeInfo::new::<F, T>(from);
+
+            if self.convert_time_info(&mut info) {
+                return unsafe { T::from_union(&info.to) }.into();
+            }
+        }
+
+        from.convert()
+    }
+
+    // 変数文字列のコンテキストを取得する
+    // 取得したコンテキストは Drop 時に解放されます。
+    pub fn get_var_string_context(&self) -> Option<VarStringContext> {
+        let result = self.param.send_message(Message::GetVarStringContext, LPARAM(0), LPARAM(0));
+
+        NonNull::new(result.0 as *mut _).map(|handle| VarStringContext::new(Arc::clone(&self.param), handle))
+    }
+
+    // 変数文字列を使って文字列をフォーマットする
+    // 通常は format_var_string / format_var_string_with を使用した方が簡単です。
+    // 成功した場合、info.result の文字列は memory_free で解放する必要があります。
+    pub fn format_var_string_info(&self, info: &mut VarStringFormatInfo) -> bool {
+        info.size = size_of::<VarStringFormatInfo>() as u32;
+        let ptr = info as *mut VarStringFormatInfo;
+
+        self.param.send_message_bool(Message::FormatVarString, LPARAM(ptr as isize), LPARAM(0))
+    }
+
+    // 変数文字列を現在のコンテキストでフォーマットする
+    // "%event-name% %date%" などの変数を展開した文字列を返します。
+    pub fn format_var_string(&self, format: &str) -> Option<String> {
+        self.format_var_string_with(format, &VarStringFormat::new())
+    }
+
+    // 変数文字列をコンテキストやマップ関数などを指定してフォーマットする
+    // 録画ファイル名に使う場合は VarStringFormat::file_name を指定します。
+    pub fn format_var_string_with(&self, format: &str, options: &VarStringFormat) -> Option<String> {
+        options.format(self, format)
+    }
+
+    // 変数を登録する
+    // 値が動的に決まる変数は Variables を使用した方が便利です。
+    pub fn register_variable_info(&self, info: &RegisterVariableInfo) -> bool {
+        let ptr = info as *const RegisterVariableInfo;
+
+        self.param.send_message_bool(Message::RegisterVariable, LPARAM(ptr as isize), LPARAM(0))
+    }
+
+    // 値が固定の変数を登録する
+    // 同じ識別子の変数を再登録すると値が更新されます。
+    pub fn register_variable(&self, keyword: &str, description: &str, value: &str) -> bool {
+        let keyword = keyword.into_wide_string();
+        let description = description.into_wide_string();
+        let value = value.into_wide_string();
+        let info = RegisterVariableInfo {
+            size: size_of::<RegisterVariableInfo>() as u32,
+            flags: BitFlags::empty(),
+            keyword: keyword.to_wide_string_ptr(),
+            description: description.to_wide_string_ptr(),
+            value: value.to_wide_string_ptr(),
+        };
+
+        self.register_variable_info(&info)
+    }
+
+    // ステータスを取得する
+    // 通常は status を使用した方が簡単です。
+    pub fn get_status_info(&self, info: &mut StatusInfo) -> bool {
+        info.size = size_of::<StatusInfo>() as u32;
+        let ptr = info as *mut StatusInfo;
+
+        self.param.send_message_bool(Message::GetStatus, LPARAM(ptr as isize), LPARAM(0))
+    }
+
+    // 信号レベル・ビットレート・エラーパケット数などのステータスを取得する
+    // 定期的に取得して監視する場合は StatusMonitor を使用した方が便利です。
+    pub fn status(&self) -> Option<StatusInfo> {
+        let mut info = StatusInfo::default();
+
+        if self.get_status_info(&mut info) {
+            info.into()
+        } else {
+            None
+        }
+    }
+
+    // ステータス(status で取得できる内容)をリセットする
+    // リセットが行われると on_status_reset が呼ばれます。
+    pub fn reset_status(&self) -> bool {
+        self.param.send_message_bool(Message::ResetStatus, LPARAM(0), LPARAM(0))
+    }
+
+    // 録画ステータスを取得する
+    // ファイル名を取得する場合は info.filename と info.max_filename を設定しておきます。
+    // utc に true を指定すると、時刻が UTC で取得されます。
+    pub fn get_record_status_info(&self, info: &mut RecordStatusInfo, utc: bool) -> bool {
+        info.size = size_of::<RecordStatusInfo>() as u32;
+        let ptr = info as *mut RecordStatusInfo;
+        let flags = if utc { RecordStatusFlag::UTC as isize } else { 0 };
+
+        self.param.send_message_bool(Message::GetRecordStatus, LPARAM(ptr as isize), LPARAM(flags))
+    }
+
+    // 録画ステータスを取得する
+    // 時刻は UTC で取得されます。
+    pub fn record_status(&self) -> Option<RecordStatusInfo> {
+        let mut info = RecordStatusInfo::default();
+
+        if self.get_record_status_info(&mut info, true) {
+            info.into()
+        } else {
+            None
+        }
+    }
+
+    // 音量を取得する(0-100)
+    pub fn get_volume(&self) -> i32 {
+        let result = self.param.send_message(Message::GetVolume, LPARAM(0), LPARAM(0)).0;
+
+        (result & 0xFFFF) as i32
+    }
+
+    // 消音状態であるか取得する
+    pub fn is_mute(&self) -> bool {
+        let result = self.param.send_message(Message::GetVolume, LPARAM(0), LPARAM(0)).0;
+
+        (result >> 16) & 0xFFFF != 0
+    }
+
+    // ステレオモードを取得する
+    // 未知の値が返された場合は None を返します。
+    pub fn get_stereo_mode(&self) -> Option<StereoMode> {
+        let result = self.param.send_message(Message::GetStereoMode, LPARAM(0), LPARAM(0)).0;
+
+        StereoMode::try_from(result).ok()
+    }
+
+    // 全画面表示の状態を取得する
+    pub fn is_fullscreen(&self) -> bool {
+        self.param.send_message_bool(Message::GetFullScreen, LPARAM(0), LPARAM(0))
+    }
+
+    // 再生が有効か取得する
+    pub fn is_preview(&self) -> bool {
+        self.param.send_message_bool(Message::GetPreview, LPARAM(0), LPARAM(0))
+    }
+
+    // 待機状態であるか取得する
+    pub fn is_standby(&self) -> bool {
+        self.param.send_message_bool(Message::GetStandby, LPARAM(0), LPARAM(0))
+    }
+
+    // ワンセグモードであるか取得する
+    pub fn is_one_seg_mode(&self) -> bool {
+        self.param.send_message_bool(Message::GetOneSegMode, LPARAM(0), LPARAM(0))
+    }
 }
@@ -0,0 +1,379 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
+use windows::Win32::UI::WindowsAndMessaging::{PostMessageW, RegisterWindowMessageW};
+use crate::api::PluginApi;
+use crate::win32::IntoWideString;
+use crate::window_message::WindowMessageHook;
+
+/// UI スレッドで実行される処理
+type Job = Box<dyn FnOnce(&PluginApi) + Send>;
+
+/// UI スレッドに処理の実行を通知する関数
+pub type ApiWaker = dyn Fn() + Send + Sync;
+
+/// ApiHandle のエラー
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ApiHandleError {
+    /// ApiDispatcher が終了している
+    Closed,
+    /// 処理がパニックした
+    Panicked,
+}
+
+impl fmt::Display for ApiHandleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiHandleError::Closed => write!(f, "the api dispatcher has been shut down"),
+            ApiHandleError::Panicked => write!(f, "the api call panicked on the ui thread"),
+        }
+    }
+}
+
+impl std::error::Error for ApiHandleError {}
+
+struct Shared {
+    // None の場合は終了している
+    queue: Mutex<Option<VecDeque<Job>>>,
+    waker: Box<ApiWaker>,
+}
+
+impl Shared {
+    fn push(&self, job: Job) -> bool {
+        {
+            let mut queue = self.queue.lock().unwrap();
+            match queue.as_mut() {
+                Some(queue) => queue.push_back(job),
+                None => return false,
+            }
+        }
+
+        (self.waker)();
+        true
+    }
+
+    fn process(&self, api: &PluginApi) -> usize {
+        let mut count = 0;
+        // 処理中に新しく追加されたものも実行する
+        loop {
+            let job = match self.queue.lock().unwrap().as_mut().and_then(VecDeque::pop_front) {
+                Some(job) => job,
+                None => return count,
+            };
+            job(api);
+            count += 1;
+        }
+    }
+
+    fn close(&self) {
+        // 実行されなかった処理は破棄され、結果は ApiHandleError::Closed になる
+        let pending = self.queue.lock().unwrap().take();
+        drop(pending);
+    }
+}
+
+/// 任意のスレッドから UI スレッドの PluginApi を呼び出すハンドル
+/// 処理はキューに追加され、ApiDispatcher によって UI スレッドで実行されます
+#[derive(Clone)]
+pub struct ApiHandle {
+    shared: Arc<Shared>,
+}
+
+impl ApiHandle {
+    /// UI スレッドで処理を実行し、結果を ApiResponse で返します
+    pub fn call<R, F>(&self, f: F) -> ApiResponse<R>
+        where R: Send + 'static,
+              F: FnOnce(&PluginApi) -> R + Send + 'static
+    {
+        let (sender, response) = ApiResponse::new();
+        let job: Job = Box::new(move |api| {
+            let result = catch_unwind(AssertUnwindSafe(|| f(api))).map_err(|_| ApiHandleError::Panicked);
+            sender.send(result);
+        });
+
+        // 終了している場合は処理と一緒に sender が破棄され、Closed になる
+        self.shared.push(job);
+        response
+    }
+
+    /// UI スレッドで処理を実行します
+    /// 結果を待たない場合に使用します。終了している場合は false を返します
+    pub fn post<F>(&self, f: F) -> bool
+        where F: FnOnce(&PluginApi) + Send + 'static
+    {
+        self.shared.push(Box::new(move |api| {
+            let _ = catch_unwind(AssertUnwindSafe(|| f(api)));
+        }))
+    }
+
+    /// ApiDispatcher が終了しているか
+    pub fn is_closed(&self) -> bool {
+        self.shared.queue.lock().unwrap().is_none()
+    }
+}
+
+struct ResponseState<R> {
+    result: Option<Result<R, ApiHandleError>>,
+    // 受け取り側が result を取り出した後も、二重に完了させないために保持する
+    completed: bool,
+    waker: Option<Waker>,
+}
+
+struct ResponseShared<R> {
+    state: Mutex<ResponseState<R>>,
+    ready: Condvar,
+}
+
+struct ResponseSender<R> {
+    shared: Arc<ResponseShared<R>>,
+}
+
+impl<R> ResponseSender<R> {
+    fn send(self, result: Result<R, ApiHandleError>) {
+        self.complete(result);
+    }
+
+    fn complete(&self, result: Result<R, ApiHandleError>) {
+        let mut state = self.shared.state.lock().unwrap();
+        if !state.completed {
+            state.completed = true;
+            state.result = result.into();
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+            self.shared.ready.notify_all();
+        }
+    }
+}
+
+impl<R> Drop for ResponseSender<R> {
+    fn drop(&mut self) {
+        // 実行されずに破棄された
+        self.complete(Err(ApiHandleError::Closed));
+    }
+}
+
+/// ApiHandle::call の結果
+/// Future として await するか、wait でブロックして受け取ります
+pub struct ApiResponse<R> {
+    shared: Arc<ResponseShared<R>>,
+}
+
+impl<R> ApiResponse<R> {
+    fn new() -> (ResponseSender<R>, Self) {
+        let shared = Arc::new(ResponseShared {
+            state: Mutex::new(ResponseState { result: None, completed: false, waker: None }),
+            ready: Condvar::new(),
+        });
+
+        (ResponseSender { shared: Arc::clone(&shared) }, Self { shared })
+    }
+
+    /// 結果が届くまで待ちます
+    /// UI スレッドで呼び出すと処理が実行されないため、デッドロックします
+    pub fn wait(self) -> Result<R, ApiHandleError> {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if let Some(result) = state.result.take() {
+                return result;
+            }
+            state = self.shared.ready.wait(state).unwrap();
+        }
+    }
+
+    /// 結果が届いていれば取得します
+    pub fn try_take(&self) -> Option<Result<R, ApiHandleError>> {
+        self.shared.state.lock().unwrap().result.take()
+    }
+}
+
+impl<R> Future for ApiResponse<R> {
+    type Output = Result<R, ApiHandleError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.shared.state.lock().unwrap();
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = cx.waker().clone().into();
+                Poll::Pending
+            },
+        }
+    }
+}
+
+/// ApiHandle からの処理を UI スレッドで実行するディスパッチャ
+/// 破棄するか shutdown を呼び出すと、未実行の処理は ApiHandleError::Closed になり、以降の呼び出しも失敗します
+///
+/// export_plugin! の TVTFinalize は finalize の後にプラグインの構造体を破棄するため、
+/// プラグインの構造体に保持していれば終了処理は自動的に行われます
+/// マクロからはプラグインが保持するディスパッチャを参照できないため、
+/// static 変数などプラグインの構造体の外に保持する場合は finalize で shutdown を呼び出してください
+pub struct ApiDispatcher {
+    api: PluginApi,
+    shared: Arc<Shared>,
+    message: u32,
+    hook: Option<WindowMessageHook>,
+}
+
+impl ApiDispatcher {
+    /// メインウィンドウにメッセージをポストして処理を通知するディスパッチャを生成します
+    /// メッセージは handle_message で処理するか、install を使用してください
+    pub fn new(api: &PluginApi) -> Self {
+        let name = "TVTest.ApiDispatcher".into_wide_string();
+        let message = unsafe { RegisterWindowMessageW(PCWSTR(name.0.as_ptr())) };
+        let hwnd = api.get_app_window().0;
+
+        // WPARAM にキューのアドレスを入れ、他のプラグインのディスパッチャと区別する
+        Self::create(api, message, move |shared| {
+            let shared = shared as usize;
+            Box::new(move || unsafe {
+                PostMessageW(HWND(hwnd), message, WPARAM(shared), LPARAM(0));
+            })
+        })
+    }
+
+    /// ウィンドウメッセージコールバックを設定してディスパッチャを生成します
+    /// 一つのプラグインで設定できるコールバックは一つだけなので、他に使用する場合は new と handle_message を使用します
+    pub fn install(api: &PluginApi) -> Option<Self> {
+        let mut dispatcher = Self::new(api);
+        let shared = Arc::clone(&dispatcher.shared);
+        let message = dispatcher.message;
+        let handler_api = api.clone();
+        dispatcher.hook = api.set_window_message_callback(move |_, msg, wparam, _| {
+            if msg == message && wparam.0 == Arc::as_ptr(&shared) as usize {
+                shared.process(&handler_api);
+                LRESULT(0).into()
+            } else {
+                None
+            }
+        })?.into();
+
+        dispatcher.into()
+    }
+
+    /// 処理の通知方法を指定してディスパッチャを生成します
+    /// `waker` は任意のスレッドから呼ばれるので、UI スレッドで process が呼ばれるようにします
+    pub fn with_waker<W: Fn() + Send + Sync + 'static>(api: &PluginApi, waker: W) -> Self {
+        Self::create(api, 0, move |_| Box::new(waker))
+    }
+
+    fn create<F>(api: &PluginApi, message: u32, waker: F) -> Self
+        where F: FnOnce(*const Shared) -> Box<ApiWaker>
+    {
+        let shared = Arc::<Shared>::new_cyclic(|weak| Shared {
+            queue: Mutex::new(VecDeque::new().into()),
+            waker: waker(weak.as_ptr()),
+        });
+
+        Self {
+            api: api.clone(),
+            shared,
+            message,
+            hook: None,
+        }
+    }
+
+    /// ApiHandle を生成します
+    pub fn handle(&self) -> ApiHandle {
+        ApiHandle {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+
+    /// キューに追加された処理を実行し、実行した数を返します
+    /// UI スレッドから呼び出す必要があります
+    pub fn process(&self) -> usize {
+        self.shared.process(&self.api)
+    }
+
+    /// ウィンドウメッセージコールバックから呼び出します
+    /// ディスパッチャへの通知だった場合は処理を実行して Some を返します
+    pub fn handle_message(&self, message: u32, wparam: WPARAM) -> Option<LRESULT> {
+        if message != 0 && message == self.message && wparam.0 == Arc::as_ptr(&self.shared) as usize {
+            self.process();
+            LRESULT(0).into()
+        } else {
+            None
+        }
+    }
+
+    /// 終了します
+    /// 未実行の処理は破棄され、以降の呼び出しは失敗します
+    pub fn shutdown(&self) {
+        self.shared.close();
+    }
+}
+
+impl Drop for ApiDispatcher {
+    fn drop(&mut self) {
+        self.hook = None;
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use std::thread;
+    use windows::Win32::Foundation::LRESULT;
+    use crate::message::Message;
+    use crate::mock::MockHost;
+    use super::*;
+
+    #[test]
+    fn calls_run_on_dispatcher_thread() {
+        let host = MockHost::new();
+        host.on(Message::GetVolume, |_, _| LRESULT(40));
+        let api = host.api();
+
+        let (wake_sender, wake) = mpsc::channel();
+        let wake_sender = Mutex::new(wake_sender);
+        let dispatcher = ApiDispatcher::with_waker(&api, move || wake_sender.lock().unwrap().send(()).unwrap());
+        let handle = dispatcher.handle();
+
+        let ui_thread = thread::current().id();
+        let worker = thread::spawn(move || {
+            let volume = handle.call(move |api| (thread::current().id() == ui_thread, api.get_volume()));
+            let panicked = handle.call(|_| -> i32 { panic!("panicked on ui thread") });
+            (volume.wait(), panicked.wait(), handle)
+        });
+
+        let mut processed = 0;
+        while processed < 2 {
+            wake.recv().unwrap();
+            processed += dispatcher.process();
+        }
+        let (volume, panicked, handle) = worker.join().unwrap();
+        assert_eq!(volume, Ok((true, 40)));
+        assert_eq!(panicked, Err(ApiHandleError::Panicked));
+
+        // 終了後の呼び出しと未実行の処理は Closed になる
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let pending = handle.call(move |_| counter.fetch_add(1, Ordering::SeqCst));
+        drop(dispatcher);
+        assert_eq!(pending.wait(), Err(ApiHandleError::Closed));
+        assert!(handle.is_closed());
+        assert_eq!(handle.call(|_| ()).try_take(), Some(Err(ApiHandleError::Closed)));
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn response_completes_once() {
+        let (sender, response) = ApiResponse::new();
+        sender.complete(Ok(1));
+        assert_eq!(response.try_take(), Some(Ok(1)));
+
+        // 取り出した後に sender が破棄されても Closed は届かない
+        drop(sender);
+        assert_eq!(response.try_take(), None);
+    }
+}
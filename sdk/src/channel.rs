@@ -1,9 +1,13 @@
+use std::fmt;
+use std::marker::PhantomData;
 use std::mem::size_of;
 use enumflags2::{bitflags, BitFlag, BitFlags};
-use crate::win32::FixedWideString;
+use crate::win32::{FixedWideString, WStr};
+use crate::WideStringPtr;
 
 /// チャンネルの情報
 #[repr(C)]
+#[derive(Clone)]
 #[cfg_attr(test, derive(Debug))]
 pub struct ChannelInfo {
     /// 構造体のサイズ
@@ -66,3 +70,257 @@ pub enum ChannelFlag {
     // 無効にされている
     Disabled = 0x00000001
 }
+
+/// チャンネル選択のフラグ
+#[bitflags]
+#[repr(u32)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
+pub enum ChannelSelectFlag {
+    /// service_id の指定を厳密に扱う
+    StrictService = 0x0001,
+}
+
+/// チャンネル選択の情報
+/// ヘッダでは 1 バイト境界でパックされているため、size もパックされたサイズになります
+#[repr(C, packed)]
+pub struct ChannelSelectInfo<'a> {
+    /// 構造体のサイズ
+    pub size: u32,
+    /// 各種フラグ
+    pub flags: BitFlags<ChannelSelectFlag>,
+    /// チューナー名(null で指定なし)
+    pub tuner: WideStringPtr,
+    /// チューニング空間(-1 で指定なし)
+    pub space: i32,
+    /// チャンネル(-1 で指定なし)
+    pub channel: i32,
+    /// ネットワークID(0 で指定なし)
+    pub network_id: u16,
+    /// トランスポートストリームID(0 で指定なし)
+    pub transport_stream_id: u16,
+    /// サービスID(0 で指定なし)
+    pub service_id: u16,
+    _marker: PhantomData<WStr<'a>>,
+}
+
+/// 選択するチャンネルの条件
+/// 指定しなかった項目は条件に含まれません
+#[derive(Clone, PartialEq, Eq, core::default::Default)]
+#[cfg_attr(test, derive(Debug))]
+pub struct ChannelSelector {
+    /// チューナー名 (BonDriver のファイル名)
+    pub tuner: Option<String>,
+    /// チューニング空間
+    pub space: Option<i32>,
+    /// チャンネル
+    pub channel: Option<i32>,
+    /// ネットワークID
+    pub network_id: Option<u16>,
+    /// トランスポートストリームID
+    pub transport_stream_id: Option<u16>,
+    /// サービスID
+    pub service_id: Option<u16>,
+    /// service_id の指定を厳密に扱う
+    pub strict_service: bool,
+    /// 現在のチャンネルが条件に一致していても選択し直す
+    /// false の場合は TVTest に選択を要求せずに成功とします
+    pub ignore_current: bool,
+}
+
+impl ChannelSelector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 条件が一つも指定されていないか
+    pub fn is_empty(&self) -> bool {
+        self.tuner.is_none()
+            && self.space.is_none()
+            && self.channel.is_none()
+            && self.network_id.is_none()
+            && self.transport_stream_id.is_none()
+            && self.service_id.is_none()
+    }
+
+    /// EPG などの (ネットワークID, トランスポートストリームID, サービスID) で選択します
+    pub fn from_ids(network_id: u16, transport_stream_id: u16, service_id: u16) -> Self {
+        Self::new()
+            .network_id(network_id)
+            .transport_stream_id(transport_stream_id)
+            .service_id(service_id)
+            .strict_service(true)
+    }
+
+    pub fn tuner(mut self, tuner: &str) -> Self {
+        self.tuner = Some(tuner.to_string());
+        self
+    }
+
+    pub fn space(mut self, space: i32) -> Self {
+        self.space = Some(space);
+        self
+    }
+
+    pub fn channel(mut self, channel: i32) -> Self {
+        self.channel = Some(channel);
+        self
+    }
+
+    pub fn network_id(mut self, network_id: u16) -> Self {
+        self.network_id = Some(network_id);
+        self
+    }
+
+    pub fn transport_stream_id(mut self, transport_stream_id: u16) -> Self {
+        self.transport_stream_id = Some(transport_stream_id);
+        self
+    }
+
+    pub fn service_id(mut self, service_id: u16) -> Self {
+        self.service_id = Some(service_id);
+        self
+    }
+
+    pub fn strict_service(mut self, strict_service: bool) -> Self {
+        self.strict_service = strict_service;
+        self
+    }
+
+    pub fn ignore_current(mut self, ignore_current: bool) -> Self {
+        self.ignore_current = ignore_current;
+        self
+    }
+
+    /// ChannelSelectInfo を生成します
+    /// `tuner` には self.tuner を変換した文字列を指定します
+    pub fn to_info<'a>(&self, tuner: Option<WStr<'a>>) -> ChannelSelectInfo<'a> {
+        let mut flags = BitFlags::empty();
+        if self.strict_service {
+            flags |= ChannelSelectFlag::StrictService;
+        }
+
+        ChannelSelectInfo {
+            size: size_of::<ChannelSelectInfo>() as u32,
+            flags,
+            tuner: tuner.map(|tuner| tuner.as_ptr()).unwrap_or_default(),
+            space: self.space.unwrap_or(-1),
+            channel: self.channel.unwrap_or(-1),
+            network_id: self.network_id.unwrap_or(0),
+            transport_stream_id: self.transport_stream_id.unwrap_or(0),
+            service_id: self.service_id.unwrap_or(0),
+            _marker: PhantomData,
+        }
+    }
+
+    /// チャンネルが条件に一致するか
+    /// チューナー名は比較できないため、指定されている場合は常に false を返します
+    pub fn matches(&self, channel: &ChannelInfo, service_id: Option<u16>) -> bool {
+        fn matches<T: PartialEq>(condition: Option<T>, value: T) -> bool {
+            condition.map(|condition| condition == value).unwrap_or(true)
+        }
+
+        self.tuner.is_none()
+            && matches(self.space, channel.space)
+            && matches(self.channel, channel.channel)
+            && matches(self.network_id, channel.network_id)
+            && matches(self.transport_stream_id, channel.transport_stream_id)
+            && self.service_id.map(|id| service_id == Some(id)).unwrap_or(true)
+    }
+}
+
+/// チャンネル選択のエラー
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ChannelSelectError {
+    /// 条件が指定されていない
+    NoCondition,
+    /// 条件に一致するチャンネルがない
+    NotFound,
+}
+
+impl fmt::Display for ChannelSelectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChannelSelectError::NoCondition => write!(f, "no channel condition is specified"),
+            ChannelSelectError::NotFound => write!(f, "no channel matches the condition"),
+        }
+    }
+}
+
+impl std::error::Error for ChannelSelectError {}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use windows::Win32::Foundation::LRESULT;
+    use crate::message::Message;
+    use crate::mock::MockHost;
+    use crate::win32::{IntoRustString, IntoWideString, TryIntoRustString, UnpairedSurrogateError};
+    use super::*;
+
+    #[test]
+    fn select_channel_by_ids() {
+        let host = MockHost::new();
+        host.on(Message::GetCurrentChannelInfo, |info, _| {
+            let info = unsafe { &mut *(info.0 as *mut ChannelInfo) };
+            info.network_id = 4;
+            info.transport_stream_id = 16625;
+            LRESULT(1)
+        });
+        let selected = Rc::new(Cell::new(None));
+        let result = Rc::clone(&selected);
+        host.on(Message::SelectChannel, move |info, _| {
+            let info = unsafe { &*(info.0 as *const ChannelSelectInfo) };
+            let (size, flags) = (info.size, info.flags);
+            assert_eq!(size, size_of::<ChannelSelectInfo>() as u32);
+            assert_eq!(flags.contains(ChannelSelectFlag::StrictService), info.service_id != 0);
+            result.set(Some((info.network_id, info.transport_stream_id, info.service_id, info.space)));
+            LRESULT((info.service_id != 0xFFFF) as isize)
+        });
+        let api = host.api();
+
+        assert_eq!(api.select_channel(&ChannelSelector::from_ids(32736, 32736, 1024)), Ok(()));
+        assert_eq!(selected.get(), Some((32736, 32736, 1024, -1)));
+        assert_eq!(api.select_channel(&ChannelSelector::from_ids(4, 16625, 0xFFFF)), Err(ChannelSelectError::NotFound));
+        assert_eq!(api.select_channel(&ChannelSelector::new().strict_service(true)), Err(ChannelSelectError::NoCondition));
+
+        // 現在のチャンネルと一致する場合は選択し直さない
+        let sent = host.sent(Message::SelectChannel).len();
+        assert_eq!(api.select_channel(&ChannelSelector::new().network_id(4).transport_stream_id(16625)), Ok(()));
+        assert_eq!(host.sent(Message::SelectChannel).len(), sent);
+        assert_eq!(api.select_channel(&ChannelSelector::new().network_id(4).ignore_current(true)), Ok(()));
+        assert_eq!(host.sent(Message::SelectChannel).len(), sent + 1);
+    }
+
+    #[test]
+    fn channel_names_round_trip() {
+        let host = MockHost::new();
+        host.on(Message::GetCurrentChannelInfo, |info, _| {
+            let info = unsafe { &mut *(info.0 as *mut ChannelInfo) };
+            info.network_name = FixedWideString::new("🗼 東京");
+            // 終端以降の内容は読み取らない
+            info.transport_stream_name = FixedWideString::new("NHK総合");
+            info.transport_stream_name.0[10] = 0x41;
+            info.channel_name.0[..3].copy_from_slice(&[0x41, 0xD800, 0x42]);
+            LRESULT(1)
+        });
+        let api = host.api();
+
+        let info = api.get_current_channel_info().unwrap();
+        assert_eq!(info.network_name.into_string(), "🗼 東京");
+        assert_eq!(info.transport_stream_name.into_string(), "NHK総合");
+        assert_eq!(info.transport_stream_name.to_wide_string().0, "NHK総合".into_wide_string().0);
+
+        // 対になっていないサロゲートは保持され、変換時に報告される
+        assert_eq!(info.channel_name.to_wide_string().0, [0x41, 0xD800, 0x42, 0]);
+        assert_eq!(info.channel_name.try_into_string(), Err(UnpairedSurrogateError { index: 1, unit: 0xD800 }));
+        assert_eq!(info.channel_name.into_string(), "A\u{FFFD}B");
+
+        // サロゲートペアの途中では切り詰めない
+        let mut name = FixedWideString::<4>::new("ab🗼");
+        assert_eq!(name.as_wide(), &[0x61, 0x62]);
+        assert!(name.set("a🗼"));
+        assert_eq!(name.try_into_string().as_deref(), Ok("a🗼"));
+    }
+}
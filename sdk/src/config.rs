@@ -0,0 +1,612 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use serde::de::{DeserializeOwned, IntoDeserializer, Visitor};
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use serde::{Deserializer, Serialize};
+use tempfile::NamedTempFile;
+use toml::value::{Table, Value};
+#[cfg(windows)]
+use crate::api::PluginApi;
+#[cfg(windows)]
+use crate::setting::INI_FILE_PATH;
+
+/// スキーマバージョンを保存するキー
+pub const SCHEMA_VERSION_KEY: &str = "SchemaVersion";
+/// INI 形式でトップレベルの値を保存するセクション
+pub const INI_ROOT_SECTION: &str = "Settings";
+
+/// プラグインの設定として保存できる構造体が実装すべき trait
+pub trait PluginConfig: Serialize + DeserializeOwned + Default {
+    /// 設定のスキーマバージョン
+    /// 構造体の形式を変更したら値を増やし、`migrate` で移行処理を実装します
+    const VERSION: u32 = 1;
+
+    /// `version` のスキーマの設定を `version + 1` のスキーマに移行します
+    /// 古い設定を読み込んだ場合に、現在のバージョンになるまで順に呼ばれます
+    fn migrate(version: u32, document: &mut Table) -> Result<(), ConfigError> {
+        let _ = document;
+
+        Err(ConfigError::Migration(version))
+    }
+}
+
+/// 設定ファイルの読み書きのエラー
+#[derive(Debug)]
+pub enum ConfigError {
+    /// ファイルの読み書きに失敗した
+    Io(io::Error),
+    /// 設定ファイルの構文が正しくない
+    Parse(String),
+    /// 設定を書き出せなかった
+    Serialize(String),
+    /// 設定ファイルの内容を構造体に変換できなかった
+    Deserialize(String),
+    /// 形式が対応していない値 (INI 形式の配列など)
+    Unsupported(String),
+    /// 設定ファイルのスキーマバージョンがプラグインより新しい
+    NewerVersion {
+        found: u32,
+        expected: u32,
+    },
+    /// 指定されたバージョンからの移行に対応していない
+    Migration(u32),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "I/O error: {}", e),
+            ConfigError::Parse(message) => write!(f, "parse error: {}", message),
+            ConfigError::Serialize(message) => write!(f, "serialize error: {}", message),
+            ConfigError::Deserialize(message) => write!(f, "deserialize error: {}", message),
+            ConfigError::Unsupported(key) => write!(f, "value of `{}` cannot be represented in this format", key),
+            ConfigError::NewerVersion { found, expected } => write!(f, "schema version {} is newer than {}", found, expected),
+            ConfigError::Migration(version) => write!(f, "cannot migrate from schema version {}", version),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+/// 設定ファイルの形式
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ConfigFormat {
+    /// INI 形式
+    /// トップレベルの値は `[Settings]` セクションに、ネストした構造体はその名前のセクションに保存されます
+    Ini,
+    /// TOML 形式
+    Toml,
+}
+
+impl ConfigFormat {
+    /// ファイルの拡張子を返します
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ConfigFormat::Ini => "ini",
+            ConfigFormat::Toml => "toml",
+        }
+    }
+
+    /// 設定を文字列に変換します
+    pub fn encode<T: PluginConfig>(&self, config: &T) -> Result<String, ConfigError> {
+        let mut document = match Value::try_from(config) {
+            Ok(Value::Table(table)) => table,
+            Ok(_) => return Err(ConfigError::Unsupported("<root>".to_string())),
+            Err(e) => return Err(ConfigError::Serialize(e.to_string())),
+        };
+        document.insert(SCHEMA_VERSION_KEY.to_string(), Value::Integer(T::VERSION as i64));
+
+        match self {
+            ConfigFormat::Ini => render_ini(&document),
+            ConfigFormat::Toml => toml::to_string(&Value::Table(document))
+                .map_err(|e| ConfigError::Serialize(e.to_string())),
+        }
+    }
+
+    /// 文字列から設定を読み込みます
+    /// スキーマバージョンが古い場合は `PluginConfig::migrate` で移行します
+    /// スキーマバージョンが記録されていない場合はバージョン 1 として扱います
+    pub fn decode<T: PluginConfig>(&self, text: &str) -> Result<T, ConfigError> {
+        let mut document = match self {
+            ConfigFormat::Ini => parse_ini(text)?,
+            ConfigFormat::Toml => toml::from_str::<Table>(text)
+                .map_err(|e| ConfigError::Parse(e.to_string()))?,
+        };
+
+        let version = match document.remove(SCHEMA_VERSION_KEY) {
+            Some(Value::Integer(version)) if version >= 0 && version <= u32::MAX as i64 => version as u32,
+            Some(_) => return Err(ConfigError::Parse(format!("`{}` must be an unsigned integer", SCHEMA_VERSION_KEY))),
+            None => 1,
+        };
+        if version > T::VERSION {
+            return Err(ConfigError::NewerVersion {
+                found: version,
+                expected: T::VERSION,
+            });
+        }
+        for from in version..T::VERSION {
+            T::migrate(from, &mut document)?;
+        }
+
+        match self {
+            ConfigFormat::Ini => T::deserialize(IniValueDeserializer(Value::Table(document))),
+            ConfigFormat::Toml => Value::Table(document).try_into(),
+        }.map_err(|e| ConfigError::Deserialize(e.to_string()))
+    }
+}
+
+/// プラグインの設定ファイル
+pub struct ConfigStore {
+    path: PathBuf,
+    format: ConfigFormat,
+}
+
+impl ConfigStore {
+    /// 設定ファイルのパスを指定して生成します
+    pub fn new<P: Into<PathBuf>>(path: P, format: ConfigFormat) -> Self {
+        Self {
+            path: path.into(),
+            format,
+        }
+    }
+
+    /// TVTest の Ini ファイルと同じディレクトリに `name` の設定ファイルを配置します
+    /// 拡張子は形式に応じて付加されます
+    #[cfg(windows)]
+    pub fn from_host(api: &PluginApi, name: &str, format: ConfigFormat) -> Option<Self> {
+        let ini_file_path: String = api.get_setting(INI_FILE_PATH)?;
+        let directory = Path::new(&ini_file_path).parent()?;
+        let path = directory.join(format!("{}.{}", name, format.extension()));
+
+        Self::new(path, format).into()
+    }
+
+    /// 設定ファイルのパスを返します
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 設定ファイルの形式を返します
+    pub fn format(&self) -> ConfigFormat {
+        self.format
+    }
+
+    /// 設定ファイルを読み込みます
+    /// ファイルが存在しない場合はデフォルト値を返します
+    pub fn load<T: PluginConfig>(&self) -> Result<T, ConfigError> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(T::default()),
+            Err(e) => return Err(e.into()),
+        };
+
+        self.format.decode(text.trim_start_matches('\u{feff}'))
+    }
+
+    /// 設定ファイルを書き込みます
+    /// 一時ファイルに書き込んでから置き換えるため、書き込み途中で失敗しても元のファイルは壊れません
+    pub fn save<T: PluginConfig>(&self, config: &T) -> Result<(), ConfigError> {
+        let text = self.format.encode(config)?;
+
+        write_atomic(&self.path, text.as_bytes()).map_err(ConfigError::from)
+    }
+}
+
+fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let directory = match path.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => {
+            fs::create_dir_all(directory)?;
+            directory
+        },
+        _ => Path::new("."),
+    };
+
+    // 同時に保存しても衝突しないように一意な名前の一時ファイルを使い、失敗した場合は Drop で削除される
+    let mut temporary = NamedTempFile::new_in(directory)?;
+    temporary.write_all(contents)?;
+    temporary.as_file().sync_all()?;
+    temporary.persist(path).map_err(|e| e.error)?;
+
+    Ok(())
+}
+
+fn render_ini(document: &Table) -> Result<String, ConfigError> {
+    let mut output = format!("[{}]\n", INI_ROOT_SECTION);
+    let mut sections = Vec::new();
+
+    for (key, value) in document {
+        match value {
+            Value::Table(table) => sections.push((key, table)),
+            _ => output.push_str(&format_ini_entry(key, key, value)?),
+        }
+    }
+
+    for (name, table) in sections {
+        output.push_str(&format!("\n[{}]\n", name));
+        for (key, value) in table {
+            output.push_str(&format_ini_entry(&format!("{}.{}", name, key), key, value)?);
+        }
+    }
+
+    Ok(output)
+}
+
+fn format_ini_entry(path: &str, key: &str, value: &Value) -> Result<String, ConfigError> {
+    if key.is_empty() || key.contains(['=', '[', ']', '\r', '\n']) {
+        return Err(ConfigError::Unsupported(path.to_string()));
+    }
+
+    let value = match value {
+        Value::String(s) => quote_ini_string(s),
+        Value::Integer(i) => i.to_string(),
+        Value::Float(f) => format!("{:?}", f),
+        Value::Boolean(b) => b.to_string(),
+        Value::Datetime(d) => quote_ini_string(&d.to_string()),
+        Value::Array(_) | Value::Table(_) => return Err(ConfigError::Unsupported(path.to_string())),
+    };
+
+    Ok(format!("{}={}\n", key, value))
+}
+
+fn quote_ini_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+
+    quoted
+}
+
+fn parse_ini(text: &str) -> Result<Table, ConfigError> {
+    let mut root = Table::new();
+    let mut section: Option<String> = None;
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with('[') && line.ends_with(']') {
+            let name = line[1..line.len() - 1].trim();
+            section = if name.eq_ignore_ascii_case(INI_ROOT_SECTION) {
+                None
+            } else {
+                Some(name.to_string())
+            };
+            continue;
+        }
+
+        let (key, value) = line.split_once('=')
+            .ok_or_else(|| ConfigError::Parse(format!("line {}: expected `key=value`", number + 1)))?;
+        let value = parse_ini_value(value.trim())
+            .ok_or_else(|| ConfigError::Parse(format!("line {}: invalid quoted string", number + 1)))?;
+
+        let table = match &section {
+            None => &mut root,
+            Some(name) => match root.entry(name.clone()).or_insert_with(|| Value::Table(Table::new())) {
+                Value::Table(table) => table,
+                _ => return Err(ConfigError::Parse(format!("line {}: section [{}] conflicts with a value", number + 1, name))),
+            },
+        };
+        table.insert(key.trim().to_string(), value);
+    }
+
+    Ok(root)
+}
+
+/// 引用符のない値は書き出した形式と完全に一致する場合のみ数値や真偽値として扱い、それ以外は文字列のまま残します
+/// 構造体のフィールドの型への変換は IniValueDeserializer で行います
+fn parse_ini_value(value: &str) -> Option<Value> {
+    if let Some(quoted) = value.strip_prefix('"') {
+        return unquote_ini_string(quoted).map(Value::String);
+    }
+
+    if let Some(i) = value.parse::<i64>().ok().filter(|i| i.to_string() == value) {
+        Value::Integer(i).into()
+    } else if let Some(f) = value.parse::<f64>().ok().filter(|f| format!("{:?}", f) == value) {
+        Value::Float(f).into()
+    } else if let Ok(b) = value.parse::<bool>() {
+        Value::Boolean(b).into()
+    } else {
+        // 手で編集された引用符のない文字列や数値 (0123 など)
+        Value::String(value.to_string()).into()
+    }
+}
+
+fn unquote_ini_string(quoted: &str) -> Option<String> {
+    let mut unquoted = String::with_capacity(quoted.len());
+    let mut chars = quoted.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return chars.next().is_none().then_some(unquoted),
+            '\\' => unquoted.push(match chars.next()? {
+                '"' => '"',
+                '\\' => '\\',
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                _ => return None,
+            }),
+            _ => unquoted.push(c),
+        }
+    }
+
+    // 閉じる引用符がない
+    None
+}
+
+/// INI 形式の値を構造体のフィールドの型に合わせて変換する Deserializer
+/// INI 形式には値の型がないため、`code=0123` を文字列のフィールドに、`width=0640` を数値のフィールドに読み込めるようにします
+struct IniValueDeserializer(Value);
+
+impl<'de> IntoDeserializer<'de, toml::de::Error> for IniValueDeserializer {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $ty:ty, $visit:ident;)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                match &self.0 {
+                    Value::String(s) => match s.parse::<$ty>() {
+                        Ok(value) => visitor.$visit(value),
+                        Err(_) => self.deserialize_any(visitor),
+                    },
+                    _ => self.deserialize_any(visitor),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for IniValueDeserializer {
+    type Error = toml::de::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Value::Table(table) => visitor.visit_map(MapDeserializer::new(table.into_iter().map(|(key, value)| (key, IniValueDeserializer(value))))),
+            Value::Array(array) => visitor.visit_seq(SeqDeserializer::new(array.into_iter().map(IniValueDeserializer))),
+            value => value.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Value::Integer(i) => visitor.visit_string(i.to_string()),
+            Value::Float(f) => visitor.visit_string(format!("{:?}", f)),
+            Value::Boolean(b) => visitor.visit_string(b.to_string()),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_str(visitor)
+    }
+
+    deserialize_parsed! {
+        deserialize_bool => bool, visit_bool;
+        deserialize_i8 => i64, visit_i64;
+        deserialize_i16 => i64, visit_i64;
+        deserialize_i32 => i64, visit_i64;
+        deserialize_i64 => i64, visit_i64;
+        deserialize_u8 => u64, visit_u64;
+        deserialize_u16 => u64, visit_u64;
+        deserialize_u32 => u64, visit_u64;
+        deserialize_u64 => u64, visit_u64;
+        deserialize_f32 => f64, visit_f64;
+        deserialize_f64 => f64, visit_f64;
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, name: &'static str, variants: &'static [&'static str], visitor: V) -> Result<V::Value, Self::Error> {
+        self.0.deserialize_enum(name, variants, visitor)
+    }
+
+    serde::forward_to_deserialize_any! {
+        i128 u128 char bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // num_enum の Default derive ではなく標準の Default derive を使う
+    use std::default::Default;
+    use serde::{Deserialize, Serialize};
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Default, PartialEq, Debug)]
+    struct Window {
+        width: i32,
+        height: i32,
+    }
+
+    #[derive(Serialize, Deserialize, Default, PartialEq, Debug)]
+    struct Config {
+        name: String,
+        code: String,
+        enabled: bool,
+        ratio: f64,
+        window: Window,
+    }
+
+    impl PluginConfig for Config {}
+
+    fn sample() -> Config {
+        Config {
+            name: "say \"hello\"\nC:\\TVTest".to_string(),
+            code: "0123".to_string(),
+            enabled: true,
+            ratio: 1.0,
+            window: Window {
+                width: 640,
+                height: 360,
+            },
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let directory = tempfile::tempdir().unwrap();
+
+        for format in [ConfigFormat::Ini, ConfigFormat::Toml] {
+            let store = ConfigStore::new(directory.path().join("nested").join(format!("plugin.{}", format.extension())), format);
+            store.save(&sample()).unwrap();
+
+            assert_eq!(store.load::<Config>().unwrap(), sample());
+        }
+    }
+
+    #[test]
+    fn missing_file_is_default() {
+        let directory = tempfile::tempdir().unwrap();
+        let store = ConfigStore::new(directory.path().join("missing.ini"), ConfigFormat::Ini);
+
+        assert_eq!(store.load::<Config>().unwrap(), Config::default());
+    }
+
+    #[test]
+    fn save_replaces_existing_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let store = ConfigStore::new(directory.path().join("plugin.ini"), ConfigFormat::Ini);
+        store.save(&Config::default()).unwrap();
+        store.save(&sample()).unwrap();
+
+        assert_eq!(store.load::<Config>().unwrap(), sample());
+        assert_eq!(fs::read_dir(directory.path()).unwrap().count(), 1);
+
+        // 同時に保存しても一時ファイルが衝突しない
+        let path = directory.path().join("plugin.ini");
+        let threads: Vec<_> = (0..4).map(|_| {
+            let store = ConfigStore::new(path.clone(), ConfigFormat::Ini);
+            std::thread::spawn(move || store.save(&sample()))
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap().unwrap();
+        }
+        assert_eq!(store.load::<Config>().unwrap(), sample());
+        assert_eq!(fs::read_dir(directory.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn ini_layout() {
+        let text = ConfigFormat::Ini.encode(&sample()).unwrap();
+
+        assert!(text.starts_with("[Settings]\n"));
+        assert!(text.contains("SchemaVersion=1\n"));
+        assert!(text.contains("code=\"0123\"\n"));
+        assert!(text.contains("\n[window]\nheight=360\nwidth=640\n"));
+    }
+
+    #[test]
+    fn unquoted_ini_values_follow_field_types() {
+        for (name, code) in [("true", "0123"), ("inf", "1.50")] {
+            let text = format!("[Settings]\nname={}\ncode={}\nenabled=true\nratio=1e3\n\n[window]\nwidth=0640\nheight=360\n", name, code);
+            let config: Config = ConfigFormat::Ini.decode(&text).unwrap();
+
+            assert_eq!(config, Config {
+                name: name.to_string(),
+                code: code.to_string(),
+                enabled: true,
+                ratio: 1000.0,
+                window: Window {
+                    width: 640,
+                    height: 360,
+                },
+            });
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Default, PartialEq, Debug)]
+    struct ConfigV3 {
+        title: String,
+        volume: i32,
+    }
+
+
+    impl PluginConfig for ConfigV3 {
+        const VERSION: u32 = 3;
+
+        fn migrate(version: u32, document: &mut Table) -> Result<(), ConfigError> {
+            match version {
+                // name -> title
+                1 => {
+                    if let Some(name) = document.remove("name") {
+                        document.insert("title".to_string(), name);
+                    }
+                    Ok(())
+                },
+                // volume を追加
+                2 => {
+                    document.insert("volume".to_string(), Value::Integer(50));
+                    Ok(())
+                },
+                _ => Err(ConfigError::Migration(version)),
+            }
+        }
+    }
+
+    #[test]
+    fn migrate_old_schema() {
+        let config: ConfigV3 = ConfigFormat::Ini.decode("[Settings]\nname=\"NHK\"\n").unwrap();
+
+        assert_eq!(config, ConfigV3 { title: "NHK".to_string(), volume: 50 });
+    }
+
+    #[test]
+    fn reject_newer_schema() {
+        let result = ConfigFormat::Toml.decode::<Config>("SchemaVersion = 2\n");
+
+        assert!(matches!(result, Err(ConfigError::NewerVersion { found: 2, expected: 1 })));
+    }
+
+    #[test]
+    fn reject_unsupported_values() {
+        #[derive(Serialize, Deserialize, Default)]
+        struct WithArray {
+            items: Vec<i32>,
+        }
+        impl PluginConfig for WithArray {}
+
+        let config = WithArray { items: vec![1, 2] };
+
+        assert!(matches!(ConfigFormat::Ini.encode(&config), Err(ConfigError::Unsupported(_))));
+        assert!(ConfigFormat::Toml.encode(&config).is_ok());
+    }
+}
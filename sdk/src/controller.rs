@@ -1,9 +1,19 @@
-use std::ffi::c_void;
-use enumflags2::BitFlags;
-use windows::Win32::Foundation::HWND;
+use std::mem::size_of;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::Arc;
+use enumflags2::{BitFlag, BitFlags};
+use windows::Win32::Foundation::{BOOL, HWND, LPARAM};
+use windows::Win32::UI::WindowsAndMessaging::MSG;
 use crate::{ClientData, WideStringPtr};
+use crate::api::PluginApi;
+use crate::message::Message;
+use crate::plugin::PluginParam;
+use crate::win32::{IntoWideString, WideString};
 
 /// 画像のボタンの位置(画像が無い場合は無視される)
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq, core::default::Default)]
+#[cfg_attr(test, derive(Debug))]
 pub struct ControllerButtonRect {
     pub left: u16,
     pub top: u16,
@@ -12,12 +22,16 @@ pub struct ControllerButtonRect {
 }
 
 /// 画像の選択ボタンの位置(画像が無い場合は無視される)
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq, core::default::Default)]
+#[cfg_attr(test, derive(Debug))]
 pub struct ControllerSelectButtonPosition {
     pub left: u16,
     pub top: u16,
 }
 
 /// コントローラのボタンの情報
+#[repr(C)]
 pub struct ControllerButtonInfo {
     /// ボタンの名称("音声切替" など)
     pub name: WideStringPtr,
@@ -39,13 +53,16 @@ pub enum ControllerFlag {
     ActiveOnly = 0x00000001,
 }
 
+/// メッセージの変換コールバック
+/// メッセージを処理した場合は TRUE を返します
 pub type TranslateMessageCallback = unsafe extern "system" fn(
     hwnd: HWND,
-    message: *const c_void,
+    message: *mut MSG,
     client_data: ClientData,
-);
+) -> BOOL;
 
 /// コントローラの情報
+#[repr(C, packed)]
 pub struct ControllerInfo {
     /// 構造体のサイズ
     pub size: u32,
@@ -56,7 +73,7 @@ pub struct ControllerInfo {
     /// コントローラの名称("HDUSリモコン" など)
     pub text: WideStringPtr,
     /// ボタンの数
-    pub num_buttons: u32,
+    pub num_buttons: i32,
     /// ボタンのリスト
     pub buttons: *const ControllerButtonInfo,
     /// 設定ファイル名(nullptr にすると TVTest の Ini ファイル)
@@ -68,7 +85,7 @@ pub struct ControllerInfo {
     /// 選択ボタン画像の識別子(無い場合は0)
     pub selection_buttons_image_id: u32,
     /// メッセージの変換コールバック(必要無ければ nullptr)
-    pub translate_message_callback: *const TranslateMessageCallback,
+    pub translate_message_callback: Option<TranslateMessageCallback>,
     /// コールバックに渡すパラメータ
     pub client_data: ClientData,
 }
@@ -93,7 +110,312 @@ pub enum ControllerSettingsFlag {
 
 /// コントローラの設定
 #[repr(C)]
+#[derive(Copy, Clone)]
 pub struct ControllerSettings {
     pub mask: BitFlags<ControllerSettingsMask>,
     pub flags: BitFlags<ControllerSettingsFlag>,
 }
+
+impl ControllerSettings {
+    /// アクティブ時のみに設定されているか
+    pub fn is_active_only(&self) -> bool {
+        self.flags.contains(ControllerSettingsFlag::ActiveOnly)
+    }
+}
+
+impl Default for ControllerSettings {
+    fn default() -> Self {
+        Self {
+            mask: ControllerSettingsMask::all(),
+            flags: ControllerSettingsFlag::empty(),
+        }
+    }
+}
+
+/// メッセージを変換するクロージャ
+/// メッセージを処理した場合は true を返します
+pub type TranslateMessageHandler = dyn Fn(HWND, &mut MSG) -> bool;
+
+/// コントローラのボタン
+pub struct ControllerButton {
+    /// ボタンの名称("音声切替" など)
+    pub name: String,
+    /// デフォルトのコマンド(MsgDoCommand と同じもの)
+    pub default_command: Option<String>,
+    /// 画像のボタンの位置
+    pub button_rect: ControllerButtonRect,
+    /// 画像の選択ボタンの位置
+    pub select_button_position: ControllerSelectButtonPosition,
+}
+
+/// コントローラを登録するためのビルダー
+pub struct ControllerBuilder {
+    name: String,
+    text: String,
+    flags: BitFlags<ControllerFlag>,
+    buttons: Vec<ControllerButton>,
+    ini_filename: Option<String>,
+    section_name: Option<String>,
+    controller_image_id: u32,
+    selection_buttons_image_id: u32,
+    translate_message: Option<Box<TranslateMessageHandler>>,
+}
+
+impl ControllerBuilder {
+    /// コントローラ識別名と名称("HDUSリモコン" など)を指定します
+    pub fn new(name: &str, text: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            text: text.to_string(),
+            flags: ControllerFlag::empty(),
+            buttons: Vec::new(),
+            ini_filename: None,
+            section_name: None,
+            controller_image_id: 0,
+            selection_buttons_image_id: 0,
+            translate_message: None,
+        }
+    }
+
+    /// 各種フラグを設定します
+    pub fn flags(mut self, flags: BitFlags<ControllerFlag>) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// ボタンを追加します
+    /// ボタンのインデックスは追加した順に 0 から割り当てられます
+    pub fn button(self, name: &str, default_command: Option<&str>) -> Self {
+        self.button_with_image(name, default_command, ControllerButtonRect::default(), ControllerSelectButtonPosition::default())
+    }
+
+    /// 画像上の位置を指定してボタンを追加します
+    pub fn button_with_image(mut self, name: &str, default_command: Option<&str>, button_rect: ControllerButtonRect, select_button_position: ControllerSelectButtonPosition) -> Self {
+        self.buttons.push(ControllerButton {
+            name: name.to_string(),
+            default_command: default_command.map(str::to_string),
+            button_rect,
+            select_button_position,
+        });
+        self
+    }
+
+    /// 設定ファイル名とセクション名を設定します
+    /// 設定ファイル名が None の場合は TVTest の Ini ファイルに保存されます
+    pub fn settings(mut self, ini_filename: Option<&str>, section_name: &str) -> Self {
+        self.ini_filename = ini_filename.map(str::to_string);
+        self.section_name = section_name.to_string().into();
+        self
+    }
+
+    /// コントローラの画像と選択ボタン画像の識別子を設定します
+    pub fn images(mut self, controller_image_id: u32, selection_buttons_image_id: u32) -> Self {
+        self.controller_image_id = controller_image_id;
+        self.selection_buttons_image_id = selection_buttons_image_id;
+        self
+    }
+
+    /// メッセージの変換コールバックを設定します
+    pub fn translate_message<F>(mut self, handler: F) -> Self
+        where F: Fn(HWND, &mut MSG) -> bool + 'static
+    {
+        self.translate_message = Some(Box::new(handler));
+        self
+    }
+
+    /// コントローラを登録します
+    pub fn register(self, api: &PluginApi) -> Option<Controller> {
+        let name = self.name.as_str().into_wide_string();
+        let text = self.text.into_wide_string();
+        let ini_filename = self.ini_filename.map(IntoWideString::into_wide_string);
+        let section_name = self.section_name.map(IntoWideString::into_wide_string);
+
+        let button_names: Vec<WideString> = self.buttons.iter()
+            .map(|button| button.name.as_str().into_wide_string())
+            .collect();
+        let default_commands: Vec<Option<WideString>> = self.buttons.iter()
+            .map(|button| button.default_command.as_deref().map(IntoWideString::into_wide_string))
+            .collect();
+        let buttons: Vec<ControllerButtonInfo> = self.buttons.iter()
+            .zip(button_names.iter().zip(default_commands.iter()))
+            .map(|(button, (name, default_command))| ControllerButtonInfo {
+                name: name.to_wide_string_ptr(),
+                default_command: to_wide_string_ptr(default_command),
+                button_rect: button.button_rect,
+                select_button_position: button.select_button_position,
+                reserved: 0,
+            })
+            .collect();
+
+        let translate_message = self.translate_message.map(Box::new);
+        let client_data = translate_message.as_ref()
+            .and_then(|handler| std::ptr::NonNull::new(&**handler as *const Box<TranslateMessageHandler> as *mut _));
+
+        let info = ControllerInfo {
+            size: size_of::<ControllerInfo>() as u32,
+            flags: self.flags,
+            name: name.to_wide_string_ptr(),
+            text: text.to_wide_string_ptr(),
+            num_buttons: buttons.len() as i32,
+            buttons: buttons.as_ptr(),
+            ini_filename: to_wide_string_ptr(&ini_filename),
+            section_name: to_wide_string_ptr(&section_name),
+            controller_image_id: self.controller_image_id,
+            selection_buttons_image_id: self.selection_buttons_image_id,
+            translate_message_callback: translate_message.as_ref().map(|_| translate_message_callback as TranslateMessageCallback),
+            client_data,
+        };
+
+        if !api.register_controller(&info) {
+            return None;
+        }
+
+        // TVTest は登録時の文字列やコールバックを参照し続け、登録を解除する方法もないため、解放せずに残す
+        let registration: &'static ControllerRegistration = Box::leak(Box::new(ControllerRegistration {
+            name,
+            _text: text,
+            _ini_filename: ini_filename,
+            _section_name: section_name,
+            _button_names: button_names,
+            _default_commands: default_commands,
+            _buttons: buttons,
+            _translate_message: translate_message,
+        }));
+
+        Controller {
+            param: Arc::clone(&api.param),
+            name: &registration.name,
+            num_buttons: self.buttons.len(),
+        }.into()
+    }
+}
+
+fn to_wide_string_ptr(string: &Option<WideString>) -> WideStringPtr {
+    string.as_ref()
+        .map(WideString::to_wide_string_ptr)
+        .unwrap_or_default()
+}
+
+/// TVTest に渡した登録時のデータ
+/// プラグインの終了まで参照されるため解放されません
+struct ControllerRegistration {
+    name: WideString,
+    _text: WideString,
+    _ini_filename: Option<WideString>,
+    _section_name: Option<WideString>,
+    _button_names: Vec<WideString>,
+    _default_commands: Vec<Option<WideString>>,
+    _buttons: Vec<ControllerButtonInfo>,
+    _translate_message: Option<Box<Box<TranslateMessageHandler>>>,
+}
+
+/// 登録されたコントローラ
+/// 破棄してもコントローラの登録は解除されず、登録時のデータは TVTest が参照し続けるため解放されません
+pub struct Controller {
+    param: Arc<PluginParam>,
+    name: &'static WideString,
+    num_buttons: usize,
+}
+
+impl Controller {
+    /// コントローラのボタンが押されたことを通知する
+    /// 割り当ての設定に従って機能が実行されます
+    pub fn notify_button_down(&self, index: usize) -> bool {
+        if index >= self.num_buttons {
+            return false;
+        }
+
+        let ptr = self.name.0.as_ptr();
+        self.param.send_message_bool(Message::OnControllerButtonDown, LPARAM(ptr as isize), LPARAM(index as isize))
+    }
+
+    /// コントローラの設定を取得する
+    pub fn get_settings(&self) -> Option<ControllerSettings> {
+        let mut settings = ControllerSettings::default();
+        let ptr = &mut settings as *mut ControllerSettings;
+        let result = self.param.send_message_bool(Message::GetControllerSettings, LPARAM(self.name.0.as_ptr() as isize), LPARAM(ptr as isize));
+
+        if result {
+            settings.into()
+        } else {
+            None
+        }
+    }
+
+    /// コントローラがアクティブ時のみに設定されているか取得する
+    pub fn is_active_only(&self) -> bool {
+        self.get_settings()
+            .map(|settings| settings.is_active_only())
+            .unwrap_or(false)
+    }
+}
+
+unsafe extern "system" fn translate_message_callback(hwnd: HWND, message: *mut MSG, client_data: ClientData) -> BOOL {
+    let handler = match client_data {
+        Some(p) => &*(p.as_ptr() as *const Box<TranslateMessageHandler>),
+        None => return BOOL(0),
+    };
+    let message = match message.as_mut() {
+        Some(message) => message,
+        None => return BOOL(0),
+    };
+
+    // パニックした場合はメッセージを処理しなかったものとして扱う
+    let result = catch_unwind(AssertUnwindSafe(|| handler(hwnd, message))).unwrap_or(false);
+
+    BOOL(result as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use std::ptr::NonNull;
+    use windows::Win32::Foundation::LRESULT;
+    use crate::mock::MockHost;
+    use crate::win32::UnsafeIntoRustString;
+    use super::*;
+
+    #[test]
+    fn register_and_notify() {
+        let host = MockHost::new();
+        let registered = Rc::new(RefCell::new(Vec::new()));
+        let names = Rc::clone(&registered);
+        host.on(Message::RegisterController, move |param1, _| {
+            let info = unsafe { &*(param1.0 as *const ControllerInfo) };
+            let buttons = unsafe { std::slice::from_raw_parts(info.buttons, info.num_buttons as usize) };
+            for button in buttons {
+                names.borrow_mut().push((button.name.read_string(), button.default_command.read_string()));
+            }
+            assert!({ info.translate_message_callback }.is_some());
+            names.borrow_mut().push((info.name.read_string(), None));
+
+            LRESULT(1)
+        });
+        host.on(Message::OnControllerButtonDown, |_, _| LRESULT(1));
+
+        let controller = ControllerBuilder::new("HIDRemote", "HID リモコン")
+            .button("音声切替", Some("SwitchAudio"))
+            .button("全画面", None)
+            .translate_message(|_, message| message.message == 0x0400)
+            .register(&host.api())
+            .unwrap();
+
+        assert_eq!(*registered.borrow(), vec![
+            (Some("音声切替".to_string()), Some("SwitchAudio".to_string())),
+            (Some("全画面".to_string()), None),
+            (Some("HIDRemote".to_string()), None),
+        ]);
+
+        assert!(controller.notify_button_down(1));
+        assert!(!controller.notify_button_down(2));
+        let sent = host.sent(Message::OnControllerButtonDown);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].1, LPARAM(1));
+
+        // 破棄しても TVTest に渡した文字列は解放されない
+        let name = WideStringPtr(NonNull::new(sent[0].0.0 as *mut u16));
+        drop(controller);
+        assert_eq!(name.read_string().as_deref(), Some("HIDRemote"));
+    }
+}
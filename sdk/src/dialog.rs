@@ -0,0 +1,262 @@
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr::NonNull;
+use enumflags2::BitFlags;
+use windows::Win32::Foundation::{HINSTANCE, HWND, LPARAM, POINT, WPARAM};
+use windows::Win32::UI::WindowsAndMessaging::DestroyWindow;
+use crate::ClientData;
+use crate::win32::{IntoWideString, WStr, WideString};
+use crate::WideStringPtr;
+
+/// ダイアログのメッセージ処理関数
+/// メッセージを処理した場合は TRUE (またはメッセージごとの戻り値) を返します
+pub type DialogMessageFunc = unsafe extern "system" fn(
+    hwnd: HWND,
+    message: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+    client_data: ClientData,
+) -> isize;
+
+/// ダイアログのメッセージを処理するクロージャ
+/// メッセージを処理しなかった場合は 0 を返します
+pub type DialogHandler<'a> = dyn Fn(HWND, u32, WPARAM, LPARAM) -> isize + 'a;
+
+/// ダイアログ表示のフラグ
+#[bitflags]
+#[derive(Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+#[repr(u32)]
+pub enum ShowDialogFlag {
+    /// モードレス
+    Modeless = 0x0001,
+    /// 位置指定が有効
+    Position = 0x0002,
+}
+
+/// ダイアログ表示の情報
+#[repr(C)]
+pub struct ShowDialogInfo<'a> {
+    /// 構造体のサイズ
+    pub size: u32,
+    /// 各種フラグ
+    pub flags: BitFlags<ShowDialogFlag>,
+    /// リソースのインスタンスハンドル
+    pub hinst: HINSTANCE,
+    /// テンプレート (リソースID の場合は MAKEINTRESOURCE の値)
+    pub template: WideStringPtr,
+    /// メッセージ処理関数
+    pub message_func: Option<DialogMessageFunc>,
+    /// コールバックに渡すデータ
+    pub client_data: ClientData,
+    /// オーナーウィンドウ
+    pub hwnd_owner: HWND,
+    /// ダイアログの位置 (ShowDialogFlag::Position が指定されている場合)
+    pub position: POINT,
+    _marker: PhantomData<WStr<'a>>,
+}
+
+impl Default for ShowDialogInfo<'_> {
+    fn default() -> Self {
+        Self {
+            size: size_of::<ShowDialogInfo>() as u32,
+            flags: BitFlags::empty(),
+            hinst: HINSTANCE(0),
+            template: WideStringPtr::default(),
+            message_func: None,
+            client_data: None,
+            hwnd_owner: HWND(0),
+            position: POINT::default(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// ダイアログのテンプレート
+#[derive(Clone)]
+pub enum DialogTemplate {
+    /// リソースID
+    Id(u16),
+    /// リソース名
+    Name(String),
+}
+
+impl From<u16> for DialogTemplate {
+    fn from(id: u16) -> Self {
+        DialogTemplate::Id(id)
+    }
+}
+
+impl From<&str> for DialogTemplate {
+    fn from(name: &str) -> Self {
+        DialogTemplate::Name(name.to_string())
+    }
+}
+
+/// ダイアログの設定
+/// PluginApi::show_dialog / PluginApi::create_dialog に渡します
+/// TVTest が表示するため、テーマや DPI に応じたスケーリングが適用されます
+#[derive(Clone)]
+pub struct DialogBuilder {
+    template: DialogTemplate,
+    hinst: Option<HINSTANCE>,
+    owner: HWND,
+    position: Option<POINT>,
+}
+
+impl DialogBuilder {
+    /// テンプレートを指定して生成します
+    /// リソースは既定ではプラグインの DLL から読み込まれます
+    pub fn new<T: Into<DialogTemplate>>(template: T) -> Self {
+        Self {
+            template: template.into(),
+            hinst: None,
+            owner: HWND(0),
+            position: None,
+        }
+    }
+
+    /// リソースを読み込むインスタンスハンドルを設定します
+    pub fn instance(mut self, hinst: HINSTANCE) -> Self {
+        self.hinst = hinst.into();
+        self
+    }
+
+    /// オーナーウィンドウを設定します
+    pub fn owner(mut self, hwnd: HWND) -> Self {
+        self.owner = hwnd;
+        self
+    }
+
+    /// ダイアログの位置を設定します
+    pub fn position(mut self, x: i32, y: i32) -> Self {
+        self.position = POINT { x, y }.into();
+        self
+    }
+
+    /// テンプレート名のワイド文字列
+    /// リソースID の場合は None を返します
+    pub fn template_name(&self) -> Option<WideString> {
+        match &self.template {
+            DialogTemplate::Id(_) => None,
+            DialogTemplate::Name(name) => name.as_str().into_wide_string().into(),
+        }
+    }
+
+    /// ShowDialogInfo に変換します
+    /// `name` には template_name で取得した文字列を指定します
+    pub fn to_info<'a>(&self, hinst: HINSTANCE, name: Option<WStr<'a>>, modeless: bool) -> ShowDialogInfo<'a> {
+        let template = match &self.template {
+            // MAKEINTRESOURCE
+            DialogTemplate::Id(id) => WideStringPtr(NonNull::new(*id as usize as *mut u16)),
+            DialogTemplate::Name(_) => name.map(|name| name.as_ptr()).unwrap_or_default(),
+        };
+
+        let mut flags = BitFlags::empty();
+        if modeless {
+            flags |= ShowDialogFlag::Modeless;
+        }
+        if self.position.is_some() {
+            flags |= ShowDialogFlag::Position;
+        }
+
+        ShowDialogInfo {
+            flags,
+            hinst: self.hinst.unwrap_or(hinst),
+            template,
+            message_func: Some(dialog_message_callback),
+            hwnd_owner: self.owner,
+            position: self.position.unwrap_or_default(),
+            ..ShowDialogInfo::default()
+        }
+    }
+}
+
+/// モードレスダイアログ
+/// Drop 時にダイアログを破棄します
+pub struct ModelessDialog {
+    hwnd: HWND,
+    _handler: Box<Box<DialogHandler<'static>>>,
+}
+
+impl ModelessDialog {
+    pub(crate) fn new(hwnd: HWND, handler: Box<Box<DialogHandler<'static>>>) -> Self {
+        Self {
+            hwnd,
+            _handler: handler,
+        }
+    }
+
+    /// ダイアログのウィンドウハンドル
+    pub fn hwnd(&self) -> HWND {
+        self.hwnd
+    }
+}
+
+impl Drop for ModelessDialog {
+    fn drop(&mut self) {
+        // 既に閉じられている場合は失敗するだけなので結果は無視する
+        let _ = unsafe { DestroyWindow(self.hwnd) };
+    }
+}
+
+unsafe extern "system" fn dialog_message_callback(
+    hwnd: HWND,
+    message: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+    client_data: ClientData,
+) -> isize {
+    let handler = match client_data {
+        Some(p) => &*(p.as_ptr() as *const Box<DialogHandler>),
+        None => return 0,
+    };
+
+    // パニックした場合はメッセージを処理しなかったものとして扱う
+    catch_unwind(AssertUnwindSafe(|| handler(hwnd, message, wparam, lparam))).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use windows::Win32::Foundation::LRESULT;
+    use crate::message::Message;
+    use crate::mock::MockHost;
+    use super::*;
+
+    const WM_INITDIALOG: u32 = 0x0110;
+
+    #[test]
+    fn show_modal_dialog() {
+        let host = MockHost::new();
+        host.on(Message::ShowDialog, |info, _| {
+            let info = unsafe { &*(info.0 as *const ShowDialogInfo) };
+            assert_eq!(info.size, size_of::<ShowDialogInfo>() as u32);
+            assert_eq!(info.flags, ShowDialogFlag::Position);
+            assert_eq!(info.template.0.map(|p| p.as_ptr() as usize), Some(101));
+            assert_eq!((info.position.x, info.position.y), (10, 20));
+
+            // TVTest のダイアログプロシージャからの呼び出し
+            let func = info.message_func.unwrap();
+            let initialized = unsafe { func(HWND(1), WM_INITDIALOG, WPARAM(0), LPARAM(0), info.client_data) };
+            let panicked = unsafe { func(HWND(1), 0, WPARAM(0), LPARAM(0), info.client_data) };
+            LRESULT(initialized * 10 + panicked)
+        });
+        let api = host.api();
+
+        let messages = Cell::new(0);
+        let result = api.show_dialog(&DialogBuilder::new(101).position(10, 20), |hwnd, message, _, _| {
+            assert_eq!(hwnd, HWND(1));
+            messages.set(messages.get() + 1);
+            match message {
+                WM_INITDIALOG => 1,
+                _ => panic!("handler panicked"),
+            }
+        });
+
+        // パニックはホストに伝播せず、処理しなかったものとして扱われる
+        assert_eq!(result, 10);
+        assert_eq!(messages.get(), 2);
+    }
+}
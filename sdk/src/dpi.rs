@@ -0,0 +1,88 @@
+use windows::Win32::Foundation::{HWND, POINT, RECT};
+use windows::Win32::Graphics::Gdi::HMONITOR;
+use enumflags2::BitFlags;
+
+/// DPI を取得する対象の種類
+#[repr(u32)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
+pub enum DpiType {
+    /// システムの DPI
+    System,
+    /// ウィンドウの DPI
+    Window,
+    /// 矩形の DPI
+    Rect,
+    /// 座標の DPI
+    Point,
+    /// モニタの DPI
+    Monitor,
+}
+
+/// DPI 取得のフラグ
+#[bitflags]
+#[derive(Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+#[repr(u32)]
+pub enum DpiFlag {
+    /// TVTest の設定で DPI に応じたスケーリングが無効になっていても取得する
+    Forced = 0x0001,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union DpiTargetData {
+    /// DpiType::Window の場合
+    pub hwnd: HWND,
+    /// DpiType::Rect の場合
+    pub rect: RECT,
+    /// DpiType::Point の場合
+    pub point: POINT,
+    /// DpiType::Monitor の場合
+    pub hmonitor: HMONITOR,
+}
+
+/// DPI の取得の情報
+#[repr(C)]
+pub struct GetDpiInfo {
+    /// 種類
+    pub kind: DpiType,
+    /// 各種フラグ
+    pub flags: BitFlags<DpiFlag>,
+    pub target: DpiTargetData,
+}
+
+impl GetDpiInfo {
+    /// 対象とフラグを指定して生成します
+    pub fn new(target: DpiTarget, flags: BitFlags<DpiFlag>) -> Self {
+        let (kind, target) = match target {
+            DpiTarget::System => (DpiType::System, DpiTargetData { hwnd: HWND(0) }),
+            DpiTarget::Window(hwnd) => (DpiType::Window, DpiTargetData { hwnd }),
+            DpiTarget::Rect(rect) => (DpiType::Rect, DpiTargetData { rect }),
+            DpiTarget::Point(point) => (DpiType::Point, DpiTargetData { point }),
+            DpiTarget::Monitor(hmonitor) => (DpiType::Monitor, DpiTargetData { hmonitor }),
+        };
+
+        Self {
+            kind,
+            flags,
+            target,
+        }
+    }
+}
+
+/// DPI を取得する対象
+/// PluginApi::dpi に渡します
+#[derive(Copy, Clone)]
+pub enum DpiTarget {
+    /// システム
+    System,
+    /// ウィンドウ
+    Window(HWND),
+    /// 矩形が含まれるモニタ
+    Rect(RECT),
+    /// 座標が含まれるモニタ
+    Point(POINT),
+    /// モニタ
+    Monitor(HMONITOR),
+}
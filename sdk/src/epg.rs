@@ -1,5 +1,6 @@
 use enumflags2::BitFlags;
-use windows::Win32::Foundation::{FILETIME, SYSTEMTIME};
+use windows::Win32::Foundation::FILETIME;
+use crate::time::EpgTime;
 use crate::WideStringPtr;
 use crate::win32::UnsafePtr;
 
@@ -138,7 +139,7 @@ pub struct EpgEventInfo {
     /// 予約
     pub reserved: u32,
     /// 開始日時(EPG 日時 : UTC+9)
-    pub start_time: SYSTEMTIME,
+    pub start_time: EpgTime,
     /// 長さ(秒単位)
     pub duration: u32,
     /// 映像の情報の数
@@ -1,9 +1,19 @@
-use windows::Win32::Foundation::{LPARAM, LRESULT};
-use crate::ClientData;
+use std::ptr::NonNull;
+use windows::Win32::Foundation::{HWND, LPARAM, LRESULT};
+use crate::command::DrawCommandIconInfo;
+use crate::filter_graph::FilterGraphInfo;
+use crate::panel::{PanelItemCreateEventInfo, PanelItemEvent, PanelItemEventInfo};
+use crate::program_guide::{ProgramGuideCommandParam, ProgramGuideInitializeMenuInfo, ProgramGuideProgramDrawBackgroundInfo, ProgramGuideProgramInfo, ProgramGuideProgramInitializeMenuInfo};
+use crate::record::{RecordStatus, StartRecordInfo};
+use crate::status_item::{StatusItemDrawInfo, StatusItemEventInfo, StatusItemMouseEventInfo};
+use crate::stereo_mode::StereoMode;
+use crate::variable::GetVariableInfo;
+use crate::{ClientData, WideStringPtr};
 
 /// イベント用コールバック関数
+/// 新しい TVTest から未知のイベントが送られる可能性があるため、イベントは u32 のまま受け取ります
 pub type EventCallbackFunc = unsafe extern "system" fn(
-    event: Event,
+    event: u32,
     param1: LPARAM,
     param2: LPARAM,
     client_data: ClientData
@@ -12,6 +22,7 @@ pub type EventCallbackFunc = unsafe extern "system" fn(
 /// イベント
 /// 各イベント発生時のパラメータは CTVTestEventHadler を参照してください。
 #[repr(u32)]
+#[derive(Copy, Clone, Eq, PartialEq, TryFromPrimitive)]
 #[cfg_attr(test, derive(Debug))]
 pub enum Event {
     PluginEnable,         // 有効状態が変化した
@@ -71,3 +82,223 @@ pub enum Event {
 
     Trailer
 }
+
+/// パラメータを解釈したイベント
+/// ポインタで渡されるパラメータはイベントの処理中のみ有効な参照として借用されます
+pub enum PluginEvent<'a> {
+    /// 有効状態が変化した
+    PluginEnable(bool),
+    /// 設定を行う
+    PluginSettings(HWND),
+    /// チャンネルが変更された
+    ChannelChange,
+    /// サービスが変更された
+    ServiceChange,
+    /// ドライバが変更された
+    DriverChange,
+    /// サービスの構成が変化した
+    ServiceUpdate,
+    /// 録画状態が変化した
+    RecordStatusChange(RecordStatus),
+    /// 全画面表示状態が変化した
+    FullScreenChange(bool),
+    /// プレビュー表示状態が変化した
+    PreviewChange(bool),
+    /// 音量が変化した
+    VolumeChange { volume: i32, is_mute: bool },
+    /// ステレオモードが変化した
+    StereoModeChange(StereoMode),
+    /// 色の設定が変化した
+    ColorChange,
+    /// 待機状態が変化した
+    StandBy(bool),
+    /// コマンドが選択された
+    Command(i32),
+    /// 複数起動禁止時に複数起動された
+    Execute(WideStringPtr),
+    /// リセットされた
+    Reset,
+    /// ステータスがリセットされた
+    StatusReset,
+    /// 音声ストリームが変更された
+    AudioStreamChange(i32),
+    /// 設定が変更された
+    SettingsChange,
+    /// TVTestのウィンドウが閉じられる
+    Close,
+    /// 録画が開始される
+    StartRecord(&'a StartRecordInfo),
+    /// 録画ファイルが切り替えられた
+    RelayRecord(WideStringPtr),
+    /// コントローラの対象を設定
+    ControllerFocus(HWND),
+    /// 起動時の処理が終わった
+    StartUpDone,
+    /// 番組表の初期化
+    ProgramGuideInitialize(HWND),
+    /// 番組表の終了
+    ProgramGuideFinalize(HWND),
+    /// 番組表のコマンド実行
+    ProgramGuideCommand { command: u32, param: &'a ProgramGuideCommandParam },
+    /// 番組表のメニューの設定
+    ProgramGuideInitializeMenu(&'a ProgramGuideInitializeMenuInfo),
+    /// 番組表のメニューが選択された
+    ProgramGuideMenuSelected(u32),
+    /// 番組表の番組の背景を描画
+    ProgramGuideProgramDrawBackground { program: &'a ProgramGuideProgramInfo, info: &'a ProgramGuideProgramDrawBackgroundInfo },
+    /// 番組表の番組のメニューの設定
+    ProgramGuideProgramInitializeMenu { program: &'a ProgramGuideProgramInfo, info: &'a ProgramGuideProgramInitializeMenuInfo },
+    /// 番組表の番組のメニューが選択された
+    ProgramGuideProgramMenuSelected { program: &'a ProgramGuideProgramInfo, command: u32 },
+    /// フィルタグラフの初期化開始
+    FilterGraphInitialize(&'a FilterGraphInfo),
+    /// フィルタグラフの初期化終了
+    FilterGraphInitialized(&'a FilterGraphInfo),
+    /// フィルタグラフの終了処理開始
+    FilterGraphFinalize(&'a FilterGraphInfo),
+    /// フィルタグラフの終了処理終了
+    FilterGraphFinalized(&'a FilterGraphInfo),
+    /// コマンドアイコンの描画
+    DrawCommandIcon(&'a DrawCommandIconInfo),
+    /// ステータス項目を描画
+    StatusItemDraw(&'a StatusItemDrawInfo),
+    /// ステータス項目の通知
+    StatusItemNotify(&'a StatusItemEventInfo),
+    /// ステータス項目のマウス操作
+    StatusItemMouse(&'a StatusItemMouseEventInfo),
+    /// パネル項目の通知
+    PanelItemNotify(&'a PanelItemEventInfo),
+    /// パネル項目の作成
+    /// PanelItemEvent::Create の通知はこちらで渡されます
+    PanelItemCreate(&'a mut PanelItemCreateEventInfo),
+    /// お気に入りチャンネルが変更された
+    FavoritesChanged,
+    /// ワンセグモードが変わった
+    OneSegModeChanged(bool),
+    /// 変数の取得
+    GetVariable(&'a mut GetVariableInfo),
+}
+
+impl<'a> PluginEvent<'a> {
+    /// コールバックの引数からイベントを解釈します
+    /// 未知のイベントや範囲外の値、NULL ポインタが渡された場合は None を返します
+    ///
+    /// # Safety
+    /// ポインタで渡されるパラメータは、NULL でなければ `'a` の間有効でなければなりません
+    pub unsafe fn decode(event: u32, param1: LPARAM, param2: LPARAM) -> Option<Self> {
+        let p1 = param1.0;
+        let p2 = param2.0;
+
+        let event = match Event::try_from(event).ok()? {
+            Event::PluginEnable => PluginEvent::PluginEnable(p1 != 0),
+            Event::PluginSettings => PluginEvent::PluginSettings(HWND(p1)),
+            Event::ChannelChange => PluginEvent::ChannelChange,
+            Event::ServiceChange => PluginEvent::ServiceChange,
+            Event::DriverChange => PluginEvent::DriverChange,
+            Event::ServiceUpdate => PluginEvent::ServiceUpdate,
+            Event::RecordStatusChange => PluginEvent::RecordStatusChange(RecordStatus::try_from(u32::try_from(p1).ok()?).ok()?),
+            Event::FullScreenChange => PluginEvent::FullScreenChange(p1 != 0),
+            Event::PreviewChange => PluginEvent::PreviewChange(p1 != 0),
+            Event::VolumeChange => PluginEvent::VolumeChange { volume: p1 as i32, is_mute: p2 != 0 },
+            Event::StereoModeChange => PluginEvent::StereoModeChange(StereoMode::try_from(p1).ok()?),
+            Event::ColorChange => PluginEvent::ColorChange,
+            Event::StandBy => PluginEvent::StandBy(p1 != 0),
+            Event::Command => PluginEvent::Command(p1 as i32),
+            Event::Execute => PluginEvent::Execute(WideStringPtr(NonNull::new(p1 as *mut u16))),
+            Event::Reset => PluginEvent::Reset,
+            Event::StatusReset => PluginEvent::StatusReset,
+            Event::AudioStreamChange => PluginEvent::AudioStreamChange(p1 as i32),
+            Event::SettingsChange => PluginEvent::SettingsChange,
+            Event::Close => PluginEvent::Close,
+            Event::StartRecord => PluginEvent::StartRecord(borrow(p1)?),
+            Event::RelayRecord => PluginEvent::RelayRecord(WideStringPtr(NonNull::new(p1 as *mut u16))),
+            Event::ControllerFocus => PluginEvent::ControllerFocus(HWND(p1)),
+            Event::StartUpDone => PluginEvent::StartUpDone,
+            Event::ProgramGuideInitialize => PluginEvent::ProgramGuideInitialize(HWND(p1)),
+            Event::ProgramGuideFinalize => PluginEvent::ProgramGuideFinalize(HWND(p1)),
+            Event::ProgramGuideCommand => PluginEvent::ProgramGuideCommand { command: p1 as u32, param: borrow(p2)? },
+            Event::ProgramGuideInitializeMenu => PluginEvent::ProgramGuideInitializeMenu(borrow(p1)?),
+            Event::ProgramGuideMenuSelected => PluginEvent::ProgramGuideMenuSelected(p1 as u32),
+            Event::ProgramGuideProgramDrawBackground => PluginEvent::ProgramGuideProgramDrawBackground { program: borrow(p1)?, info: borrow(p2)? },
+            Event::ProgramGuideProgramInitializeMenu => PluginEvent::ProgramGuideProgramInitializeMenu { program: borrow(p1)?, info: borrow(p2)? },
+            Event::ProgramGuideProgramMenuSelected => PluginEvent::ProgramGuideProgramMenuSelected { program: borrow(p1)?, command: p2 as u32 },
+            Event::FilterGraphInitialize => PluginEvent::FilterGraphInitialize(borrow(p1)?),
+            Event::FilterGraphInitialized => PluginEvent::FilterGraphInitialized(borrow(p1)?),
+            Event::FilterGraphFinalize => PluginEvent::FilterGraphFinalize(borrow(p1)?),
+            Event::FilterGraphFinalized => PluginEvent::FilterGraphFinalized(borrow(p1)?),
+            Event::DrawCommandIcon => PluginEvent::DrawCommandIcon(borrow(p1)?),
+            Event::StatusItemDraw => PluginEvent::StatusItemDraw(borrow(p1)?),
+            Event::StatusItemNotify => PluginEvent::StatusItemNotify(borrow(p1)?),
+            Event::StatusItemMouse => PluginEvent::StatusItemMouse(borrow(p1)?),
+            Event::PanelItemNotify => {
+                // Create の場合は PanelItemCreateEventInfo として渡され、hwnd_item に書き込む
+                if borrow::<PanelItemEventInfo>(p1)?.event == PanelItemEvent::Create as u32 {
+                    PluginEvent::PanelItemCreate((p1 as *mut PanelItemCreateEventInfo).as_mut()?)
+                } else {
+                    PluginEvent::PanelItemNotify(borrow(p1)?)
+                }
+            }
+            Event::FavoritesChanged => PluginEvent::FavoritesChanged,
+            Event::OneSegModeChanged => PluginEvent::OneSegModeChanged(p1 != 0),
+            Event::GetVariable => PluginEvent::GetVariable((p1 as *mut GetVariableInfo).as_mut()?),
+            Event::Trailer => return None,
+        };
+
+        Some(event)
+    }
+
+    /// イベントの種類
+    pub fn kind(&self) -> Event {
+        match self {
+            PluginEvent::PluginEnable(_) => Event::PluginEnable,
+            PluginEvent::PluginSettings(_) => Event::PluginSettings,
+            PluginEvent::ChannelChange => Event::ChannelChange,
+            PluginEvent::ServiceChange => Event::ServiceChange,
+            PluginEvent::DriverChange => Event::DriverChange,
+            PluginEvent::ServiceUpdate => Event::ServiceUpdate,
+            PluginEvent::RecordStatusChange(_) => Event::RecordStatusChange,
+            PluginEvent::FullScreenChange(_) => Event::FullScreenChange,
+            PluginEvent::PreviewChange(_) => Event::PreviewChange,
+            PluginEvent::VolumeChange { .. } => Event::VolumeChange,
+            PluginEvent::StereoModeChange(_) => Event::StereoModeChange,
+            PluginEvent::ColorChange => Event::ColorChange,
+            PluginEvent::StandBy(_) => Event::StandBy,
+            PluginEvent::Command(_) => Event::Command,
+            PluginEvent::Execute(_) => Event::Execute,
+            PluginEvent::Reset => Event::Reset,
+            PluginEvent::StatusReset => Event::StatusReset,
+            PluginEvent::AudioStreamChange(_) => Event::AudioStreamChange,
+            PluginEvent::SettingsChange => Event::SettingsChange,
+            PluginEvent::Close => Event::Close,
+            PluginEvent::StartRecord(_) => Event::StartRecord,
+            PluginEvent::RelayRecord(_) => Event::RelayRecord,
+            PluginEvent::ControllerFocus(_) => Event::ControllerFocus,
+            PluginEvent::StartUpDone => Event::StartUpDone,
+            PluginEvent::ProgramGuideInitialize(_) => Event::ProgramGuideInitialize,
+            PluginEvent::ProgramGuideFinalize(_) => Event::ProgramGuideFinalize,
+            PluginEvent::ProgramGuideCommand { .. } => Event::ProgramGuideCommand,
+            PluginEvent::ProgramGuideInitializeMenu(_) => Event::ProgramGuideInitializeMenu,
+            PluginEvent::ProgramGuideMenuSelected(_) => Event::ProgramGuideMenuSelected,
+            PluginEvent::ProgramGuideProgramDrawBackground { .. } => Event::ProgramGuideProgramDrawBackground,
+            PluginEvent::ProgramGuideProgramInitializeMenu { .. } => Event::ProgramGuideProgramInitializeMenu,
+            PluginEvent::ProgramGuideProgramMenuSelected { .. } => Event::ProgramGuideProgramMenuSelected,
+            PluginEvent::FilterGraphInitialize(_) => Event::FilterGraphInitialize,
+            PluginEvent::FilterGraphInitialized(_) => Event::FilterGraphInitialized,
+            PluginEvent::FilterGraphFinalize(_) => Event::FilterGraphFinalize,
+            PluginEvent::FilterGraphFinalized(_) => Event::FilterGraphFinalized,
+            PluginEvent::DrawCommandIcon(_) => Event::DrawCommandIcon,
+            PluginEvent::StatusItemDraw(_) => Event::StatusItemDraw,
+            PluginEvent::StatusItemNotify(_) => Event::StatusItemNotify,
+            PluginEvent::StatusItemMouse(_) => Event::StatusItemMouse,
+            PluginEvent::PanelItemNotify(_) => Event::PanelItemNotify,
+            PluginEvent::PanelItemCreate(_) => Event::PanelItemNotify,
+            PluginEvent::FavoritesChanged => Event::FavoritesChanged,
+            PluginEvent::OneSegModeChanged(_) => Event::OneSegModeChanged,
+            PluginEvent::GetVariable(_) => Event::GetVariable,
+        }
+    }
+}
+
+unsafe fn borrow<'a, T>(param: isize) -> Option<&'a T> {
+    (param as *const T).as_ref()
+}
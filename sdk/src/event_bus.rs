@@ -0,0 +1,508 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use crate::event::{Event, PluginEvent};
+use crate::program_guide::{ProgramGuideInitializeMenuInfo, ProgramGuideMenu, ProgramGuideProgramInfo, ProgramGuideProgramInitializeMenuInfo};
+use crate::record::RecordStatus;
+use crate::stereo_mode::StereoMode;
+use crate::variable::GetVariableInfo;
+
+/// イベントを受け取るクロージャ
+/// 戻り値は TVTest に返す値で、複数の購読者がいる場合は MergePolicy に従って合成されます
+pub type EventSubscriber = dyn Fn(&mut PluginEvent) -> isize;
+
+/// 購読の ID
+/// EventBus::unsubscribe で購読を解除できます
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(test, derive(Debug))]
+pub struct SubscriptionId(pub(crate) u64);
+
+/// 複数の購読者の戻り値の合成方法
+#[derive(Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+pub enum MergePolicy {
+    /// すべての購読者を呼び出し、いずれかが 0 以外を返した場合は 1 を返します
+    Broadcast,
+    /// 購読者を順に呼び出し、0 を返した購読者がいればそこで拒否します
+    /// 購読者がいない場合は TVTestEventHandler の既定の戻り値を返します
+    Veto,
+    /// 0 以外を返した最初の購読者の戻り値を返し、以降の購読者は呼び出しません
+    FirstHandled,
+    /// 購読者ごとに項目のIDをずらしてメニューを初期化し、使用したIDの数の合計を返します
+    /// 選択された項目は、その項目を追加した購読者に相対値で渡されます
+    Menu,
+}
+
+impl MergePolicy {
+    /// イベントの種類ごとの合成方法
+    pub fn of(kind: Event) -> Self {
+        match kind {
+            Event::PluginEnable
+            | Event::ProgramGuideInitialize
+            | Event::ProgramGuideFinalize => MergePolicy::Veto,
+            Event::PluginSettings
+            | Event::Command
+            | Event::ProgramGuideCommand
+            | Event::ProgramGuideProgramDrawBackground
+            | Event::DrawCommandIcon
+            | Event::StatusItemDraw
+            | Event::StatusItemNotify
+            | Event::StatusItemMouse
+            | Event::PanelItemNotify
+            | Event::GetVariable => MergePolicy::FirstHandled,
+            Event::ProgramGuideInitializeMenu
+            | Event::ProgramGuideMenuSelected
+            | Event::ProgramGuideProgramInitializeMenu
+            | Event::ProgramGuideProgramMenuSelected => MergePolicy::Menu,
+            _ => MergePolicy::Broadcast,
+        }
+    }
+}
+
+struct Subscriber {
+    id: SubscriptionId,
+    kind: Event,
+    callback: Rc<EventSubscriber>,
+}
+
+// 直前のメニューの初期化で購読者に割り当てたIDの範囲
+struct MenuRange {
+    id: SubscriptionId,
+    kind: Event,
+    start: u32,
+    count: u32,
+}
+
+/// 複数の購読者にイベントを配信するイベントバス
+/// Rc で共有し、TVTestEventHandler::on_event から dispatch を呼び出します
+pub struct EventBus {
+    next_id: Cell<u64>,
+    subscribers: RefCell<Vec<Subscriber>>,
+    menu_ranges: RefCell<Vec<MenuRange>>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self {
+            next_id: Cell::new(1),
+            subscribers: RefCell::new(Vec::new()),
+            menu_ranges: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn next_id(&self) -> SubscriptionId {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        SubscriptionId(id)
+    }
+
+    fn add(&self, id: SubscriptionId, kind: Event, callback: Rc<EventSubscriber>) {
+        self.subscribers.borrow_mut().push(Subscriber { id, kind, callback });
+    }
+
+    /// イベントの種類を指定して購読します
+    pub fn subscribe<F>(&self, kind: Event, callback: F) -> SubscriptionId
+        where F: Fn(&mut PluginEvent) -> isize + 'static
+    {
+        let id = self.next_id();
+        self.add(id, kind, Rc::new(callback));
+        id
+    }
+
+    /// 購読を解除します
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        self.menu_ranges.borrow_mut().retain(|range| range.id != id);
+
+        let mut subscribers = self.subscribers.borrow_mut();
+        let len = subscribers.len();
+        subscribers.retain(|subscriber| subscriber.id != id);
+        subscribers.len() != len
+    }
+
+    /// 購読者の数
+    pub fn subscriber_count(&self, kind: Event) -> usize {
+        self.subscribers.borrow().iter().filter(|subscriber| subscriber.kind == kind).count()
+    }
+
+    // 購読者の呼び出し中に購読や解除ができるように複製して返す
+    fn subscribers_of(&self, kind: Event) -> Vec<(SubscriptionId, Rc<EventSubscriber>)> {
+        self.subscribers.borrow().iter()
+            .filter(|subscriber| subscriber.kind == kind)
+            .map(|subscriber| (subscriber.id, Rc::clone(&subscriber.callback)))
+            .collect()
+    }
+
+    /// イベントを購読者に配信し、TVTest に返す値を返します
+    pub fn dispatch(&self, mut event: PluginEvent) -> isize {
+        let kind = event.kind();
+        let subscribers = self.subscribers_of(kind);
+
+        match MergePolicy::of(kind) {
+            MergePolicy::Broadcast => {
+                let mut result = 0;
+                for (_, callback) in subscribers {
+                    if callback(&mut event) != 0 {
+                        result = 1;
+                    }
+                }
+                result
+            },
+            MergePolicy::Veto => {
+                if subscribers.is_empty() {
+                    // TVTestEventHandler の既定値に合わせる
+                    return (kind != Event::PluginEnable) as isize;
+                }
+                subscribers.iter().all(|(_, callback)| callback(&mut event) != 0) as isize
+            },
+            MergePolicy::FirstHandled => {
+                subscribers.iter()
+                    .map(|(_, callback)| callback(&mut event))
+                    .find(|&result| result != 0)
+                    .unwrap_or(0)
+            },
+            MergePolicy::Menu => self.dispatch_menu(event, subscribers),
+        }
+    }
+
+    fn dispatch_menu(&self, event: PluginEvent, subscribers: Vec<(SubscriptionId, Rc<EventSubscriber>)>) -> isize {
+        match event {
+            PluginEvent::ProgramGuideInitializeMenu(info) => {
+                self.initialize_menu(Event::ProgramGuideInitializeMenu, subscribers, |offset, callback| {
+                    let info = ProgramGuideInitializeMenuInfo {
+                        hmenu: info.hmenu,
+                        command: info.command + offset,
+                        reserved: info.reserved,
+                    };
+                    callback(&mut PluginEvent::ProgramGuideInitializeMenu(&info))
+                })
+            },
+            PluginEvent::ProgramGuideProgramInitializeMenu { program, info } => {
+                self.initialize_menu(Event::ProgramGuideProgramInitializeMenu, subscribers, |offset, callback| {
+                    let info = ProgramGuideProgramInitializeMenuInfo {
+                        hmenu: info.hmenu,
+                        command: info.command + offset,
+                        reserved: info.reserved,
+                        cursor_pos: info.cursor_pos,
+                        item_rect: info.item_rect,
+                    };
+                    callback(&mut PluginEvent::ProgramGuideProgramInitializeMenu { program, info: &info })
+                })
+            },
+            PluginEvent::ProgramGuideMenuSelected(command) => {
+                self.select_menu(Event::ProgramGuideInitializeMenu, command, subscribers, |command, callback| {
+                    callback(&mut PluginEvent::ProgramGuideMenuSelected(command))
+                })
+            },
+            PluginEvent::ProgramGuideProgramMenuSelected { program, command } => {
+                self.select_menu(Event::ProgramGuideProgramInitializeMenu, command, subscribers, |command, callback| {
+                    callback(&mut PluginEvent::ProgramGuideProgramMenuSelected { program, command })
+                })
+            },
+            _ => 0,
+        }
+    }
+
+    fn initialize_menu<F>(&self, kind: Event, subscribers: Vec<(SubscriptionId, Rc<EventSubscriber>)>, initialize: F) -> isize
+        where F: Fn(u32, &EventSubscriber) -> isize
+    {
+        let mut ranges = Vec::new();
+        let mut offset = 0;
+        for (id, callback) in subscribers {
+            let count = initialize(offset, &*callback).max(0) as u32;
+            ranges.push(MenuRange { id, kind, start: offset, count });
+            offset += count;
+        }
+
+        let mut menu_ranges = self.menu_ranges.borrow_mut();
+        menu_ranges.retain(|range| range.kind != kind);
+        menu_ranges.extend(ranges);
+
+        offset as isize
+    }
+
+    fn select_menu<F>(&self, kind: Event, command: u32, subscribers: Vec<(SubscriptionId, Rc<EventSubscriber>)>, select: F) -> isize
+        where F: Fn(u32, &EventSubscriber) -> isize
+    {
+        let range = self.menu_ranges.borrow().iter()
+            .find(|range| range.kind == kind && (range.start..range.start + range.count).contains(&command))
+            .map(|range| (range.id, range.start));
+
+        match range {
+            Some((id, start)) => subscribers.iter()
+                .filter(|(subscriber, _)| *subscriber == id)
+                .map(|(_, callback)| select(command - start, &**callback))
+                .find(|&result| result != 0)
+                .unwrap_or(0),
+            None => 0,
+        }
+    }
+
+    /// 有効状態の変化を購読します
+    /// 変化を拒否する場合 false を返します
+    pub fn on_plugin_enable<F: Fn(bool) -> bool + 'static>(&self, callback: F) -> SubscriptionId {
+        self.subscribe(Event::PluginEnable, move |event| match event {
+            PluginEvent::PluginEnable(is_enable) => callback(*is_enable) as isize,
+            _ => 0,
+        })
+    }
+
+    /// チャンネルの変更を購読します
+    pub fn on_channel_change<F: Fn() + 'static>(&self, callback: F) -> SubscriptionId {
+        self.subscribe(Event::ChannelChange, move |_| {
+            callback();
+            0
+        })
+    }
+
+    /// サービスの変更を購読します
+    pub fn on_service_change<F: Fn() + 'static>(&self, callback: F) -> SubscriptionId {
+        self.subscribe(Event::ServiceChange, move |_| {
+            callback();
+            0
+        })
+    }
+
+    /// ドライバの変更を購読します
+    pub fn on_driver_change<F: Fn() + 'static>(&self, callback: F) -> SubscriptionId {
+        self.subscribe(Event::DriverChange, move |_| {
+            callback();
+            0
+        })
+    }
+
+    /// サービスの構成の変化を購読します
+    pub fn on_service_update<F: Fn() + 'static>(&self, callback: F) -> SubscriptionId {
+        self.subscribe(Event::ServiceUpdate, move |_| {
+            callback();
+            0
+        })
+    }
+
+    /// 録画状態の変化を購読します
+    pub fn on_record_status_change<F: Fn(RecordStatus) + 'static>(&self, callback: F) -> SubscriptionId {
+        self.subscribe(Event::RecordStatusChange, move |event| {
+            if let PluginEvent::RecordStatusChange(status) = event {
+                callback(*status);
+            }
+            0
+        })
+    }
+
+    /// 全画面表示状態の変化を購読します
+    pub fn on_fullscreen_change<F: Fn(bool) + 'static>(&self, callback: F) -> SubscriptionId {
+        self.subscribe(Event::FullScreenChange, move |event| {
+            if let PluginEvent::FullScreenChange(is_fullscreen) = event {
+                callback(*is_fullscreen);
+            }
+            0
+        })
+    }
+
+    /// プレビュー表示状態の変化を購読します
+    pub fn on_preview_change<F: Fn(bool) + 'static>(&self, callback: F) -> SubscriptionId {
+        self.subscribe(Event::PreviewChange, move |event| {
+            if let PluginEvent::PreviewChange(is_preview) = event {
+                callback(*is_preview);
+            }
+            0
+        })
+    }
+
+    /// 音量の変化を購読します
+    pub fn on_volume_change<F: Fn(i32, bool) + 'static>(&self, callback: F) -> SubscriptionId {
+        self.subscribe(Event::VolumeChange, move |event| {
+            if let PluginEvent::VolumeChange { volume, is_mute } = event {
+                callback(*volume, *is_mute);
+            }
+            0
+        })
+    }
+
+    /// ステレオモードの変化を購読します
+    pub fn on_stereo_mode_change<F: Fn(StereoMode) + 'static>(&self, callback: F) -> SubscriptionId {
+        self.subscribe(Event::StereoModeChange, move |event| {
+            if let PluginEvent::StereoModeChange(mode) = event {
+                callback(*mode);
+            }
+            0
+        })
+    }
+
+    /// 待機状態の変化を購読します
+    pub fn on_standby<F: Fn(bool) + 'static>(&self, callback: F) -> SubscriptionId {
+        self.subscribe(Event::StandBy, move |event| {
+            if let PluginEvent::StandBy(is_standby) = event {
+                callback(*is_standby);
+            }
+            0
+        })
+    }
+
+    /// コマンドの選択を購読します
+    /// コマンドを処理した場合は true を返します
+    pub fn on_command<F: Fn(i32) -> bool + 'static>(&self, callback: F) -> SubscriptionId {
+        self.subscribe(Event::Command, move |event| match event {
+            PluginEvent::Command(id) => callback(*id) as isize,
+            _ => 0,
+        })
+    }
+
+    /// ウィンドウが閉じられるのを購読します
+    pub fn on_close<F: Fn() + 'static>(&self, callback: F) -> SubscriptionId {
+        self.subscribe(Event::Close, move |_| {
+            callback();
+            0
+        })
+    }
+
+    /// 起動時の処理の終了を購読します
+    pub fn on_startup_done<F: Fn() + 'static>(&self, callback: F) -> SubscriptionId {
+        self.subscribe(Event::StartUpDone, move |_| {
+            callback();
+            0
+        })
+    }
+
+    /// ワンセグモードの変化を購読します
+    pub fn on_one_seg_mode_changed<F: Fn(bool) + 'static>(&self, callback: F) -> SubscriptionId {
+        self.subscribe(Event::OneSegModeChanged, move |event| {
+            if let PluginEvent::OneSegModeChanged(mode) = event {
+                callback(*mode);
+            }
+            0
+        })
+    }
+
+    /// 変数の取得を購読します
+    /// 値を設定した場合は true を返します
+    pub fn on_get_variable<F: Fn(&mut GetVariableInfo) -> bool + 'static>(&self, callback: F) -> SubscriptionId {
+        self.subscribe(Event::GetVariable, move |event| match event {
+            PluginEvent::GetVariable(info) => callback(info) as isize,
+            _ => 0,
+        })
+    }
+
+    /// 番組表のメニューを購読します
+    /// `initialize` は使用した項目のIDの数を返し、`selected` には最初の項目のIDからの相対値が渡されます
+    pub fn on_program_guide_menu<I, S>(&self, initialize: I, selected: S) -> SubscriptionId
+        where I: Fn(&ProgramGuideInitializeMenuInfo) -> i32 + 'static,
+              S: Fn(u32) -> bool + 'static
+    {
+        let id = self.next_id();
+        self.add(id, Event::ProgramGuideInitializeMenu, Rc::new(move |event: &mut PluginEvent| match event {
+            PluginEvent::ProgramGuideInitializeMenu(info) => initialize(info) as isize,
+            _ => 0,
+        }));
+        self.add(id, Event::ProgramGuideMenuSelected, Rc::new(move |event: &mut PluginEvent| match event {
+            PluginEvent::ProgramGuideMenuSelected(command) => selected(*command) as isize,
+            _ => 0,
+        }));
+        id
+    }
+
+    /// 番組表の番組のメニューを購読します
+    /// `initialize` は使用した項目のIDの数を返し、`selected` には最初の項目のIDからの相対値が渡されます
+    pub fn on_program_guide_program_menu<I, S>(&self, initialize: I, selected: S) -> SubscriptionId
+        where I: Fn(&ProgramGuideProgramInfo, &ProgramGuideProgramInitializeMenuInfo) -> i32 + 'static,
+              S: Fn(&ProgramGuideProgramInfo, u32) -> bool + 'static
+    {
+        let id = self.next_id();
+        self.add(id, Event::ProgramGuideProgramInitializeMenu, Rc::new(move |event: &mut PluginEvent| match event {
+            PluginEvent::ProgramGuideProgramInitializeMenu { program, info } => initialize(program, info) as isize,
+            _ => 0,
+        }));
+        self.add(id, Event::ProgramGuideProgramMenuSelected, Rc::new(move |event: &mut PluginEvent| match event {
+            PluginEvent::ProgramGuideProgramMenuSelected { program, command } => selected(program, *command) as isize,
+            _ => 0,
+        }));
+        id
+    }
+
+    /// 番組表のメニューを登録します
+    pub fn program_guide_menu(&self, menu: ProgramGuideMenu<()>) -> SubscriptionId {
+        let menu = Rc::new(menu);
+        let selected = Rc::clone(&menu);
+        self.on_program_guide_menu(move |info| menu.initialize(info), move |command| selected.dispatch(command, &()))
+    }
+
+    /// 番組表の番組のメニューを登録します
+    pub fn program_guide_program_menu(&self, menu: ProgramGuideMenu<ProgramGuideProgramInfo>) -> SubscriptionId {
+        let menu = Rc::new(menu);
+        let selected = Rc::clone(&menu);
+        self.on_program_guide_program_menu(
+            move |program, info| menu.initialize(program, info),
+            move |program, command| selected.dispatch(command, program),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use windows::Win32::UI::WindowsAndMessaging::HMENU;
+    use super::*;
+
+    #[test]
+    fn merge_results() {
+        let bus = Rc::new(EventBus::new());
+        let log = Rc::new(RefCell::new(Vec::new()));
+
+        // 独立した二つのコンポーネント
+        let first = Rc::clone(&log);
+        let recorder = bus.on_record_status_change(move |status| first.borrow_mut().push(format!("a:{}", status as u32)));
+        let second = Rc::clone(&log);
+        bus.on_record_status_change(move |status| second.borrow_mut().push(format!("b:{}", status as u32)));
+        bus.on_plugin_enable(|_| true);
+        bus.on_plugin_enable(|is_enable| is_enable);
+
+        assert_eq!(bus.dispatch(PluginEvent::RecordStatusChange(RecordStatus::Recording)), 0);
+        assert!(bus.unsubscribe(recorder));
+        assert!(!bus.unsubscribe(recorder));
+        bus.dispatch(PluginEvent::RecordStatusChange(RecordStatus::Paused));
+        assert_eq!(*log.borrow(), ["a:1", "b:1", "b:2"]);
+
+        // 一つでも拒否すれば拒否
+        assert_eq!(bus.dispatch(PluginEvent::PluginEnable(true)), 1);
+        assert_eq!(bus.dispatch(PluginEvent::PluginEnable(false)), 0);
+        // 購読者がいない場合は既定値
+        assert_eq!(bus.dispatch(PluginEvent::ProgramGuideInitialize(Default::default())), 1);
+
+        // 最初に処理した購読者で終わる
+        bus.on_command(|id| id == 1);
+        bus.on_command(|id| id <= 2);
+        assert_eq!(bus.dispatch(PluginEvent::Command(2)), 1);
+        assert_eq!(bus.dispatch(PluginEvent::Command(3)), 0);
+    }
+
+    #[test]
+    fn menu_commands_are_offset_per_subscriber() {
+        let bus = EventBus::new();
+        let selected = Rc::new(RefCell::new(Vec::new()));
+
+        for (name, count) in [("a", 2), ("b", 3)] {
+            let selected = Rc::clone(&selected);
+            bus.on_program_guide_menu(
+                move |info| {
+                    assert_eq!(info.command, if name == "a" { 100 } else { 102 });
+                    count
+                },
+                move |command| {
+                    selected.borrow_mut().push((name, command));
+                    true
+                },
+            );
+        }
+
+        let info = ProgramGuideInitializeMenuInfo { hmenu: HMENU(0), command: 100, reserved: 0 };
+        assert_eq!(bus.dispatch(PluginEvent::ProgramGuideInitializeMenu(&info)), 5);
+        assert_eq!(bus.dispatch(PluginEvent::ProgramGuideMenuSelected(1)), 1);
+        assert_eq!(bus.dispatch(PluginEvent::ProgramGuideMenuSelected(3)), 1);
+        assert_eq!(bus.dispatch(PluginEvent::ProgramGuideMenuSelected(5)), 0);
+        assert_eq!(*selected.borrow(), [("a", 1), ("b", 1)]);
+    }
+}
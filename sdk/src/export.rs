@@ -1,7 +1,15 @@
-use std::ptr::NonNull;
-use windows::Win32::Foundation::{HWND, LPARAM, LRESULT};
-use crate::{DrawCommandIconInfo, FilterGraphInfo, GetVariableInfo, PanelItemEventInfo, ProgramGuideCommandParam, ProgramGuideInitializeMenuInfo, ProgramGuideProgramDrawBackgroundInfo, ProgramGuideProgramInfo, ProgramGuideProgramInitializeMenuInfo, RecordStatus, StartRecordInfo, StatusItemDrawInfo, StatusItemEventInfo, StatusItemMouseEventInfo, StereoMode, TVTestEventHandler, WideStringPtr};
-use crate::event::Event;
+use std::io;
+use windows::Win32::Foundation::{LPARAM, LRESULT};
+use crate::{TVTestEventHandler, TVTestPlugin};
+use crate::api::PluginApi;
+use crate::event::PluginEvent;
+#[cfg(feature = "tokio")]
+pub use crate::runtime::PluginRuntime;
+
+/// tokio フィーチャーが無効な場合の PluginRuntime
+/// 値を持たないため、export_plugin! が保持するランタイムは常に None になります
+#[cfg(not(feature = "tokio"))]
+pub enum PluginRuntime {}
 
 #[repr(u32)]
 pub enum DllLoadReason {
@@ -19,6 +27,8 @@ macro_rules! export_plugin {
         static mut __UNSAFE_DLL__: Option<std::sync::Arc<tvtest::windows::Win32::Foundation::HINSTANCE>> = None;
         #[deprecated]
         static mut __UNSAFE_PLUGIN__: Option<$type> = None;
+        #[deprecated]
+        static mut __UNSAFE_RUNTIME__: Option<tvtest::export::PluginRuntime> = None;
 
         // エントリポイント
         // プラグインクラスのインスタンスの生成と破棄を行っています
@@ -63,16 +73,14 @@ macro_rules! export_plugin {
         #[no_mangle]
         #[deprecated]
         #[allow(deprecated)]
-        pub extern "system" fn TVTGetPluginInfo(
-            mut info: tvtest::plugin::PluginInfo,
+        pub unsafe extern "system" fn TVTGetPluginInfo(
+            info: *mut tvtest::plugin::PluginInfo,
         ) -> bool {
-            let new_info = <$type>::get_info();
-            info.kind = new_info.kind;
-            info.flags = new_info.flags;
-            info.name = new_info.name;
-            info.copyright = new_info.copyright;
-            info.description = new_info.description;
+            if info.is_null() {
+                return false;
+            }
 
+            info.write(<$type>::get_info());
             true
         }
 
@@ -97,7 +105,13 @@ macro_rules! export_plugin {
                 let plugin = <$type>::new(api);
                 plugin.api.set_event_callback(default_event_handler);
 
+                // tokio フィーチャーが有効な場合は initialize の前にランタイムを開始する
+                let runtime = match tvtest::export::start_runtime(&plugin, &plugin.api) {
+                    Ok(runtime) => runtime,
+                    Err(_) => return false,
+                };
                 let result = plugin.initialize();
+                __UNSAFE_RUNTIME__ = runtime;
                 __UNSAFE_PLUGIN__ = plugin.into();
 
                 result
@@ -115,6 +129,8 @@ macro_rules! export_plugin {
         pub unsafe extern "system" fn TVTFinalize() -> bool {
             if let Some(plugin) = &__UNSAFE_PLUGIN__ {
                 let result = plugin.finalize();
+                // タスクの終了を待ってからプラグインを破棄する
+                __UNSAFE_RUNTIME__ = None;
                 __UNSAFE_PLUGIN__ = None;
 
                 result
@@ -127,13 +143,13 @@ macro_rules! export_plugin {
         #[deprecated]
         #[allow(deprecated)]
         pub unsafe extern "system" fn default_event_handler(
-            event: tvtest::event::Event,
+            event: u32,
             param1: tvtest::windows::Win32::Foundation::LPARAM,
             param2: tvtest::windows::Win32::Foundation::LPARAM,
             client_data: tvtest::ClientData,
         ) -> tvtest::windows::Win32::Foundation::LRESULT {
             if let Some(plugin) = &__UNSAFE_PLUGIN__ {
-                tvtest::export::handle_event(event, param1, param2, plugin)
+                tvtest::export::handle_event_with_runtime(event, param1, param2, plugin, __UNSAFE_RUNTIME__.as_ref())
             } else {
                 panic!("__UNSAFE_PLUGIN__ has not initialized yet.")
             }
@@ -141,158 +157,210 @@ macro_rules! export_plugin {
     }
 }
 
+/// 非同期ランタイムを開始し、TVTestPlugin::on_runtime_start を呼び出します
+/// export_plugin! が TVTInitialize から呼び出します。tokio フィーチャーが無効な場合は何もしません
+#[allow(unused_variables)]
+pub fn start_runtime<T: TVTestPlugin>(plugin: &T, api: &PluginApi) -> io::Result<Option<PluginRuntime>> {
+    #[cfg(feature = "tokio")]
+    {
+        let runtime = PluginRuntime::start(api)?;
+        plugin.on_runtime_start(&runtime);
+
+        Ok(runtime.into())
+    }
+    #[cfg(not(feature = "tokio"))]
+    Ok(None)
+}
+
 #[inline]
 pub fn handle_event<T: TVTestEventHandler>(
-    event: Event,
+    event: u32,
     param1: LPARAM,
     param2: LPARAM,
     handler: &T,
 ) -> LRESULT {
-    let param1 = param1.0;
-    let param2 = param2.0;
-
-    let result: isize = match event {
-        Event::PluginEnable => handler.on_plugin_enable(param1 != 0) as isize,
-        Event::PluginSettings => handler.on_plugin_settings(HWND(param1)) as isize,
-        Event::ChannelChange => handler.on_channel_change() as isize,
-        Event::ServiceChange => handler.on_service_change() as isize,
-        Event::DriverChange => handler.on_driver_change() as isize,
-        Event::ServiceUpdate => handler.on_service_update() as isize,
-        Event::RecordStatusChange => unsafe {
-            let status = RecordStatus::from_unchecked(param1 as u32);
-            handler.on_record_status_change(status) as isize
-        },
-        Event::FullScreenChange => handler.on_fullscreen_change(param1 != 0) as isize,
-        Event::PreviewChange => handler.on_preview_change(param1 != 0) as isize,
-        Event::VolumeChange => handler.on_volume_change(param1 as i32, param2 != 0) as isize,
-        Event::StereoModeChange => unsafe {
-            let mode = StereoMode::from_unchecked(param1);
-            handler.on_stereo_mode_change(mode) as isize
-        },
-        Event::ColorChange => handler.on_color_change() as isize,
-        Event::StandBy => handler.on_standby(param1 != 0) as isize,
-        Event::Command => handler.on_command(param1 as i32) as isize,
-        Event::Execute => {
-            let ptr = WideStringPtr(NonNull::new(param1 as *mut u16));
-            handler.on_execute(ptr) as isize
-        },
-        Event::Reset => handler.on_reset() as isize,
-        Event::StatusReset => handler.on_status_reset() as isize,
-        Event::AudioStreamChange => handler.on_audio_stream_change(param1 as i32) as isize,
-        Event::SettingsChange => handler.on_settings_change() as isize,
-        Event::Close => handler.on_close() as isize,
-        Event::StartRecord => unsafe {
-            let ptr = param1 as *const StartRecordInfo;
-            let info = ptr.as_ref().unwrap();
-            handler.on_start_record(info) as isize
-        },
-        Event::RelayRecord => {
-            let ptr = WideStringPtr(NonNull::new(param1 as *mut u16));
-            handler.on_relay_record(ptr) as isize
-        },
-        Event::ControllerFocus => handler.on_controller_focus(HWND(param1)) as isize,
-        Event::StartUpDone => {
+    handle_event_with_runtime(event, param1, param2, handler, None)
+}
+
+/// イベントを処理し、ランタイムがあれば非同期タスクにも配信します
+#[inline]
+#[allow(unused_variables)]
+pub fn handle_event_with_runtime<T: TVTestEventHandler>(
+    event: u32,
+    param1: LPARAM,
+    param2: LPARAM,
+    handler: &T,
+    runtime: Option<&PluginRuntime>,
+) -> LRESULT {
+    // 未知のイベントや解釈できないパラメータは on_unknown_event に渡す
+    match unsafe { PluginEvent::decode(event, param1, param2) } {
+        Some(event) => {
+            #[cfg(feature = "tokio")]
+            if let Some(runtime) = runtime {
+                runtime.notify(&event);
+            }
+            LRESULT(handler.on_event(event))
+        }
+        None => LRESULT(handler.on_unknown_event(event, param1, param2)),
+    }
+}
+
+/// 解釈済みのイベントを TVTestEventHandler の各メソッドに振り分けます
+pub fn dispatch_event<T: TVTestEventHandler>(event: PluginEvent, handler: &T) -> isize {
+    match event {
+        PluginEvent::PluginEnable(is_enable) => handler.on_plugin_enable(is_enable) as isize,
+        PluginEvent::PluginSettings(owner) => handler.on_plugin_settings(owner) as isize,
+        PluginEvent::ChannelChange => handler.on_channel_change() as isize,
+        PluginEvent::ServiceChange => handler.on_service_change() as isize,
+        PluginEvent::DriverChange => handler.on_driver_change() as isize,
+        PluginEvent::ServiceUpdate => handler.on_service_update() as isize,
+        PluginEvent::RecordStatusChange(status) => handler.on_record_status_change(status) as isize,
+        PluginEvent::FullScreenChange(is_fullscreen) => handler.on_fullscreen_change(is_fullscreen) as isize,
+        PluginEvent::PreviewChange(is_preview) => handler.on_preview_change(is_preview) as isize,
+        PluginEvent::VolumeChange { volume, is_mute } => handler.on_volume_change(volume, is_mute) as isize,
+        PluginEvent::StereoModeChange(mode) => handler.on_stereo_mode_change(mode) as isize,
+        PluginEvent::ColorChange => handler.on_color_change() as isize,
+        PluginEvent::StandBy(is_standby) => handler.on_standby(is_standby) as isize,
+        PluginEvent::Command(id) => handler.on_command(id) as isize,
+        PluginEvent::Execute(command_line) => handler.on_execute(command_line) as isize,
+        PluginEvent::Reset => handler.on_reset() as isize,
+        PluginEvent::StatusReset => handler.on_status_reset() as isize,
+        PluginEvent::AudioStreamChange(stream) => handler.on_audio_stream_change(stream) as isize,
+        PluginEvent::SettingsChange => handler.on_settings_change() as isize,
+        PluginEvent::Close => handler.on_close() as isize,
+        PluginEvent::StartRecord(info) => handler.on_start_record(info) as isize,
+        PluginEvent::RelayRecord(file_name) => handler.on_relay_record(file_name) as isize,
+        PluginEvent::ControllerFocus(hwnd) => handler.on_controller_focus(hwnd) as isize,
+        PluginEvent::StartUpDone => {
             handler.on_startup_done();
             0
         },
-        Event::ProgramGuideInitialize => handler.on_program_guide_initialize(HWND(param1)) as isize,
-        Event::ProgramGuideFinalize => handler.on_program_guide_finalize(HWND(param1)) as isize,
-        Event::ProgramGuideCommand => unsafe {
-            let ptr = param2 as *const ProgramGuideCommandParam;
-            let param = ptr.as_ref().unwrap();
-            handler.on_program_guide_command(param1 as u32, param) as isize
-        },
-        Event::ProgramGuideInitializeMenu => unsafe {
-            let ptr = param1 as *const ProgramGuideInitializeMenuInfo;
-            let info = ptr.as_ref().unwrap();
-            handler.on_program_guide_initialize_menu(info) as isize
-        }
-        Event::ProgramGuideMenuSelected => handler.on_program_guide_menu_selected(param1 as u32) as isize,
-        Event::ProgramGuideProgramDrawBackground => unsafe {
-            let ptr1 = param1 as *const ProgramGuideProgramInfo;
-            let program_info = ptr1.as_ref().unwrap();
-            let ptr2 = param2 as *const ProgramGuideProgramDrawBackgroundInfo;
-            let info = ptr2.as_ref().unwrap();
-            handler.on_program_guide_program_draw_background(program_info, info) as isize
-        }
-        Event::ProgramGuideProgramInitializeMenu => unsafe {
-            let ptr1 = param1 as *const ProgramGuideProgramInfo;
-            let program_info = ptr1.as_ref().unwrap();
-            let ptr2 = param2 as *const ProgramGuideProgramInitializeMenuInfo;
-            let info = ptr2.as_ref().unwrap();
-            handler.on_program_guide_program_initialize_menu(program_info, info) as isize
-        }
-        Event::ProgramGuideProgramMenuSelected => unsafe {
-            let ptr = param1 as *const ProgramGuideProgramInfo;
-            let program_info = ptr.as_ref().unwrap();
-            handler.on_program_guide_program_menu_selected(program_info, param2 as u32) as isize
-        }
-        Event::FilterGraphInitialize => unsafe {
-            let ptr = param1 as *const FilterGraphInfo;
-            let info = ptr.as_ref().unwrap();
+        PluginEvent::ProgramGuideInitialize(hwnd) => handler.on_program_guide_initialize(hwnd) as isize,
+        PluginEvent::ProgramGuideFinalize(hwnd) => handler.on_program_guide_finalize(hwnd) as isize,
+        PluginEvent::ProgramGuideCommand { command, param } => handler.on_program_guide_command(command, param) as isize,
+        PluginEvent::ProgramGuideInitializeMenu(info) => handler.on_program_guide_initialize_menu(info) as isize,
+        PluginEvent::ProgramGuideMenuSelected(command) => handler.on_program_guide_menu_selected(command) as isize,
+        PluginEvent::ProgramGuideProgramDrawBackground { program, info } => handler.on_program_guide_program_draw_background(program, info) as isize,
+        PluginEvent::ProgramGuideProgramInitializeMenu { program, info } => handler.on_program_guide_program_initialize_menu(program, info) as isize,
+        PluginEvent::ProgramGuideProgramMenuSelected { program, command } => handler.on_program_guide_program_menu_selected(program, command) as isize,
+        PluginEvent::FilterGraphInitialize(info) => {
             handler.on_filter_graph_initialize(info);
             0
-        }
-        Event::FilterGraphInitialized => unsafe {
-            let ptr = param1 as *const FilterGraphInfo;
-            let info = ptr.as_ref().unwrap();
+        },
+        PluginEvent::FilterGraphInitialized(info) => {
             handler.on_filter_graph_initialized(info);
             0
-        }
-        Event::FilterGraphFinalize => unsafe {
-            let ptr = param1 as *const FilterGraphInfo;
-            let info = ptr.as_ref().unwrap();
+        },
+        PluginEvent::FilterGraphFinalize(info) => {
             handler.on_filter_graph_finalize(info);
             0
-        }
-        Event::FilterGraphFinalized => unsafe {
-            let ptr = param1 as *const FilterGraphInfo;
-            let info = ptr.as_ref().unwrap();
+        },
+        PluginEvent::FilterGraphFinalized(info) => {
             handler.on_filter_graph_finalized(info);
             0
-        }
-        Event::DrawCommandIcon => unsafe {
-            let ptr = param1 as *const DrawCommandIconInfo;
-            let info = ptr.as_ref().unwrap();
-            handler.on_draw_command_icon(info) as isize
-        }
-        Event::StatusItemDraw => unsafe {
-            let ptr = param1 as *const StatusItemDrawInfo;
-            let info = ptr.as_ref().unwrap();
-            handler.on_status_item_draw(info) as isize
-        }
-        Event::StatusItemNotify => unsafe {
-            let ptr = param1 as *const StatusItemEventInfo;
-            let info = ptr.as_ref().unwrap();
-            handler.on_status_item_notify(info) as isize
-        }
-        Event::StatusItemMouse => unsafe {
-            let ptr = param1 as *const StatusItemMouseEventInfo;
-            let info = ptr.as_ref().unwrap();
-            handler.on_status_item_mouse_event(info) as isize
-        }
-        Event::PanelItemNotify => unsafe {
-            let ptr = param1 as *const PanelItemEventInfo;
-            let info = ptr.as_ref().unwrap();
-            handler.on_panel_item_notify(info) as isize
-        }
-        Event::FavoritesChanged => {
+        },
+        PluginEvent::DrawCommandIcon(info) => handler.on_draw_command_icon(info) as isize,
+        PluginEvent::StatusItemDraw(info) => handler.on_status_item_draw(info) as isize,
+        PluginEvent::StatusItemNotify(info) => handler.on_status_item_notify(info) as isize,
+        PluginEvent::StatusItemMouse(info) => handler.on_status_item_mouse_event(info) as isize,
+        PluginEvent::PanelItemNotify(info) => handler.on_panel_item_notify(info) as isize,
+        PluginEvent::PanelItemCreate(info) => handler.on_panel_item_create(info) as isize,
+        PluginEvent::FavoritesChanged => {
             handler.on_favorites_changed();
             0
-        }
-        Event::OneSegModeChanged => {
-            handler.on_one_seg_mode_changed(param1 != 0);
-            0
-        }
-        Event::GetVariable => unsafe {
-            let ptr = param1 as *const GetVariableInfo;
-            let info = ptr.as_ref().unwrap();
-            handler.on_get_variable(info) as isize
         },
-        Event::Trailer => {
+        PluginEvent::OneSegModeChanged(mode) => {
+            handler.on_one_seg_mode_changed(mode);
             0
+        },
+        PluginEvent::GetVariable(info) => handler.on_get_variable(info) as isize,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use crate::event::Event;
+    use crate::record::RecordStatus;
+    use crate::status_item::{StatusItemEvent, StatusItemEventInfo};
+    use super::*;
+
+    struct Handler {
+        statuses: RefCell<Vec<u32>>,
+        unknown: RefCell<Vec<(u32, isize, isize)>>,
+        notified: RefCell<Vec<(u32, Option<StatusItemEvent>)>>,
+    }
+
+    impl TVTestEventHandler for Handler {
+        fn on_record_status_change(&self, status: RecordStatus) -> bool {
+            self.statuses.borrow_mut().push(status as u32);
+            true
         }
-    };
 
-    LRESULT(result)
+        fn on_status_item_notify(&self, info: &StatusItemEventInfo) -> bool {
+            self.notified.borrow_mut().push((info.event, info.event()));
+            true
+        }
+
+        fn on_unknown_event(&self, code: u32, param1: LPARAM, param2: LPARAM) -> isize {
+            self.unknown.borrow_mut().push((code, param1.0, param2.0));
+            -1
+        }
+    }
+
+    #[test]
+    fn unknown_events_are_not_decoded() {
+        let handler = Handler { statuses: RefCell::new(Vec::new()), unknown: RefCell::new(Vec::new()), notified: RefCell::new(Vec::new()) };
+        let event = |code: u32, param1: isize, param2: isize| handle_event(code, LPARAM(param1), LPARAM(param2), &handler).0;
+
+        assert_eq!(event(Event::RecordStatusChange as u32, RecordStatus::Paused as isize, 0), 1);
+        // 範囲外の録画状態、未知のイベント、NULL ポインタ
+        assert_eq!(event(Event::RecordStatusChange as u32, 3, 0), -1);
+        assert_eq!(event(Event::Trailer as u32 + 10, 1, 2), -1);
+        assert_eq!(event(Event::GetVariable as u32, 0, 0), -1);
+
+        assert_eq!(*handler.statuses.borrow(), vec![RecordStatus::Paused as u32]);
+        assert_eq!(*handler.unknown.borrow(), vec![
+            (Event::RecordStatusChange as u32, 3, 0),
+            (Event::Trailer as u32 + 10, 1, 2),
+            (Event::GetVariable as u32, 0, 0),
+        ]);
+    }
+
+    #[test]
+    fn unknown_status_item_event_is_kept_raw() {
+        let handler = Handler { statuses: RefCell::new(Vec::new()), unknown: RefCell::new(Vec::new()), notified: RefCell::new(Vec::new()) };
+        let notify = |info: &StatusItemEventInfo| handle_event(Event::StatusItemNotify as u32, LPARAM(info as *const _ as isize), LPARAM(0), &handler).0;
+
+        // 未知のイベントの種類は列挙型に変換せずにそのまま渡される
+        assert_eq!(notify(&StatusItemEventInfo { id: 1, event: 0x100, param: LPARAM(0) }), 1);
+        assert_eq!(notify(&StatusItemEventInfo { id: 1, event: StatusItemEvent::UpdateTimer as u32, param: LPARAM(0) }), 1);
+
+        assert_eq!(*handler.notified.borrow(), vec![(0x100, None), (StatusItemEvent::UpdateTimer as u32, Some(StatusItemEvent::UpdateTimer))]);
+        assert!(handler.unknown.borrow().is_empty());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn events_are_delivered_to_runtime() {
+        use std::time::Duration;
+        use crate::api_handle::ApiDispatcher;
+        use crate::mock::MockHost;
+        use crate::runtime::AsyncEvent;
+
+        let host = MockHost::new();
+        let dispatcher = ApiDispatcher::with_waker(&host.api(), || {});
+        let runtime = PluginRuntime::with_dispatcher(dispatcher).unwrap().shutdown_timeout(Duration::from_secs(1));
+        let mut events = runtime.events();
+        let handler = Handler { statuses: RefCell::new(Vec::new()), unknown: RefCell::new(Vec::new()), notified: RefCell::new(Vec::new()) };
+
+        // 未知のイベントは配信されない
+        let event = |code: u32, param1: isize| handle_event_with_runtime(code, LPARAM(param1), LPARAM(0), &handler, Some(&runtime)).0;
+        assert_eq!(event(Event::Trailer as u32 + 10, 0), -1);
+        assert_eq!(event(Event::RecordStatusChange as u32, RecordStatus::Paused as isize), 1);
+
+        let received = runtime.handle().block_on(events.next());
+        assert_eq!(received, Some(AsyncEvent::RecordStatusChange(RecordStatus::Paused)));
+        assert_eq!(*handler.statuses.borrow(), vec![RecordStatus::Paused as u32]);
+    }
 }
@@ -0,0 +1,376 @@
+use std::mem::size_of;
+use std::ptr;
+use std::slice;
+use enumflags2::BitFlags;
+#[cfg(feature = "json")]
+use std::{fs, io, path::Path};
+#[cfg(feature = "json")]
+use serde::{Deserialize, Serialize};
+use crate::win32::UnsafeIntoRustString;
+use crate::WideStringPtr;
+
+/// お気に入り項目の種類
+#[repr(u32)]
+#[derive(Copy, Clone, PartialEq, Eq, TryFromPrimitive)]
+#[cfg_attr(test, derive(Debug))]
+pub enum FavoriteItemKind {
+    /// フォルダ
+    Folder,
+    /// チャンネル
+    Channel,
+}
+
+/// お気に入りチャンネルのフラグ
+#[bitflags]
+#[derive(Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+#[repr(u32)]
+pub enum FavoriteChannelFlag {
+    /// チューナー指定を強制
+    ForceTunerChange = 0x0001,
+}
+
+/// お気に入りフォルダの情報
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct FavoriteFolderInfo {
+    /// 各種フラグ(現在は常に0)
+    pub flags: u32,
+    /// 子項目数
+    pub item_count: u32,
+    /// 子項目のリスト
+    pub item_list: *const FavoriteItemInfo,
+}
+
+/// お気に入りチャンネルの情報
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct FavoriteChannelInfo {
+    /// 各種フラグ
+    pub flags: BitFlags<FavoriteChannelFlag>,
+    /// チューニング空間
+    pub space: i32,
+    /// チャンネルインデックス
+    pub channel: i32,
+    /// リモコン番号
+    pub channel_no: i32,
+    /// ネットワークID
+    pub network_id: u16,
+    /// トランスポートストリームID
+    pub transport_stream_id: u16,
+    /// サービスID
+    pub service_id: u16,
+    /// 予約(現在は常に0)
+    pub reserved: u16,
+    /// チューナー名
+    pub tuner: WideStringPtr,
+}
+
+#[repr(C)]
+pub union FavoriteItemData {
+    /// kind == FavoriteItemKind::Folder の場合
+    pub folder: FavoriteFolderInfo,
+    /// kind == FavoriteItemKind::Channel の場合
+    pub channel: FavoriteChannelInfo,
+}
+
+/// お気に入り項目の情報
+#[repr(C)]
+pub struct FavoriteItemInfo {
+    /// 種類 (FavoriteItemKind の値)
+    /// 未知の値が返される可能性があるため、kind() で取得してください
+    pub kind: u32,
+    /// 各種フラグ(現在は常に0)
+    pub flags: u32,
+    /// 名前
+    pub name: WideStringPtr,
+    pub data: FavoriteItemData,
+}
+
+impl FavoriteItemInfo {
+    /// 種類
+    /// 未知の値の場合は None を返します
+    pub fn kind(&self) -> Option<FavoriteItemKind> {
+        FavoriteItemKind::try_from(self.kind).ok()
+    }
+}
+
+/// お気に入りリスト
+#[repr(C)]
+pub struct FavoriteList {
+    /// 構造体のサイズ
+    pub size: u32,
+    /// お気に入り項目数
+    pub item_count: u32,
+    /// お気に入り項目のリスト
+    pub item_list: *mut FavoriteItemInfo,
+}
+
+impl Default for FavoriteList {
+    fn default() -> Self {
+        Self {
+            size: size_of::<FavoriteList>() as u32,
+            item_count: 0,
+            item_list: ptr::null_mut(),
+        }
+    }
+}
+
+/// お気に入りのフォルダ
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+pub struct FavoriteFolder {
+    /// 名前
+    pub name: String,
+    /// 子項目
+    #[cfg_attr(feature = "json", serde(default))]
+    pub items: Vec<FavoriteItem>,
+}
+
+/// お気に入りのチャンネル
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+pub struct FavoriteChannel {
+    /// 名前
+    pub name: String,
+    /// チューナー名 (BonDriver のファイル名)
+    pub tuner: String,
+    /// チューニング空間
+    pub space: i32,
+    /// チャンネルインデックス
+    pub channel: i32,
+    /// リモコン番号
+    pub channel_no: i32,
+    /// ネットワークID
+    pub network_id: u16,
+    /// トランスポートストリームID
+    pub transport_stream_id: u16,
+    /// サービスID
+    pub service_id: u16,
+    /// チューナー指定を強制
+    #[cfg_attr(feature = "json", serde(default))]
+    pub force_tuner_change: bool,
+}
+
+/// お気に入りの項目
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "json", serde(tag = "type", rename_all = "snake_case"))]
+pub enum FavoriteItem {
+    Folder(FavoriteFolder),
+    Channel(FavoriteChannel),
+}
+
+impl FavoriteItem {
+    /// 名前
+    pub fn name(&self) -> &str {
+        match self {
+            FavoriteItem::Folder(folder) => &folder.name,
+            FavoriteItem::Channel(channel) => &channel.name,
+        }
+    }
+
+    /// FavoriteItemInfo を変換します
+    /// 未知の種類の項目の場合は None を返します
+    ///
+    /// # Safety
+    /// `info` とその子項目が指す文字列やリストは有効である必要があります
+    pub unsafe fn from_raw(info: &FavoriteItemInfo) -> Option<Self> {
+        let kind = info.kind()?;
+        let name = info.name.read_string().unwrap_or_default();
+
+        let item = match kind {
+            FavoriteItemKind::Folder => FavoriteItem::Folder(FavoriteFolder {
+                name,
+                items: items_from_raw(info.data.folder.item_list, info.data.folder.item_count),
+            }),
+            FavoriteItemKind::Channel => {
+                let channel = &info.data.channel;
+
+                FavoriteItem::Channel(FavoriteChannel {
+                    name,
+                    tuner: channel.tuner.read_string().unwrap_or_default(),
+                    space: channel.space,
+                    channel: channel.channel,
+                    channel_no: channel.channel_no,
+                    network_id: channel.network_id,
+                    transport_stream_id: channel.transport_stream_id,
+                    service_id: channel.service_id,
+                    force_tuner_change: channel.flags.contains(FavoriteChannelFlag::ForceTunerChange),
+                })
+            }
+        };
+
+        Some(item)
+    }
+}
+
+// 未知の種類の項目は読み飛ばす
+unsafe fn items_from_raw(list: *const FavoriteItemInfo, count: u32) -> Vec<FavoriteItem> {
+    if list.is_null() {
+        return Vec::new();
+    }
+
+    slice::from_raw_parts(list, count as usize)
+        .iter()
+        .filter_map(|info| FavoriteItem::from_raw(info))
+        .collect()
+}
+
+/// お気に入りチャンネルの一覧
+/// PluginApi::favorites で取得します
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+pub struct Favorites {
+    /// 最上位の項目
+    pub items: Vec<FavoriteItem>,
+}
+
+impl Favorites {
+    /// FavoriteList を変換します
+    ///
+    /// # Safety
+    /// `list` は MESSAGE_GETFAVORITELIST で取得したもので、まだ解放されていない必要があります
+    pub unsafe fn from_raw(list: &FavoriteList) -> Self {
+        Self {
+            items: items_from_raw(list.item_list, list.item_count),
+        }
+    }
+
+    /// フォルダの中も含めたすべてのチャンネルを列挙します
+    pub fn channels(&self) -> Vec<&FavoriteChannel> {
+        fn collect<'a>(items: &'a [FavoriteItem], channels: &mut Vec<&'a FavoriteChannel>) {
+            for item in items {
+                match item {
+                    FavoriteItem::Folder(folder) => collect(&folder.items, channels),
+                    FavoriteItem::Channel(channel) => channels.push(channel),
+                }
+            }
+        }
+
+        let mut channels = Vec::new();
+        collect(&self.items, &mut channels);
+        channels
+    }
+
+    /// JSON に変換します
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// JSON から読み込みます
+    #[cfg(feature = "json")]
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    /// JSON ファイルに書き出します
+    #[cfg(feature = "json")]
+    pub fn export_json<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_json()?)
+    }
+
+    /// JSON ファイルから読み込みます
+    #[cfg(feature = "json")]
+    pub fn import_json<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::from_json(&fs::read_to_string(path)?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::win32::{IntoWideString, WideString};
+    use super::*;
+
+    fn channel_info(tuner: &WideString, service_id: u16, flags: BitFlags<FavoriteChannelFlag>) -> FavoriteItemData {
+        FavoriteItemData {
+            channel: FavoriteChannelInfo {
+                flags,
+                space: 0,
+                channel: 3,
+                channel_no: 1,
+                network_id: 32736,
+                transport_stream_id: 32736,
+                service_id,
+                reserved: 0,
+                tuner: tuner.to_wide_string_ptr(),
+            },
+        }
+    }
+
+    fn sample() -> Favorites {
+        let names: Vec<WideString> = ["NHK総合", "地上波", "NHK Eテレ"].iter().map(|name| name.into_wide_string()).collect();
+        let tuner = "BonDriver_PT3-T.dll".into_wide_string();
+
+        let children = [FavoriteItemInfo {
+            kind: FavoriteItemKind::Channel as u32,
+            flags: 0,
+            name: names[2].to_wide_string_ptr(),
+            data: channel_info(&tuner, 1032, BitFlags::empty()),
+        }];
+        let mut items = [
+            FavoriteItemInfo {
+                kind: FavoriteItemKind::Channel as u32,
+                flags: 0,
+                name: names[0].to_wide_string_ptr(),
+                data: channel_info(&tuner, 1024, FavoriteChannelFlag::ForceTunerChange.into()),
+            },
+            FavoriteItemInfo {
+                kind: FavoriteItemKind::Folder as u32,
+                flags: 0,
+                name: names[1].to_wide_string_ptr(),
+                data: FavoriteItemData {
+                    folder: FavoriteFolderInfo {
+                        flags: 0,
+                        item_count: children.len() as u32,
+                        item_list: children.as_ptr(),
+                    },
+                },
+            },
+            // 未知の種類の項目は読み飛ばされる
+            FavoriteItemInfo {
+                kind: 0x100,
+                flags: 0,
+                name: names[0].to_wide_string_ptr(),
+                data: channel_info(&tuner, 0, BitFlags::empty()),
+            },
+        ];
+        let list = FavoriteList {
+            item_count: items.len() as u32,
+            item_list: items.as_mut_ptr(),
+            ..FavoriteList::default()
+        };
+
+        unsafe { Favorites::from_raw(&list) }
+    }
+
+    #[test]
+    fn convert_favorite_list() {
+        let favorites = sample();
+
+        assert_eq!(favorites.items.iter().map(FavoriteItem::name).collect::<Vec<_>>(), vec!["NHK総合", "地上波"]);
+        let channels = favorites.channels();
+        assert_eq!(channels.len(), 2);
+        assert_eq!(channels[0].tuner, "BonDriver_PT3-T.dll");
+        assert!(channels[0].force_tuner_change);
+        assert_eq!((channels[1].name.as_str(), channels[1].service_id), ("NHK Eテレ", 1032));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_round_trip() {
+        let favorites = sample();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("favorites.json");
+
+        favorites.export_json(&path).unwrap();
+        assert!(fs::read_to_string(&path).unwrap().contains(r#""type": "folder""#));
+        assert_eq!(Favorites::import_json(&path).unwrap(), favorites);
+        assert!(Favorites::from_json(r#"{"items": [{"type": "unknown"}]}"#).is_err());
+    }
+}
@@ -0,0 +1,170 @@
+use std::marker::PhantomData;
+use std::mem::size_of;
+use windows::Win32::Graphics::Gdi::LOGFONTW;
+use crate::win32::{FixedWideString, IntoRustString, WStr};
+use crate::WideStringPtr;
+
+/// OSD のフォント
+pub const FONT_NAME_OSD: &str = "OSDFont";
+/// パネルのフォント
+pub const FONT_NAME_PANEL: &str = "PanelFont";
+/// 番組表のフォント
+pub const FONT_NAME_PROGRAM_GUIDE: &str = "ProgramGuideFont";
+/// ステータスバーのフォント
+pub const FONT_NAME_STATUS_BAR: &str = "StatusBarFont";
+
+/// フォントの取得の情報
+#[repr(C)]
+pub struct GetFontInfo<'a> {
+    /// 構造体のサイズ
+    pub size: u32,
+    /// 各種フラグ(現在は常に0)
+    pub flags: u32,
+    /// フォント名
+    pub name: WideStringPtr,
+    /// 取得されたフォント
+    pub log_font: LOGFONTW,
+    /// DPI の指定 (0 でメインウィンドウと同じ)
+    pub dpi: i32,
+    _marker: PhantomData<WStr<'a>>,
+}
+
+impl<'a> GetFontInfo<'a> {
+    /// フォント名と DPI を指定して生成します
+    pub fn new(name: WStr<'a>, dpi: i32) -> Self {
+        Self {
+            size: size_of::<GetFontInfo>() as u32,
+            flags: 0,
+            name: name.as_ptr(),
+            log_font: LOGFONTW::default(),
+            dpi,
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// フォントの情報
+/// LOGFONTW と相互に変換できます
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
+pub struct Font {
+    /// 高さ (負の値の場合は文字の高さ)
+    pub height: i32,
+    /// 平均文字幅
+    pub width: i32,
+    /// 文字列の角度 (1/10度単位)
+    pub escapement: i32,
+    /// 文字の角度 (1/10度単位)
+    pub orientation: i32,
+    /// 太さ (400 が標準、700 が太字)
+    pub weight: i32,
+    /// 斜体
+    pub italic: bool,
+    /// 下線
+    pub underline: bool,
+    /// 取り消し線
+    pub strike_out: bool,
+    /// 文字セット
+    pub char_set: u8,
+    /// 出力精度
+    pub out_precision: u8,
+    /// クリッピング精度
+    pub clip_precision: u8,
+    /// 品質
+    pub quality: u8,
+    /// ピッチとファミリ
+    pub pitch_and_family: u8,
+    /// フォント名
+    pub face_name: String,
+}
+
+impl Font {
+    /// LOGFONTW に変換します
+    /// フォント名が 31 文字を超える場合は文字の境界で切り詰められます
+    pub fn to_log_font(&self) -> LOGFONTW {
+        let face_name = FixedWideString::<32>::new(&self.face_name);
+
+        LOGFONTW {
+            lfHeight: self.height,
+            lfWidth: self.width,
+            lfEscapement: self.escapement,
+            lfOrientation: self.orientation,
+            lfWeight: self.weight,
+            lfItalic: self.italic as u8,
+            lfUnderline: self.underline as u8,
+            lfStrikeOut: self.strike_out as u8,
+            lfCharSet: self.char_set,
+            lfOutPrecision: self.out_precision,
+            lfClipPrecision: self.clip_precision,
+            lfQuality: self.quality,
+            lfPitchAndFamily: self.pitch_and_family,
+            lfFaceName: face_name.0,
+        }
+    }
+}
+
+impl From<&LOGFONTW> for Font {
+    fn from(log_font: &LOGFONTW) -> Self {
+        Self {
+            height: log_font.lfHeight,
+            width: log_font.lfWidth,
+            escapement: log_font.lfEscapement,
+            orientation: log_font.lfOrientation,
+            weight: log_font.lfWeight,
+            italic: log_font.lfItalic != 0,
+            underline: log_font.lfUnderline != 0,
+            strike_out: log_font.lfStrikeOut != 0,
+            char_set: log_font.lfCharSet,
+            out_precision: log_font.lfOutPrecision,
+            clip_precision: log_font.lfClipPrecision,
+            quality: log_font.lfQuality,
+            pitch_and_family: log_font.lfPitchAndFamily,
+            face_name: FixedWideString(log_font.lfFaceName).into_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Win32::Foundation::LRESULT;
+    use crate::message::Message;
+    use crate::mock::MockHost;
+    use crate::win32::UnsafeIntoRustString;
+    use super::*;
+
+    #[test]
+    fn get_font() {
+        let host = MockHost::new();
+        host.on(Message::GetFont, |info, _| {
+            let info = unsafe { &mut *(info.0 as *mut GetFontInfo) };
+            assert_eq!(info.size, size_of::<GetFontInfo>() as u32);
+            if info.name.read_string().as_deref() != Some(FONT_NAME_OSD) {
+                return LRESULT(0);
+            }
+            info.log_font = Font {
+                height: -12 * info.dpi / 72,
+                width: 0,
+                escapement: 0,
+                orientation: 0,
+                weight: 700,
+                italic: false,
+                underline: false,
+                strike_out: false,
+                char_set: 1,
+                out_precision: 0,
+                clip_precision: 0,
+                quality: 5,
+                pitch_and_family: 0,
+                face_name: "メイリオ".into(),
+            }.to_log_font();
+            LRESULT(1)
+        });
+        let api = host.api();
+
+        let font = api.font(FONT_NAME_OSD, 144).unwrap();
+        assert_eq!((font.height, font.weight, font.face_name.as_str()), (-24, 700, "メイリオ"));
+        assert_eq!(Font::from(&font.to_log_font()), font);
+        assert!(api.font(FONT_NAME_PANEL, 96).is_none());
+        assert_eq!(host.sent(Message::GetFont).len(), 2);
+    }
+}
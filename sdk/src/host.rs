@@ -1,7 +1,9 @@
+use std::mem::size_of;
 use crate::{Version, WideStringPtr};
+use crate::win32::UnsafeIntoRustString;
 
 /// ホストプログラムの情報
-#[repr(C)]
+#[repr(C, packed)]
 pub struct HostInfo {
     /// 構造体のサイズ
     pub size: u32,
@@ -13,3 +15,90 @@ pub struct HostInfo {
     /// 対応しているプラグインのバージョン
     pub supported_plugin_version: u32,
 }
+
+impl Default for HostInfo {
+    fn default() -> Self {
+        Self {
+            size: size_of::<Self>() as u32,
+            app_name: Default::default(),
+            version: Default::default(),
+            version_text: Default::default(),
+            supported_plugin_version: 0,
+        }
+    }
+}
+
+/// ホストプログラムの情報
+/// HostInfo の文字列をコピーしたものです
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
+pub struct GetHostInfo {
+    /// プログラム名 ("TVTest"、"TVH264" など)
+    pub app_name: String,
+    pub version: Version,
+    /// バージョン文字列 ("1.2.0" など)
+    pub version_text: String,
+    /// 対応しているプラグインのバージョン
+    pub supported_plugin_version: Version,
+}
+
+impl GetHostInfo {
+    /// 本家の TVTest かどうか
+    /// フォークなどでは app_name が異なります
+    pub fn is_tvtest(&self) -> bool {
+        self.app_name == "TVTest"
+    }
+
+    /// バージョン文字列を解析します
+    /// 解析できない場合は version を返します
+    pub fn parse_version_text(&self) -> Version {
+        self.version_text.parse().unwrap_or(self.version)
+    }
+
+    /// 指定されたプラグインのバージョンに対応しているかどうか
+    pub fn supports_plugin_version(&self, version: Version) -> bool {
+        self.supported_plugin_version >= version
+    }
+}
+
+impl From<&HostInfo> for GetHostInfo {
+    fn from(info: &HostInfo) -> Self {
+        Self {
+            app_name: info.app_name.read_string().unwrap_or_default(),
+            version: info.version,
+            version_text: info.version_text.read_string().unwrap_or_default(),
+            supported_plugin_version: info.supported_plugin_version.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Win32::Foundation::LRESULT;
+    use crate::message::Message;
+    use crate::mock::MockHost;
+    use crate::win32::IntoWideString;
+    use super::*;
+
+    #[test]
+    fn get_host_info() {
+        let app_name = "TVTest".into_wide_string();
+        let version_text = "0.10.0".into_wide_string();
+        let (app_name, version_text) = (app_name.to_wide_string_ptr(), version_text.to_wide_string_ptr());
+        let host = MockHost::new();
+        host.on(Message::GetHostInfo, move |info, _| {
+            let info = unsafe { &mut *(info.0 as *mut HostInfo) };
+            info.app_name = app_name;
+            info.version = Version::new(0, 10, 0);
+            info.version_text = version_text;
+            info.supported_plugin_version = Version::new(0, 0, 14).into();
+            LRESULT(1)
+        });
+        let api = host.api();
+
+        let info = api.host_info().unwrap();
+        assert!(info.is_tvtest());
+        assert_eq!(info.parse_version_text(), Version::new(0, 10, 0));
+        assert!(info.supports_plugin_version(Version::new(0, 0, 14)));
+    }
+}
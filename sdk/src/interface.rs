@@ -0,0 +1,122 @@
+// TVTestInterface.h で宣言されている COM インターフェース
+// 通常は ts_processor::TsProcessor を実装して利用します。
+
+use std::ffi::c_void;
+use windows::core::{GUID, HRESULT};
+use windows::Win32::Foundation::BOOL;
+use crate::WideStringPtr;
+
+/// IUnknown のインターフェースID
+pub const IID_IUNKNOWN: GUID = GUID::from_u128(0x00000000_0000_0000_C000_000000000046);
+/// IStreamingClient のインターフェースID
+pub const IID_ISTREAMINGCLIENT: GUID = GUID::from_u128(0xD513D10B_9438_4613_9758_2C17C434BE36);
+/// ITSPacket のインターフェースID
+pub const IID_ITSPACKET: GUID = GUID::from_u128(0x558F0FBF_992E_490F_8BAE_6CB709801B1D);
+/// ITSOutput のインターフェースID
+pub const IID_ITSOUTPUT: GUID = GUID::from_u128(0xF34436F9_35CD_4883_B4B4_E6F1CB33BE51);
+/// ITSProcessor のインターフェースID
+pub const IID_ITSPROCESSOR: GUID = GUID::from_u128(0x207D79AE_193B_4CD6_8228_456F032820F6);
+
+/// ログの種類
+#[repr(u32)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
+pub enum LogType {
+    Verbose,
+    Info,
+    Warning,
+    Error,
+}
+
+/// 通知の種類
+#[repr(u32)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
+pub enum NotifyType {
+    Info,
+    Warning,
+    Error,
+}
+
+/// エラーの情報
+#[repr(C, packed)]
+pub struct ErrorInfo {
+    pub hr: HRESULT,
+    pub text: WideStringPtr,
+    pub advise: WideStringPtr,
+    pub system_message: WideStringPtr,
+}
+
+/// IUnknown のメソッド
+#[repr(C)]
+pub struct IUnknownVtbl {
+    pub query_interface: unsafe extern "system" fn(this: *mut c_void, iid: *const GUID, object: *mut *mut c_void) -> HRESULT,
+    pub add_ref: unsafe extern "system" fn(this: *mut c_void) -> u32,
+    pub release: unsafe extern "system" fn(this: *mut c_void) -> u32,
+}
+
+/// ストリーミングのクライアント
+#[repr(C)]
+pub struct IStreamingClient {
+    pub vtable: *const IStreamingClientVtbl,
+}
+
+#[repr(C)]
+pub struct IStreamingClientVtbl {
+    pub unknown: IUnknownVtbl,
+    pub on_error: unsafe extern "system" fn(this: *mut IStreamingClient, info: *const ErrorInfo) -> HRESULT,
+    pub out_log: unsafe extern "system" fn(this: *mut IStreamingClient, kind: LogType, message: WideStringPtr) -> HRESULT,
+    pub notify: unsafe extern "system" fn(this: *mut IStreamingClient, kind: NotifyType, message: WideStringPtr) -> HRESULT,
+}
+
+/// TS のパケット
+#[repr(C)]
+pub struct ITSPacket {
+    pub vtable: *const ITSPacketVtbl,
+}
+
+#[repr(C)]
+pub struct ITSPacketVtbl {
+    pub unknown: IUnknownVtbl,
+    pub get_data: unsafe extern "system" fn(this: *mut ITSPacket, data: *mut *mut u8) -> HRESULT,
+    pub get_size: unsafe extern "system" fn(this: *mut ITSPacket, size: *mut u32) -> HRESULT,
+    pub set_modified: unsafe extern "system" fn(this: *mut ITSPacket, modified: BOOL) -> HRESULT,
+    /// 変更されている場合は S_OK、変更されていない場合は S_FALSE を返します
+    pub get_modified: unsafe extern "system" fn(this: *mut ITSPacket) -> HRESULT,
+}
+
+/// TS の出力先
+#[repr(C)]
+pub struct ITSOutput {
+    pub vtable: *const ITSOutputVtbl,
+}
+
+#[repr(C)]
+pub struct ITSOutputVtbl {
+    pub unknown: IUnknownVtbl,
+    pub output_packet: unsafe extern "system" fn(this: *mut ITSOutput, packet: *mut ITSPacket) -> HRESULT,
+}
+
+/// TS プロセッサ
+#[repr(C)]
+pub struct ITSProcessor {
+    pub vtable: *const ITSProcessorVtbl,
+}
+
+#[repr(C)]
+pub struct ITSProcessorVtbl {
+    pub unknown: IUnknownVtbl,
+    pub get_guid: unsafe extern "system" fn(this: *mut ITSProcessor, guid: *mut GUID) -> HRESULT,
+    /// 名前を SysAllocString で確保した BSTR で返します
+    pub get_name: unsafe extern "system" fn(this: *mut ITSProcessor, name: *mut *const u16) -> HRESULT,
+    pub initialize: unsafe extern "system" fn(this: *mut ITSProcessor, client: *mut IStreamingClient) -> HRESULT,
+    pub finalize: unsafe extern "system" fn(this: *mut ITSProcessor) -> HRESULT,
+    pub start_streaming: unsafe extern "system" fn(this: *mut ITSProcessor, output: *mut ITSOutput) -> HRESULT,
+    pub stop_streaming: unsafe extern "system" fn(this: *mut ITSProcessor) -> HRESULT,
+    pub input_packet: unsafe extern "system" fn(this: *mut ITSProcessor, packet: *mut ITSPacket) -> HRESULT,
+    pub reset: unsafe extern "system" fn(this: *mut ITSProcessor) -> HRESULT,
+    pub set_enable_processing: unsafe extern "system" fn(this: *mut ITSProcessor, enable: BOOL) -> HRESULT,
+    pub get_enable_processing: unsafe extern "system" fn(this: *mut ITSProcessor, enable: *mut BOOL) -> HRESULT,
+    pub set_active_service_id: unsafe extern "system" fn(this: *mut ITSProcessor, service_id: u16) -> HRESULT,
+    pub get_active_service_id: unsafe extern "system" fn(this: *mut ITSProcessor, service_id: *mut u16) -> HRESULT,
+}
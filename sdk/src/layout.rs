@@ -0,0 +1,44 @@
+//! TVTestPlugin.h の構造体のレイアウトの確認
+
+use std::mem::size_of;
+use crate::channel::ChannelSelectInfo;
+use crate::controller::ControllerInfo;
+use crate::host::HostInfo;
+use crate::panel::PanelItemGetInfo;
+use crate::program_guide::ProgramGuideProgramDrawBackgroundInfo;
+use crate::record::{RecordStatusInfo, StartRecordInfo};
+use crate::setting::SettingInfo;
+use crate::status_item::{StatusItemDrawInfo, StatusItemGetInfo, StatusItemMouseEventInfo};
+use crate::style::StyleValueInfo;
+use crate::theme::{ThemeDrawBackgroundInfo, ThemeDrawIconInfo, ThemeDrawTextInfo};
+use crate::ts_processor::TsProcessorInfo;
+
+#[test]
+fn packed_struct_sizes() {
+    // TVTestPlugin.h の構造体は 1 バイト境界でパックされており、
+    // size がパックされたサイズと一致しなければ TVTest は失敗する
+    const PTR: usize = size_of::<usize>();
+    const RECT: usize = 16;
+    let sizes = [
+        ("ChannelSelectInfo", size_of::<ChannelSelectInfo>(), 4 * 4 + 2 * 3 + PTR),
+        ("ControllerInfo", size_of::<ControllerInfo>(), 4 * 5 + PTR * 7),
+        ("HostInfo", size_of::<HostInfo>(), 4 * 5 + PTR * 2),
+        ("PanelItemGetInfo", size_of::<PanelItemGetInfo>(), 4 * 5 + PTR * 2),
+        ("ProgramGuideProgramDrawBackgroundInfo", size_of::<ProgramGuideProgramDrawBackgroundInfo>(), RECT * 3 + 4 + PTR),
+        ("RecordStatusInfo", size_of::<RecordStatusInfo>(), 4 * 6 + 8 * 2 + PTR),
+        ("SettingInfo", size_of::<SettingInfo>(), 4 * 2 + PTR * 2),
+        ("StartRecordInfo", size_of::<StartRecordInfo>(), 4 * 7 + 8 * 2 + PTR),
+        ("StatusItemDrawInfo", size_of::<StatusItemDrawInfo>(), 4 * 2 + 2 * 2 + RECT * 2 + PTR * 2),
+        ("StatusItemGetInfo", size_of::<StatusItemGetInfo>(), 4 * 5 + RECT * 2 + PTR),
+        ("StatusItemMouseEventInfo", size_of::<StatusItemMouseEventInfo>(), 4 * 3 + 8 + RECT * 2 + PTR),
+        ("StyleValueInfo", size_of::<StyleValueInfo>(), 4 * 5 + PTR),
+        ("ThemeDrawBackgroundInfo", size_of::<ThemeDrawBackgroundInfo>(), 4 * 3 + RECT + PTR * 2),
+        ("ThemeDrawIconInfo", size_of::<ThemeDrawIconInfo>(), 4 * 4 + RECT * 2 + PTR * 3),
+        ("ThemeDrawTextInfo", size_of::<ThemeDrawTextInfo>(), 4 * 4 + RECT + PTR * 3),
+        ("TsProcessorInfo", size_of::<TsProcessorInfo>(), 4 * 3 + PTR),
+    ];
+
+    for (name, actual, expected) in sizes {
+        assert_eq!(actual, expected, "{}", name);
+    }
+}
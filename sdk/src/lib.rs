@@ -1,64 +1,151 @@
+#[cfg(windows)]
 use std::ffi::c_void;
-use windows::Win32::Foundation::HWND;
+#[cfg(windows)]
+use windows::Win32::Foundation::{HWND, LPARAM};
+#[cfg(windows)]
 use crate::api::PluginApi;
+#[cfg(windows)]
 use crate::command::DrawCommandIconInfo;
+#[cfg(windows)]
+use crate::event::PluginEvent;
+#[cfg(windows)]
+use crate::export::dispatch_event;
+#[cfg(windows)]
 use crate::filter_graph::FilterGraphInfo;
-use crate::panel::PanelItemEventInfo;
+#[cfg(windows)]
+use crate::panel::{PanelItemCreateEventInfo, PanelItemEventInfo};
+#[cfg(windows)]
 use crate::plugin::PluginInfo;
+#[cfg(windows)]
 use crate::program_guide::{ProgramGuideCommandParam, ProgramGuideInitializeMenuInfo, ProgramGuideProgramDrawBackgroundInfo, ProgramGuideProgramInfo, ProgramGuideProgramInitializeMenuInfo};
+#[cfg(windows)]
 use crate::record::{RecordStatus, StartRecordInfo};
+#[cfg(windows)]
 use crate::status_item::{StatusItemDrawInfo, StatusItemEventInfo, StatusItemMouseEventInfo};
+#[cfg(windows)]
 use crate::stereo_mode::StereoMode;
+#[cfg(windows)]
 use crate::variable::GetVariableInfo;
+#[cfg(windows)]
 use crate::version::{DEFAULT_API_VERSION, Version};
+#[cfg(windows)]
 use crate::win32::{UnsafePtr, WideStringPtr};
 
+#[cfg(windows)]
 pub mod arib_string;
+#[cfg(windows)]
 pub mod channel;
+#[cfg(windows)]
 pub mod close;
+#[cfg(windows)]
 pub mod command;
+#[cfg(windows)]
 pub mod controller;
+#[cfg(windows)]
+pub mod dialog;
+#[cfg(windows)]
+pub mod dpi;
+#[cfg(windows)]
 pub mod epg;
+#[cfg(windows)]
 pub mod event;
+#[cfg(windows)]
+pub mod event_bus;
+#[cfg(windows)]
+pub mod favorite;
+#[cfg(windows)]
 pub mod filter_graph;
+#[cfg(windows)]
+pub mod font;
+#[cfg(windows)]
 pub mod host;
+#[cfg(windows)]
 pub mod log;
+#[cfg(windows)]
+pub mod logo;
+#[cfg(windows)]
+pub mod memory;
+#[cfg(windows)]
 pub mod message;
+#[cfg(windows)]
 pub mod pan_scan;
+#[cfg(windows)]
 pub mod panel;
+#[cfg(windows)]
 pub mod program;
+#[cfg(windows)]
 pub mod program_guide;
+#[cfg(windows)]
 pub mod record;
+#[cfg(windows)]
 pub mod reset;
+#[cfg(all(windows, feature = "tokio"))]
+pub mod runtime;
+#[cfg(windows)]
 pub mod service;
+#[cfg(windows)]
 pub mod setting;
+#[cfg(windows)]
 pub mod silent_mode;
+#[cfg(windows)]
+pub mod state;
+#[cfg(windows)]
 pub mod status;
+#[cfg(windows)]
 pub mod status_item;
+#[cfg(windows)]
 pub mod stereo_mode;
+#[cfg(windows)]
 pub mod stream;
+#[cfg(windows)]
 pub mod style;
+#[cfg(windows)]
 pub mod theme;
+#[cfg(windows)]
+pub mod time;
+#[cfg(windows)]
+pub mod ts_processor;
+pub mod ts_packet;
+#[cfg(windows)]
 pub mod tuning_space;
+#[cfg(windows)]
 pub mod variable;
 pub mod version;
+#[cfg(windows)]
+pub mod window_message;
 
+#[cfg(windows)]
 pub mod api;
+#[cfg(windows)]
+pub mod api_handle;
+#[cfg(feature = "config")]
+pub mod config;
+#[cfg(windows)]
 pub mod plugin;
+#[cfg(windows)]
 pub mod interface;
+#[cfg(windows)]
 #[macro_use]
 pub mod export;
+#[cfg(windows)]
 pub mod win32;
+#[cfg(all(test, windows))]
+mod layout;
+#[cfg(all(test, windows))]
+mod mock;
 
-#[macro_use]
+#[cfg_attr(windows, macro_use)]
 pub extern crate enumflags2;
-#[macro_use]
+#[cfg_attr(windows, macro_use)]
 pub extern crate num_enum;
+#[cfg(windows)]
 pub extern crate windows;
 
+#[cfg(windows)]
 pub type ClientData = UnsafePtr<c_void>;
 
 /// すべての TVTest プラグイン構造体が実装すべき trait
+#[cfg(windows)]
 pub trait TVTestPlugin: TVTestEventHandler {
     fn new(api: PluginApi) -> Self;
     fn get_api_version() -> Version { DEFAULT_API_VERSION }
@@ -66,8 +153,17 @@ pub trait TVTestPlugin: TVTestEventHandler {
 
     fn initialize(&self) -> bool { true }
     fn finalize(&self) -> bool { true }
+
+    /// 非同期ランタイムが開始された
+    /// export_plugin! が initialize の前に呼び出します。ランタイムは finalize の後に破棄されるため、
+    /// 使用するハンドルや AsyncApi、イベントのストリームはここで取得してください
+    /// ランタイムはウィンドウメッセージコールバックを使用するため、set_window_message_callback とは併用できません
+    #[cfg(feature = "tokio")]
+    #[allow(unused_variables)]
+    fn on_runtime_start(&self, runtime: &runtime::PluginRuntime) {}
 }
 
+#[cfg(windows)]
 #[allow(unused_variables)]
 pub trait TVTestEventHandler {
     /// 有効状態が変化した
@@ -163,10 +259,21 @@ pub trait TVTestEventHandler {
     fn on_status_item_mouse_event(&self, info: &StatusItemMouseEventInfo) -> bool { false }
     /// パネル項目の通知
     fn on_panel_item_notify(&self, info: &PanelItemEventInfo) -> bool { false }
+    /// パネル項目の作成
+    /// 作成したウィンドウのハンドルを info.hwnd_item に設定します
+    fn on_panel_item_create(&self, info: &mut PanelItemCreateEventInfo) -> bool { false }
     /// お気に入りチャンネルが変更された
     fn on_favorites_changed(&self) {}
     /// ワンセグモードが変わった
     fn on_one_seg_mode_changed(&self, mode: bool) {}
     /// 変数を取得
-    fn on_get_variable(&self, info: &GetVariableInfo) -> bool { false }
+    /// 値は GetVariableInfo::set_value または Variables::get_variable で設定します
+    fn on_get_variable(&self, info: &mut GetVariableInfo) -> bool { false }
+    /// 解釈済みのイベント
+    /// 既定では各メソッドに振り分けます。EventBus に配信する場合などにオーバーライドします
+    fn on_event(&self, event: PluginEvent) -> isize where Self: Sized { dispatch_event(event, self) }
+    /// 未知のイベント
+    /// 新しい TVTest から送られた未対応のイベントや、パラメータが解釈できなかったイベントで呼ばれます
+    /// 戻り値はそのまま TVTest に返されます
+    fn on_unknown_event(&self, code: u32, param1: LPARAM, param2: LPARAM) -> isize { 0 }
 }
@@ -0,0 +1,314 @@
+use std::ffi::c_void;
+use std::mem::{size_of, MaybeUninit};
+use std::slice;
+use windows::Win32::Graphics::Gdi::{DeleteObject, GetObjectW, DIBSECTION, HBITMAP, HGDIOBJ};
+
+/// ロゴの種類
+/// いずれのロゴも 16:9 で表示すると本来の比率になります
+#[repr(u8)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(test, derive(Debug))]
+pub enum LogoType {
+    /// 48x24
+    Type0,
+    /// 36x24
+    Type1,
+    /// 48x27
+    Type2,
+    /// 72x36
+    Type3,
+    /// 54x36
+    Type4,
+    /// 64x36
+    Type5,
+}
+
+impl LogoType {
+    /// すべてのロゴの種類
+    pub const ALL: [LogoType; 6] = [
+        LogoType::Type0,
+        LogoType::Type1,
+        LogoType::Type2,
+        LogoType::Type3,
+        LogoType::Type4,
+        LogoType::Type5,
+    ];
+
+    /// ロゴの画像の大きさ (幅, 高さ) を返します
+    pub fn size(&self) -> (u32, u32) {
+        match self {
+            LogoType::Type0 => (48, 24),
+            LogoType::Type1 => (36, 24),
+            LogoType::Type2 => (48, 27),
+            LogoType::Type3 => (72, 36),
+            LogoType::Type4 => (54, 36),
+            LogoType::Type5 => (64, 36),
+        }
+    }
+
+    /// MESSAGE_GETAVAILABLELOGOTYPE の戻り値から利用可能なロゴの種類を列挙します
+    /// 下位から1ビットごとに Type0 から Type5 までを表します
+    pub fn from_available_bits(bits: u32) -> Vec<LogoType> {
+        LogoType::ALL.iter()
+            .copied()
+            .filter(|kind| bits & (1 << *kind as u32) != 0)
+            .collect()
+    }
+}
+
+/// ロゴの画像
+/// TVTest から取得したビットマップ (DIB セクション) のハンドルを保持し、Drop 時に破棄します
+pub struct Logo(HBITMAP);
+
+impl Logo {
+    /// ビットマップのハンドルから生成します
+    /// 以後ハンドルの所有権は Logo が持ちます
+    pub fn from_raw(hbitmap: HBITMAP) -> Option<Self> {
+        if hbitmap.0 != 0 {
+            Logo(hbitmap).into()
+        } else {
+            None
+        }
+    }
+
+    /// ビットマップのハンドルを返します
+    pub fn as_raw(&self) -> HBITMAP {
+        self.0
+    }
+
+    /// ビットマップのハンドルの所有権を手放します
+    /// 不要になった時に DeleteObject() で破棄する必要があります
+    pub fn into_raw(self) -> HBITMAP {
+        let hbitmap = self.0;
+        std::mem::forget(self);
+
+        hbitmap
+    }
+
+    /// DIB セクションのピクセルを RGBA に変換します
+    pub fn to_bitmap(&self) -> Option<LogoBitmap> {
+        let mut section = MaybeUninit::<DIBSECTION>::zeroed();
+        let size = unsafe {
+            GetObjectW(HGDIOBJ(self.0.0), size_of::<DIBSECTION>() as i32, section.as_mut_ptr() as *mut c_void)
+        };
+        if size != size_of::<DIBSECTION>() as i32 {
+            return None;
+        }
+        let section = unsafe { section.assume_init() };
+
+        let bitmap = &section.dsBm;
+        if bitmap.bmBits.is_null() || bitmap.bmWidth <= 0 || bitmap.bmHeight <= 0 || bitmap.bmWidthBytes <= 0 {
+            return None;
+        }
+        let data = unsafe {
+            slice::from_raw_parts(bitmap.bmBits as *const u8, bitmap.bmWidthBytes as usize * bitmap.bmHeight as usize)
+        };
+
+        LogoBitmap::from_dib(
+            bitmap.bmWidth as u32,
+            bitmap.bmHeight as u32,
+            bitmap.bmBitsPixel,
+            bitmap.bmWidthBytes as usize,
+            // biHeight が正の値の場合はボトムアップ
+            section.dsBmih.biHeight > 0,
+            data,
+        )
+    }
+
+    /// PNG 形式に変換します
+    pub fn to_png(&self) -> Option<Vec<u8>> {
+        self.to_bitmap().map(|bitmap| bitmap.to_png())
+    }
+}
+
+impl Drop for Logo {
+    fn drop(&mut self) {
+        unsafe {
+            DeleteObject(HGDIOBJ(self.0.0));
+        }
+    }
+}
+
+/// RGBA のロゴの画像
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
+pub struct LogoBitmap {
+    /// 幅 (ピクセル単位)
+    pub width: u32,
+    /// 高さ (ピクセル単位)
+    pub height: u32,
+    /// 上の行から並んだ RGBA のピクセル
+    pub pixels: Vec<u8>,
+}
+
+impl LogoBitmap {
+    /// DIB のピクセルデータから変換します
+    /// 24 ビット (BGR) と 32 ビット (BGRA) に対応しています
+    /// 32 ビットでアルファ値がすべて 0 の場合は不透明として扱います
+    pub fn from_dib(width: u32, height: u32, bits_per_pixel: u16, stride: usize, bottom_up: bool, data: &[u8]) -> Option<Self> {
+        let bytes_per_pixel = match bits_per_pixel {
+            24 => 3,
+            32 => 4,
+            _ => return None,
+        };
+        if stride < width as usize * bytes_per_pixel || data.len() < stride * height as usize {
+            return None;
+        }
+
+        let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+        for y in 0..height as usize {
+            let row = if bottom_up { height as usize - 1 - y } else { y };
+            let row = &data[row * stride..][..width as usize * bytes_per_pixel];
+
+            for pixel in row.chunks_exact(bytes_per_pixel) {
+                let alpha = if bytes_per_pixel == 4 { pixel[3] } else { 0xFF };
+                pixels.extend_from_slice(&[pixel[2], pixel[1], pixel[0], alpha]);
+            }
+        }
+
+        if bytes_per_pixel == 4 && pixels.chunks_exact(4).all(|pixel| pixel[3] == 0) {
+            pixels.chunks_exact_mut(4).for_each(|pixel| pixel[3] = 0xFF);
+        }
+
+        LogoBitmap {
+            width,
+            height,
+            pixels,
+        }.into()
+    }
+
+    /// PNG 形式 (8 ビット RGBA、無圧縮) に変換します
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&self.width.to_be_bytes());
+        header.extend_from_slice(&self.height.to_be_bytes());
+        // ビット深度 8, カラータイプ 6 (RGBA), 圧縮方式, フィルタ方式, インターレースなし
+        header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+        // 各行の先頭にフィルタタイプ 0 (None) を付加する
+        let row_length = self.width as usize * 4;
+        let mut scanlines = Vec::with_capacity((row_length + 1) * self.height as usize);
+        for row in self.pixels.chunks_exact(row_length.max(1)).take(self.height as usize) {
+            scanlines.push(0);
+            scanlines.extend_from_slice(row);
+        }
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        write_png_chunk(&mut png, b"IHDR", &header);
+        write_png_chunk(&mut png, b"IDAT", &zlib_store(&scanlines));
+        write_png_chunk(&mut png, b"IEND", &[]);
+
+        png
+    }
+}
+
+fn write_png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    png.extend_from_slice(&crc32(&[kind, data]).to_be_bytes());
+}
+
+/// 無圧縮の deflate ブロックで zlib ストリームを作成します
+fn zlib_store(data: &[u8]) -> Vec<u8> {
+    const MAX_BLOCK: usize = 0xFFFF;
+
+    let mut stream = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        stream.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let is_final = blocks.peek().is_none();
+        let length = block.len() as u16;
+        stream.push(is_final as u8);
+        stream.extend_from_slice(&length.to_le_bytes());
+        stream.extend_from_slice(&(!length).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+
+    stream
+}
+
+fn crc32(data: &[&[u8]]) -> u32 {
+    let mut table = [0u32; 256];
+    for (n, entry) in table.iter_mut().enumerate() {
+        let mut c = n as u32;
+        for _ in 0..8 {
+            c = if c & 1 != 0 { 0xEDB88320 ^ (c >> 1) } else { c >> 1 };
+        }
+        *entry = c;
+    }
+
+    let mut crc = 0xFFFFFFFFu32;
+    for byte in data.iter().flat_map(|part| part.iter()) {
+        crc = table[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+
+    crc ^ 0xFFFFFFFF
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % MOD;
+        b = (b + a) % MOD;
+    }
+
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn available_logo_types() {
+        assert_eq!(LogoType::from_available_bits(0b100101), vec![LogoType::Type0, LogoType::Type2, LogoType::Type5]);
+        assert!(LogoType::from_available_bits(0).is_empty());
+    }
+
+    #[test]
+    fn convert_bottom_up_dib() {
+        // 2x2, 24 ビット, 4 バイト境界のため stride は 8
+        let data = [
+            // 下の行: 青, 白
+            0xFF, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0x00, 0x00,
+            // 上の行: 赤, 緑
+            0x00, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0x00, 0x00,
+        ];
+        let bitmap = LogoBitmap::from_dib(2, 2, 24, 8, true, &data).unwrap();
+
+        assert_eq!(bitmap.pixels, vec![
+            0xFF, 0x00, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF,
+            0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+        ]);
+        assert!(LogoBitmap::from_dib(2, 2, 16, 8, true, &data).is_none());
+        assert!(LogoBitmap::from_dib(3, 2, 24, 8, true, &data).is_none());
+    }
+
+    #[test]
+    fn encode_png() {
+        let bitmap = LogoBitmap {
+            width: 1,
+            height: 1,
+            pixels: vec![0xFF, 0x00, 0x00, 0xFF],
+        };
+        let png = bitmap.to_png();
+
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..24], &[0, 0, 0, 1, 0, 0, 0, 1]);
+        // IEND チャンクは常に同じ CRC になる
+        assert_eq!(&png[png.len() - 12..], &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]);
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(&[b"123456789"]), 0xCBF43926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+    }
+}
@@ -0,0 +1,284 @@
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::{align_of, size_of, ManuallyDrop};
+use std::ops::{Deref, DerefMut};
+use std::ptr::{self, NonNull};
+use std::slice;
+use crate::api::PluginApi;
+use crate::win32::WideStringPtr;
+
+// TVTest のメモリ確保関数で確保し、アラインメントを満たさない場合は解放して None を返す
+fn allocate<T>(api: &PluginApi, len: usize) -> Option<NonNull<T>> {
+    // 0 バイトの確保は解放の意味になるため、最低 1 バイト確保する
+    let size = size_of::<T>().checked_mul(len)?.max(1);
+    let ptr = api.memory_alloc(isize::try_from(size).ok()?)?;
+
+    if ptr.as_ptr().align_offset(align_of::<T>()) != 0 {
+        api.memory_free(ptr.into());
+        return None;
+    }
+
+    ptr.cast::<T>().into()
+}
+
+/// TVTest のメモリ確保関数で確保された値
+/// Drop 時に PluginApi::memory_free で解放されます
+pub struct HostBox<T> {
+    api: PluginApi,
+    ptr: NonNull<T>,
+    _marker: PhantomData<T>,
+}
+
+impl<T> HostBox<T> {
+    /// 値を TVTest のメモリに移動します
+    /// 確保に失敗した場合は None を返します
+    pub fn new(api: &PluginApi, value: T) -> Option<Self> {
+        let ptr = allocate::<T>(api, 1)?;
+        unsafe { ptr.as_ptr().write(value) };
+
+        Self {
+            api: api.clone(),
+            ptr,
+            _marker: PhantomData,
+        }.into()
+    }
+
+    /// TVTest のメモリ確保関数で確保された値の所有権を取得します
+    ///
+    /// # Safety
+    /// `ptr` は TVTest のメモリ確保関数で確保され、初期化された T を指している必要があります
+    pub unsafe fn from_raw(api: &PluginApi, ptr: NonNull<T>) -> Self {
+        Self {
+            api: api.clone(),
+            ptr,
+            _marker: PhantomData,
+        }
+    }
+
+    /// 所有権を手放してポインタを返します
+    /// TVTest に解放させる場合に使用します
+    pub fn into_raw(self) -> NonNull<T> {
+        ManuallyDrop::new(self).ptr
+    }
+
+    /// 生ポインタ
+    pub fn as_ptr(&self) -> *mut T {
+        self.ptr.as_ptr()
+    }
+}
+
+impl<T> Deref for HostBox<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T> DerefMut for HostBox<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T> Drop for HostBox<T> {
+    fn drop(&mut self) {
+        unsafe { ptr::drop_in_place(self.ptr.as_ptr()) };
+        self.api.memory_free(self.ptr.cast().into());
+    }
+}
+
+/// TVTest のメモリ確保関数で確保された配列
+/// Drop 時に PluginApi::memory_free で解放されます
+pub struct HostSlice<T> {
+    api: PluginApi,
+    ptr: NonNull<T>,
+    len: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Clone> HostSlice<T> {
+    /// スライスを TVTest のメモリに複製します
+    /// 確保に失敗した場合は None を返します
+    pub fn from_slice(api: &PluginApi, values: &[T]) -> Option<Self> {
+        let ptr = allocate::<T>(api, values.len())?;
+        let mut slice = Self {
+            api: api.clone(),
+            ptr,
+            len: 0,
+            _marker: PhantomData,
+        };
+
+        // clone がパニックしても初期化済みの要素だけを解放できるように一つずつ増やす
+        for value in values {
+            unsafe { slice.ptr.as_ptr().add(slice.len).write(value.clone()) };
+            slice.len += 1;
+        }
+
+        slice.into()
+    }
+}
+
+impl<T> HostSlice<T> {
+    /// TVTest のメモリ確保関数で確保された配列の所有権を取得します
+    ///
+    /// # Safety
+    /// `ptr` は TVTest のメモリ確保関数で確保され、初期化された T を `len` 個持っている必要があります
+    pub unsafe fn from_raw(api: &PluginApi, ptr: NonNull<T>, len: usize) -> Self {
+        Self {
+            api: api.clone(),
+            ptr,
+            len,
+            _marker: PhantomData,
+        }
+    }
+
+    /// 所有権を手放してポインタと要素数を返します
+    /// TVTest に解放させる場合に使用します
+    pub fn into_raw(self) -> (NonNull<T>, usize) {
+        let slice = ManuallyDrop::new(self);
+        (slice.ptr, slice.len)
+    }
+
+    /// 生ポインタ
+    pub fn as_ptr(&self) -> *mut T {
+        self.ptr.as_ptr()
+    }
+}
+
+impl<T> Deref for HostSlice<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T> DerefMut for HostSlice<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T> Drop for HostSlice<T> {
+    fn drop(&mut self) {
+        unsafe { ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.ptr.as_ptr(), self.len)) };
+        self.api.memory_free(self.ptr.cast().into());
+    }
+}
+
+/// TVTest のメモリ確保関数で確保された NULL 終端なワイド文字列
+/// 変数の値など、TVTest に解放させる文字列に使用します
+pub struct HostWideString {
+    // 終端の NULL 文字を含む
+    buffer: HostSlice<u16>,
+}
+
+impl HostWideString {
+    /// 文字列を TVTest のメモリに複製します
+    /// 確保に失敗した場合は None を返します
+    pub fn new(api: &PluginApi, string: &str) -> Option<Self> {
+        let wide: Vec<u16> = string.encode_utf16().chain(Some(0)).collect();
+
+        Self {
+            buffer: HostSlice::from_slice(api, &wide)?,
+        }.into()
+    }
+
+    /// ワイド文字列を TVTest のメモリに複製します
+    /// 途中に NULL 文字がある場合はそこで終端されます
+    pub fn from_wide(api: &PluginApi, wide: &[u16]) -> Option<Self> {
+        let len = wide.iter().position(|&c| c == 0).unwrap_or(wide.len());
+        let wide: Vec<u16> = wide[..len].iter().copied().chain(Some(0)).collect();
+
+        Self {
+            buffer: HostSlice::from_slice(api, &wide)?,
+        }.into()
+    }
+
+    /// TVTest のメモリ確保関数で確保された文字列の所有権を取得します
+    /// ぬるぽの場合は None を返します
+    ///
+    /// # Safety
+    /// `string` は TVTest のメモリ確保関数で確保された NULL 終端な文字列である必要があります
+    pub unsafe fn from_raw(api: &PluginApi, string: WideStringPtr) -> Option<Self> {
+        let len = string.get_length()? + 1;
+
+        Self {
+            buffer: HostSlice::from_raw(api, string.0?, len),
+        }.into()
+    }
+
+    /// 所有権を手放してポインタを返します
+    /// TVTest に解放させる場合に使用します
+    pub fn into_raw(self) -> WideStringPtr {
+        WideStringPtr(self.buffer.into_raw().0.into())
+    }
+
+    /// ポインタ
+    /// HostWideString が破棄されるまで有効です
+    pub fn as_ptr(&self) -> WideStringPtr {
+        WideStringPtr(NonNull::new(self.buffer.as_ptr()))
+    }
+
+    /// 終端の NULL 文字を含まないワイド文字列
+    pub fn as_wide(&self) -> &[u16] {
+        &self.buffer[..self.buffer.len() - 1]
+    }
+
+    /// 文字列の長さ (UTF-16 単位)
+    pub fn len(&self) -> usize {
+        self.buffer.len() - 1
+    }
+
+    /// 空文字列か
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl fmt::Display for HostWideString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&String::from_utf16_lossy(self.as_wide()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mock::MockHost;
+    use super::*;
+
+    #[test]
+    fn host_memory_is_freed() {
+        let host = MockHost::new();
+        host.track_allocations();
+        let api = host.api();
+
+        {
+            let mut value = HostBox::new(&api, 42u64).unwrap();
+            *value += 1;
+            assert_eq!(*value, 43);
+
+            let slice = HostSlice::from_slice(&api, &[String::from("a"), String::from("b")]).unwrap();
+            assert_eq!(slice.join(","), "a,b");
+
+            let string = HostWideString::new(&api, "番組名").unwrap();
+            assert_eq!((string.to_string().as_str(), string.len()), ("番組名", 3));
+            assert_eq!(host.allocations(), 3);
+        }
+        assert_eq!(host.allocations(), 0);
+
+        // TVTest に渡した文字列は TVTest 側で解放される
+        let raw = HostWideString::new(&api, "value").unwrap().into_raw();
+        assert_eq!(host.allocations(), 1);
+        let string = unsafe { HostWideString::from_raw(&api, raw) }.unwrap();
+        assert_eq!(string.to_string(), "value");
+        drop(string);
+        assert_eq!(host.allocations(), 0);
+
+        let empty = HostSlice::<u32>::from_slice(&api, &[]).unwrap();
+        assert!(empty.is_empty());
+        drop(empty);
+        assert_eq!(host.allocations(), 0);
+    }
+}
@@ -0,0 +1,117 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::rc::Rc;
+use std::sync::Arc;
+use windows::Win32::Foundation::{HINSTANCE, HWND, LPARAM, LRESULT};
+use crate::api::PluginApi;
+use crate::message::Message;
+use crate::plugin::PluginParam;
+
+type MessageHandler = Box<dyn FnMut(LPARAM, LPARAM) -> LRESULT>;
+
+/// テスト用の TVTest
+/// プラグインから送られたメッセージを記録し、登録されたハンドラで応答します
+/// ハンドラが登録されていないメッセージには 0 を返します
+pub(crate) struct MockHost {
+    state: Box<MockState>,
+    param: Arc<PluginParam>,
+}
+
+struct MockState {
+    handlers: RefCell<HashMap<u32, MessageHandler>>,
+    messages: RefCell<Vec<(u32, LPARAM, LPARAM)>>,
+    blocks: Rc<RefCell<HashMap<isize, Vec<u64>>>>,
+}
+
+impl MockHost {
+    pub fn new() -> Self {
+        let state = Box::new(MockState {
+            handlers: RefCell::new(HashMap::new()),
+            messages: RefCell::new(Vec::new()),
+            blocks: Rc::new(RefCell::new(HashMap::new())),
+        });
+        let internal_data = &*state as *const MockState as *mut c_void;
+        #[allow(clippy::arc_with_non_send_sync)]
+        let param = Arc::new(PluginParam::new(mock_callback, HWND(0), internal_data));
+
+        Self {
+            state,
+            param,
+        }
+    }
+
+    /// MockHost に接続された PluginApi を生成します
+    pub fn api(&self) -> PluginApi {
+        PluginApi {
+            dll: Arc::new(HINSTANCE(0)),
+            param: Arc::clone(&self.param),
+        }
+    }
+
+    /// メッセージのハンドラを登録します
+    pub fn on<F>(&self, message: Message, handler: F)
+        where F: FnMut(LPARAM, LPARAM) -> LRESULT + 'static
+    {
+        self.state.handlers.borrow_mut().insert(message as u32, Box::new(handler));
+    }
+
+    /// 送られたメッセージのパラメータを古い順に返します
+    pub fn sent(&self, message: Message) -> Vec<(LPARAM, LPARAM)> {
+        let message = message as u32;
+
+        self.state.messages.borrow()
+            .iter()
+            .filter(|(m, _, _)| *m == message)
+            .map(|(_, param1, param2)| (*param1, *param2))
+            .collect()
+    }
+
+    /// TVTest のメモリ確保関数 (Message::MemoryAlloc) の代わりを登録します
+    /// 確保中の領域の数は allocations で確認できます
+    pub fn track_allocations(&self) {
+        let blocks = Rc::clone(&self.state.blocks);
+        self.on(Message::MemoryAlloc, move |data, size| {
+            let mut blocks = blocks.borrow_mut();
+            let old = match data.0 {
+                0 => None,
+                data => Some(blocks.remove(&data).expect("freed memory not allocated by the host")),
+            };
+            if size.0 == 0 {
+                return LRESULT(0);
+            }
+
+            // 8 バイト境界に揃えるため u64 単位で確保し、再確保では元の内容を引き継ぐ
+            let mut block = vec![0u64; (size.0 as usize).div_ceil(8)];
+            if let Some(old) = old {
+                let len = old.len().min(block.len());
+                block[..len].copy_from_slice(&old[..len]);
+            }
+            let ptr = block.as_mut_ptr() as isize;
+            blocks.insert(ptr, block);
+            LRESULT(ptr)
+        });
+    }
+
+    /// track_allocations で確保され、解放されていない領域の数
+    pub fn allocations(&self) -> usize {
+        self.state.blocks.borrow().len()
+    }
+}
+
+unsafe extern "system" fn mock_callback(param: *const PluginParam, message: Message, param1: LPARAM, param2: LPARAM) -> LRESULT {
+    let state = &*((*param).internal_data() as *const MockState);
+    let message = message as u32;
+    state.messages.borrow_mut().push((message, param1, param2));
+
+    // ハンドラ内から PluginApi を呼び出せるように、呼び出し中は取り外しておく
+    let handler = state.handlers.borrow_mut().remove(&message);
+    match handler {
+        Some(mut handler) => {
+            let result = handler(param1, param2);
+            state.handlers.borrow_mut().entry(message).or_insert(handler);
+            result
+        },
+        None => LRESULT(0),
+    }
+}
@@ -1,3 +1,403 @@
+use std::mem::size_of;
+use enumflags2::BitFlags;
+use windows::Win32::Foundation::{HWND, RECT};
+use windows::Win32::Graphics::Gdi::HBITMAP;
+use crate::api::PluginApi;
+use crate::win32::{IntoWideString, WideString};
+use crate::WideStringPtr;
+
+/// パネル項目のスタイル
+#[bitflags]
+#[derive(Copy, Clone, Eq, PartialEq)]
+#[repr(u32)]
+pub enum PanelItemStyle {
+    /// キーボードフォーカスを受け取る
+    NeedFocus = 0x0001,
+}
+
+/// パネル項目の状態
+#[bitflags]
+#[derive(Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+#[repr(u32)]
+pub enum PanelItemState {
+    /// 有効(タブに表示されている)
+    Enabled = 0x0001,
+    /// アクティブ
+    Active  = 0x0002,
+}
+
+/// パネル項目の情報
+#[repr(C)]
+pub struct PanelItemInfo {
+    /// 構造体のサイズ
+    pub size: u32,
+    /// 各種フラグ(現在は常に0)
+    pub flags: u32,
+    /// スタイルフラグ
+    pub style: BitFlags<PanelItemStyle>,
+    /// 識別子
+    pub id: i32,
+    /// 識別子文字列
+    pub id_text: WideStringPtr,
+    /// タイトル
+    pub title: WideStringPtr,
+    /// アイコンのビットマップ
+    pub hbm_icon: HBITMAP,
+}
+
+/// パネル項目の設定のマスク
+#[bitflags]
+#[derive(Copy, Clone, Eq, PartialEq)]
+#[repr(u32)]
+pub enum PanelItemSetInfoMask {
+    /// state_mask / state を設定
+    State = 0x00000001,
+    /// style_mask / style を設定
+    Style = 0x00000002,
+}
+
+/// パネル項目の設定の情報
+#[repr(C)]
+pub struct PanelItemSetInfo {
+    /// 構造体のサイズ
+    pub size: u32,
+    /// 設定する情報のマスク
+    pub mask: BitFlags<PanelItemSetInfoMask>,
+    /// 項目の識別子
+    pub id: i32,
+    /// 状態フラグのマスク
+    pub state_mask: BitFlags<PanelItemState>,
+    /// 状態フラグ
+    pub state: BitFlags<PanelItemState>,
+    /// スタイルフラグのマスク
+    pub style_mask: BitFlags<PanelItemStyle>,
+    /// スタイルフラグ
+    pub style: BitFlags<PanelItemStyle>,
+}
+
+impl PanelItemSetInfo {
+    /// 項目の識別子を指定して生成します
+    pub fn new(id: i32) -> Self {
+        Self {
+            size: size_of::<PanelItemSetInfo>() as u32,
+            mask: BitFlags::empty(),
+            id,
+            state_mask: BitFlags::empty(),
+            state: BitFlags::empty(),
+            style_mask: BitFlags::empty(),
+            style: BitFlags::empty(),
+        }
+    }
+}
+
+/// パネル項目の情報取得のマスク
+#[bitflags]
+#[derive(Copy, Clone, Eq, PartialEq)]
+#[repr(u32)]
+pub enum PanelItemGetInfoMask {
+    /// state を取得
+    State      = 0x0001,
+    /// hwnd_parent を取得
+    HwndParent = 0x0002,
+    /// hwnd_item を取得
+    HwndItem   = 0x0004,
+    /// style を取得
+    Style      = 0x0008,
+}
+
+/// パネル項目の情報取得
+#[repr(C, packed)]
+pub struct PanelItemGetInfo {
+    /// 構造体のサイズ
+    pub size: u32,
+    /// 取得する情報のマスク
+    pub mask: BitFlags<PanelItemGetInfoMask>,
+    /// 項目の識別子
+    pub id: i32,
+    /// 項目の状態フラグ
+    pub state: BitFlags<PanelItemState>,
+    /// 親ウィンドウのハンドル
+    pub hwnd_parent: HWND,
+    /// 項目のウィンドウハンドル
+    pub hwnd_item: HWND,
+    /// スタイルフラグ
+    pub style: BitFlags<PanelItemStyle>,
+}
+
+impl PanelItemGetInfo {
+    /// 項目の識別子と取得する情報を指定して生成します
+    pub fn new(id: i32, mask: BitFlags<PanelItemGetInfoMask>) -> Self {
+        Self {
+            size: size_of::<PanelItemGetInfo>() as u32,
+            mask,
+            id,
+            state: BitFlags::empty(),
+            hwnd_parent: HWND(0),
+            hwnd_item: HWND(0),
+            style: BitFlags::empty(),
+        }
+    }
+}
+
+/// パネル項目のイベント
+#[repr(u32)]
+#[derive(Copy, Clone, PartialEq, Eq, TryFromPrimitive)]
+#[cfg_attr(test, derive(Debug))]
+pub enum PanelItemEvent {
+    /// 項目を作成する
+    Create = 1,
+    /// 項目がアクティブになる
+    Activate,
+    /// 項目が非アクティブになる
+    Deactivate,
+    /// 項目が有効になる
+    Enable,
+    /// 項目が無効になる
+    Disable,
+    /// スタイルが変わった(DPI の変更など)
+    StyleChanged,
+    /// フォントが変わった
+    FontChanged,
+}
+
+/// パネル項目の通知情報
+#[repr(C)]
+#[cfg_attr(test, derive(Debug))]
+pub struct PanelItemEventInfo {
+    /// 項目の識別子
+    pub id: i32,
+    /// イベントの種類 (PanelItemEvent の値)
+    /// 未知の値が渡される可能性があるため、event() で取得してください
+    pub event: u32,
+}
+
+impl PanelItemEventInfo {
+    /// イベントの種類
+    /// 未知の値の場合は None を返します
+    pub fn event(&self) -> Option<PanelItemEvent> {
+        PanelItemEvent::try_from(self.event).ok()
+    }
+}
+
+/// パネル項目作成イベントの情報
+/// PanelItemEvent::Create で渡されます
+/// hwnd_item に作成したウィンドウのハンドルを返します
 #[repr(C)]
 #[cfg_attr(test, derive(Debug))]
-pub struct PanelItemEventInfo;
+pub struct PanelItemCreateEventInfo {
+    pub event_info: PanelItemEventInfo,
+    pub item_rect: RECT,
+    pub hwnd_parent: HWND,
+    pub hwnd_item: HWND,
+}
+
+/// パネル項目の処理
+/// PanelItems に登録すると、識別子の一致するイベントが振り分けられます
+#[allow(unused_variables)]
+pub trait PanelItem {
+    /// 項目のウィンドウを hwnd_parent の子ウィンドウとして作成し、そのハンドルを返します
+    fn create(&self, hwnd_parent: HWND, item_rect: &RECT) -> Option<HWND>;
+    /// 項目がアクティブになる
+    fn activate(&self) -> bool { false }
+    /// 項目が非アクティブになる
+    fn deactivate(&self) -> bool { false }
+    /// 項目が有効になる
+    fn enable(&self) -> bool { false }
+    /// 項目が無効になる
+    fn disable(&self) -> bool { false }
+    /// その他の通知
+    fn notify(&self, info: &PanelItemEventInfo) -> bool { false }
+    /// 項目を破棄します
+    /// PanelItems が破棄される時に呼ばれるので、作成したウィンドウなどを破棄します
+    fn destroy(&self) {}
+}
+
+/// パネル項目の登録内容
+pub struct PanelItemBuilder {
+    id: i32,
+    id_text: String,
+    title: String,
+    style: BitFlags<PanelItemStyle>,
+    icon: HBITMAP,
+}
+
+impl PanelItemBuilder {
+    /// 識別子、識別子文字列、タイトルを指定します
+    pub fn new(id: i32, id_text: &str, title: &str) -> Self {
+        Self {
+            id,
+            id_text: id_text.to_string(),
+            title: title.to_string(),
+            style: BitFlags::empty(),
+            icon: HBITMAP(0),
+        }
+    }
+
+    /// スタイルフラグを設定します
+    pub fn style(mut self, style: BitFlags<PanelItemStyle>) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// アイコンのビットマップを設定します
+    /// ビットマップは登録後に破棄して構いません
+    pub fn icon(mut self, hbm_icon: HBITMAP) -> Self {
+        self.icon = hbm_icon;
+        self
+    }
+}
+
+struct RegisteredPanelItem {
+    id: i32,
+    item: Box<dyn PanelItem>,
+}
+
+/// パネル項目の一覧
+/// on_panel_item_create / on_panel_item_notify から create() / notify() を呼び出して各項目に振り分けます
+#[derive(core::default::Default)]
+pub struct PanelItems {
+    items: Vec<RegisteredPanelItem>,
+}
+
+impl PanelItems {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// TVTest にパネル項目を登録します
+    pub fn register<T: PanelItem + 'static>(&mut self, api: &PluginApi, builder: PanelItemBuilder, item: T) -> bool {
+        let id_text: WideString = builder.id_text.into_wide_string();
+        let title: WideString = builder.title.into_wide_string();
+        let info = PanelItemInfo {
+            size: size_of::<PanelItemInfo>() as u32,
+            flags: 0,
+            style: builder.style,
+            id: builder.id,
+            id_text: id_text.to_wide_string_ptr(),
+            title: title.to_wide_string_ptr(),
+            hbm_icon: builder.icon,
+        };
+        if !api.register_panel_item(&info) {
+            return false;
+        }
+
+        self.items.retain(|registered| registered.id != builder.id);
+        self.items.push(RegisteredPanelItem {
+            id: builder.id,
+            item: Box::new(item),
+        });
+        true
+    }
+
+    fn find(&self, id: i32) -> Option<&dyn PanelItem> {
+        self.items.iter()
+            .find(|registered| registered.id == id)
+            .map(|registered| registered.item.as_ref())
+    }
+
+    /// 項目を作成し、作成したウィンドウのハンドルを hwnd_item に設定します
+    pub fn create(&self, info: &mut PanelItemCreateEventInfo) -> bool {
+        let item = match self.find(info.event_info.id) {
+            Some(item) => item,
+            None => return false,
+        };
+
+        match item.create(info.hwnd_parent, &info.item_rect) {
+            Some(hwnd) => {
+                info.hwnd_item = hwnd;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 通知を振り分けます
+    /// 作成の通知は PanelItemCreateEventInfo として create() に渡されるため、ここでは処理しません
+    pub fn notify(&self, info: &PanelItemEventInfo) -> bool {
+        self.find(info.id).is_some_and(|item| match info.event() {
+            Some(PanelItemEvent::Create) => false,
+            Some(PanelItemEvent::Activate) => item.activate(),
+            Some(PanelItemEvent::Deactivate) => item.deactivate(),
+            Some(PanelItemEvent::Enable) => item.enable(),
+            Some(PanelItemEvent::Disable) => item.disable(),
+            _ => item.notify(info),
+        })
+    }
+}
+
+impl Drop for PanelItems {
+    fn drop(&mut self) {
+        for registered in &self.items {
+            registered.item.destroy();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use windows::Win32::Foundation::{LPARAM, LRESULT};
+    use crate::event::{Event, PluginEvent};
+    use crate::message::Message;
+    use crate::mock::MockHost;
+    use super::*;
+
+    struct Viewer {
+        active: Rc<Cell<bool>>,
+        destroyed: Rc<Cell<bool>>,
+    }
+
+    impl PanelItem for Viewer {
+        fn create(&self, hwnd_parent: HWND, _: &RECT) -> Option<HWND> {
+            HWND(hwnd_parent.0 + 1).into()
+        }
+
+        fn activate(&self) -> bool {
+            self.active.set(true);
+            true
+        }
+
+        fn destroy(&self) {
+            self.destroyed.set(true);
+        }
+    }
+
+    #[test]
+    fn route_events_by_id() {
+        let host = MockHost::new();
+        host.on(Message::RegisterPanelItem, |_, _| LRESULT(1));
+        let api = host.api();
+
+        let (active, destroyed) = (Rc::new(Cell::new(false)), Rc::new(Cell::new(false)));
+        let mut items = PanelItems::new();
+        assert!(items.register(&api, PanelItemBuilder::new(1, "Caption", "字幕"), Viewer {
+            active: Rc::clone(&active),
+            destroyed: Rc::clone(&destroyed),
+        }));
+
+        // 作成の通知は PanelItemCreateEventInfo として解釈される
+        let mut create = PanelItemCreateEventInfo {
+            event_info: PanelItemEventInfo { id: 1, event: PanelItemEvent::Create as u32 },
+            item_rect: RECT::default(),
+            hwnd_parent: HWND(10),
+            hwnd_item: HWND(0),
+        };
+        let event = unsafe { PluginEvent::decode(Event::PanelItemNotify as u32, LPARAM(&mut create as *mut _ as isize), LPARAM(0)) };
+        match event {
+            Some(PluginEvent::PanelItemCreate(info)) => assert!(items.create(info)),
+            _ => panic!("PanelItemCreate として解釈されていない"),
+        }
+        assert_eq!(create.hwnd_item, HWND(11));
+
+        let event = |id, event: PanelItemEvent| PanelItemEventInfo { id, event: event as u32 };
+        assert!(items.notify(&event(1, PanelItemEvent::Activate)));
+        assert!(!items.notify(&event(2, PanelItemEvent::Activate)));
+        assert!(!items.notify(&PanelItemEventInfo { id: 1, event: 0x100 }));
+        assert!(active.get());
+
+        drop(items);
+        assert!(destroyed.get());
+    }
+}
@@ -4,7 +4,7 @@ use windows::Win32::Foundation::HWND;
 use windows::Win32::Graphics::Gdi::HBITMAP;
 use crate::ClientData;
 use crate::message::MessageCallbackFunc;
-use crate::win32::WideStringPtr;
+use crate::win32::{WStr, WideStringPtr};
 
 /// プラグインの種類
 #[repr(u32)]
@@ -50,6 +50,20 @@ pub struct PluginInfo
     pub description: WideStringPtr,
 }
 
+impl PluginInfo {
+    /// プラグインの情報を生成します
+    /// 文字列は TVTest が読み取るまで生存している必要があるため、wstr! などで生成した `'static` な文字列を指定します
+    pub fn new(kind: PluginKind, flags: BitFlags<PluginFlag>, name: WStr<'static>, copyright: WStr<'static>, description: WStr<'static>) -> Self {
+        Self {
+            kind,
+            flags,
+            name: name.into(),
+            copyright: copyright.into(),
+            description: description.into(),
+        }
+    }
+}
+
 /// プラグインパラメータ
 #[repr(C)]
 pub struct PluginParam
@@ -64,6 +78,24 @@ pub struct PluginParam
     internal_data: *mut c_void,
 }
 
+#[cfg(test)]
+impl PluginParam {
+    /// テスト用の PluginParam を生成します
+    /// internal_data には MockHost の状態が格納されます
+    pub(crate) fn new(callback: MessageCallbackFunc, hwnd_app: HWND, internal_data: *mut c_void) -> Self {
+        Self {
+            callback,
+            hwnd_app,
+            client_data: None,
+            internal_data,
+        }
+    }
+
+    pub(crate) fn internal_data(&self) -> *mut c_void {
+        self.internal_data
+    }
+}
+
 /// プラグインのアイコンの情報
 pub struct PluginIconInfo {
     /// 構造体のサイズ
@@ -1,8 +1,8 @@
-use windows::Win32::Foundation::SYSTEMTIME;
+use crate::time::EpgTime;
 use crate::WideStringPtr;
 
 /// 番組の情報
-#[repr(C)]
+#[repr(C, packed)]
 pub struct ProgramInfo {
     /// 構造体のサイズ
     pub size: u32,
@@ -23,7 +23,7 @@ pub struct ProgramInfo {
     /// 追加イベントテキストの最大長
     pub max_event_ext_text: i32,
     /// 開始日時(EPG 日時 : UTC+9)
-    pub start_time: SYSTEMTIME,
+    pub start_time: EpgTime,
     /// 長さ(秒単位)
     pub duration: u32,
 }
@@ -1,7 +1,12 @@
+use std::ptr;
 use enumflags2::BitFlags;
-use windows::Win32::Foundation::{POINT, RECT, SYSTEMTIME};
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::{POINT, RECT};
 use windows::Win32::Graphics::Gdi::HDC;
-use windows::Win32::UI::WindowsAndMessaging::HMENU;
+use windows::Win32::UI::WindowsAndMessaging::{AppendMenuW, HMENU, MF_CHECKED, MF_GRAYED, MF_SEPARATOR, MF_STRING};
+use crate::api::PluginApi;
+use crate::time::EpgTime;
+use crate::win32::{IntoWideString, WideString};
 use crate::WideStringPtr;
 
 /// 番組表の番組の情報
@@ -17,13 +22,14 @@ pub struct ProgramGuideProgramInfo {
     /// イベントID
     pub event_id: u16,
     /// 開始日時(EPG 日時 : UTC+9)
-    pub start_time: SYSTEMTIME,
+    pub start_time: EpgTime,
     /// 長さ(秒単位)
     pub duration: u32,
 }
 
 /// 番組表の番組の背景描画の情報
-#[repr(C)]
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
 #[cfg_attr(test, derive(Debug))]
 pub struct ProgramGuideProgramDrawBackgroundInfo {
     /// 描画先DCハンドル
@@ -85,6 +91,7 @@ pub enum ProgramGuideCommandKind {
     Program = 0x0001,
 }
 
+#[repr(C)]
 pub struct ProgramGuideCommandInfo {
     /// 種類
     pub kind: BitFlags<ProgramGuideCommandKind>,
@@ -100,6 +107,8 @@ pub struct ProgramGuideCommandInfo {
 
 /// 番組表のコマンド実行の操作の種類
 #[repr(u32)]
+#[derive(Copy, Clone, PartialEq, Eq, TryFromPrimitive)]
+#[cfg_attr(test, derive(Debug))]
 pub enum ProgramGuideCommandAction {
     /// マウスなど
     Mouse,
@@ -112,8 +121,9 @@ pub enum ProgramGuideCommandAction {
 pub struct ProgramGuideCommandParam {
     /// 識別子
     pub id: u32,
-    /// 操作の種類
-    pub action: ProgramGuideCommandAction,
+    /// 操作の種類 (ProgramGuideCommandAction の値)
+    /// 未知の値が渡される可能性があるため、action() で取得してください
+    pub action: u32,
     /// 番組の情報
     pub program: ProgramGuideProgramInfo,
     /// カーソル位置
@@ -121,3 +131,271 @@ pub struct ProgramGuideCommandParam {
     /// 項目の位置
     pub item_rect: RECT,
 }
+
+impl ProgramGuideCommandParam {
+    /// 操作の種類
+    /// 未知の値の場合は None を返します
+    pub fn action(&self) -> Option<ProgramGuideCommandAction> {
+        ProgramGuideCommandAction::try_from(self.action).ok()
+    }
+}
+
+/// 番組表のコマンドの処理
+pub type ProgramGuideCommandHandler = dyn Fn(&ProgramGuideCommandParam) -> bool;
+
+struct ProgramGuideCommand {
+    id: u32,
+    text: String,
+    name: String,
+    handler: Box<ProgramGuideCommandHandler>,
+}
+
+/// 番組表のコマンドの一覧
+/// 登録したコマンドは on_program_guide_command から dispatch() を呼び出して実行します
+#[derive(core::default::Default)]
+pub struct ProgramGuideCommands {
+    commands: Vec<ProgramGuideCommand>,
+}
+
+impl ProgramGuideCommands {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 各番組のコマンドを追加します
+    /// `text` は番組表のメニューなどに表示される文字列、`name` は設定ファイルなどに保存される名前です
+    pub fn command<F>(mut self, id: u32, text: &str, name: &str, handler: F) -> Self
+        where F: Fn(&ProgramGuideCommandParam) -> bool + 'static
+    {
+        self.commands.push(ProgramGuideCommand {
+            id,
+            text: text.to_string(),
+            name: name.to_string(),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    /// TVTest にコマンドを登録します
+    pub fn register(&self, api: &PluginApi) -> bool {
+        let strings: Vec<(WideString, WideString)> = self.commands.iter()
+            .map(|command| (command.text.as_str().into_wide_string(), command.name.as_str().into_wide_string()))
+            .collect();
+        let infos: Vec<ProgramGuideCommandInfo> = self.commands.iter()
+            .zip(strings.iter())
+            .map(|(command, (text, name))| ProgramGuideCommandInfo {
+                kind: ProgramGuideCommandKind::Program.into(),
+                flags: 0,
+                id: command.id,
+                text: text.to_wide_string_ptr(),
+                name: name.to_wide_string_ptr(),
+            })
+            .collect();
+
+        api.register_program_guide_command(&infos)
+    }
+
+    /// コマンドを実行します
+    /// 該当するコマンドがない場合は false を返します
+    pub fn dispatch(&self, command: u32, param: &ProgramGuideCommandParam) -> bool {
+        self.commands.iter()
+            .find(|c| c.id == command)
+            .is_some_and(|c| (c.handler)(param))
+    }
+}
+
+/// 番組表のメニューの項目の処理・表示条件
+pub type ProgramGuideMenuPredicate<P> = dyn Fn(&P) -> bool;
+
+enum ProgramGuideMenuEntry<P> {
+    Item {
+        text: WideString,
+        visible: Option<Box<ProgramGuideMenuPredicate<P>>>,
+        enabled: Option<Box<ProgramGuideMenuPredicate<P>>>,
+        checked: Option<Box<ProgramGuideMenuPredicate<P>>>,
+        handler: Box<ProgramGuideMenuPredicate<P>>,
+    },
+    Separator,
+}
+
+/// 番組表のメニューの項目の設定
+pub struct ProgramGuideMenuItem<P> {
+    visible: Option<Box<ProgramGuideMenuPredicate<P>>>,
+    enabled: Option<Box<ProgramGuideMenuPredicate<P>>>,
+    checked: Option<Box<ProgramGuideMenuPredicate<P>>>,
+}
+
+impl<P> ProgramGuideMenuItem<P> {
+    /// 条件を満たす場合のみ項目を表示します
+    pub fn visible_if<F: Fn(&P) -> bool + 'static>(mut self, predicate: F) -> Self {
+        self.visible = Some(Box::new(predicate));
+        self
+    }
+
+    /// 条件を満たさない場合は項目を無効にします
+    pub fn enabled_if<F: Fn(&P) -> bool + 'static>(mut self, predicate: F) -> Self {
+        self.enabled = Some(Box::new(predicate));
+        self
+    }
+
+    /// 条件を満たす場合は項目にチェックを付けます
+    pub fn checked_if<F: Fn(&P) -> bool + 'static>(mut self, predicate: F) -> Self {
+        self.checked = Some(Box::new(predicate));
+        self
+    }
+}
+
+/// 番組表のメニュー
+/// 項目のIDは追加した順に自動で割り当てられます
+/// on_program_guide_(program_)initialize_menu で insert() を、on_program_guide_(program_)menu_selected で dispatch() を呼び出します
+pub struct ProgramGuideMenu<P> {
+    entries: Vec<ProgramGuideMenuEntry<P>>,
+}
+
+/// 番組表全体のメニュー
+pub type ProgramGuideGeneralMenu = ProgramGuideMenu<()>;
+/// 番組表の番組のメニュー
+pub type ProgramGuideProgramMenu = ProgramGuideMenu<ProgramGuideProgramInfo>;
+
+impl<P> Default for ProgramGuideMenu<P> {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl<P> ProgramGuideMenu<P> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 項目を追加します
+    pub fn item<F>(self, text: &str, handler: F) -> Self
+        where F: Fn(&P) -> bool + 'static
+    {
+        self.item_with(text, handler, |item| item)
+    }
+
+    /// 表示条件などを指定して項目を追加します
+    pub fn item_with<F, C>(mut self, text: &str, handler: F, configure: C) -> Self
+        where F: Fn(&P) -> bool + 'static,
+              C: FnOnce(ProgramGuideMenuItem<P>) -> ProgramGuideMenuItem<P>
+    {
+        let item = configure(ProgramGuideMenuItem {
+            visible: None,
+            enabled: None,
+            checked: None,
+        });
+        self.entries.push(ProgramGuideMenuEntry::Item {
+            text: text.into_wide_string(),
+            visible: item.visible,
+            enabled: item.enabled,
+            checked: item.checked,
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    /// 区切り線を追加します
+    pub fn separator(mut self) -> Self {
+        self.entries.push(ProgramGuideMenuEntry::Separator);
+        self
+    }
+
+    /// 使用する項目のIDの数
+    /// 表示しない項目の分も含みます
+    pub fn command_count(&self) -> u32 {
+        self.items().count() as u32
+    }
+
+    fn items(&self) -> impl Iterator<Item = &ProgramGuideMenuEntry<P>> {
+        self.entries.iter().filter(|entry| matches!(entry, ProgramGuideMenuEntry::Item { .. }))
+    }
+
+    /// メニューに項目を追加し、使用した項目のIDの数を返します
+    /// `command` は TVTest から渡された最初の項目のIDです
+    pub fn insert(&self, hmenu: HMENU, command: u32, target: &P) -> i32 {
+        let mut offset = 0;
+        // 表示する項目の直前にのみ区切り線を入れる
+        let mut separator = false;
+        let mut appended = false;
+
+        for entry in &self.entries {
+            match entry {
+                ProgramGuideMenuEntry::Separator => separator = appended,
+                ProgramGuideMenuEntry::Item { text, visible, enabled, checked, .. } => {
+                    let id = command + offset;
+                    offset += 1;
+
+                    if !visible.as_ref().map(|f| f(target)).unwrap_or(true) {
+                        continue;
+                    }
+                    if separator {
+                        unsafe { AppendMenuW(hmenu, MF_SEPARATOR, 0, PCWSTR(ptr::null())) };
+                        separator = false;
+                    }
+
+                    let mut flags = MF_STRING;
+                    if !enabled.as_ref().map(|f| f(target)).unwrap_or(true) {
+                        flags |= MF_GRAYED;
+                    }
+                    if checked.as_ref().is_some_and(|f| f(target)) {
+                        flags |= MF_CHECKED;
+                    }
+                    unsafe { AppendMenuW(hmenu, flags, id as usize, PCWSTR(text.0.as_ptr())) };
+                    appended = true;
+                }
+            }
+        }
+
+        offset as i32
+    }
+
+    /// 選択された項目の処理を実行します
+    /// `command` は最初の項目のIDからの相対値です
+    pub fn dispatch(&self, command: u32, target: &P) -> bool {
+        match self.items().nth(command as usize) {
+            Some(ProgramGuideMenuEntry::Item { handler, .. }) => handler(target),
+            _ => false,
+        }
+    }
+}
+
+impl ProgramGuideMenu<()> {
+    /// on_program_guide_initialize_menu から呼び出します
+    pub fn initialize(&self, info: &ProgramGuideInitializeMenuInfo) -> i32 {
+        self.insert(info.hmenu, info.command, &())
+    }
+}
+
+impl ProgramGuideMenu<ProgramGuideProgramInfo> {
+    /// on_program_guide_program_initialize_menu から呼び出します
+    pub fn initialize(&self, program_info: &ProgramGuideProgramInfo, info: &ProgramGuideProgramInitializeMenuInfo) -> i32 {
+        self.insert(info.hmenu, info.command, program_info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use super::*;
+
+    #[test]
+    fn dispatch_menu_by_offset() {
+        let selected = Rc::new(Cell::new(None));
+        let (first, second) = (Rc::clone(&selected), Rc::clone(&selected));
+        let menu = ProgramGuideGeneralMenu::new()
+            .item("First", move |_| { first.set(Some(0)); true })
+            .separator()
+            .item_with("Second", move |_| { second.set(Some(1)); true }, |item| item.visible_if(|_| false));
+
+        assert_eq!(menu.command_count(), 2);
+        assert!(menu.dispatch(1, &()));
+        assert_eq!(selected.get(), Some(1));
+        assert!(menu.dispatch(0, &()));
+        assert_eq!(selected.get(), Some(0));
+        assert!(!menu.dispatch(2, &()));
+    }
+}
@@ -1,6 +1,9 @@
 use std::mem::size_of;
 use enumflags2::{BitFlag, BitFlags};
+use std::time::SystemTime;
 use windows::Win32::Foundation::{FILETIME, RECT};
+use crate::api::PluginApi;
+use crate::time::{LocalFileTime, TVTestTime, UtcFileTime};
 use crate::win32::WideStringPtr;
 
 /// 録画情報のマスク
@@ -33,7 +36,7 @@ pub enum RecordFlag {
 
 /// 録画開始時間の指定方法
 #[repr(u32)]
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Copy, Clone, PartialEq, Eq, TryFromPrimitive)]
 #[cfg_attr(test, derive(Debug))]
 pub enum RecordStart {
     /// 未指定
@@ -46,7 +49,7 @@ pub enum RecordStart {
 
 /// 録画停止時間の指定方法
 #[repr(u32)]
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Copy, Clone, PartialEq, Eq, TryFromPrimitive)]
 #[cfg_attr(test, derive(Debug))]
 pub enum RecordStop {
     /// 未指定
@@ -78,7 +81,7 @@ pub union RecordStopTime {
 }
 
 /// 録画情報
-#[repr(C)]
+#[repr(C, packed)]
 pub struct RecordInfo {
     // 構造体のサイズ
     pub size: u32,
@@ -123,9 +126,65 @@ impl Default for RecordInfo {
     }
 }
 
+impl RecordInfo {
+    /// 録画予約された時刻
+    pub fn reserved_at(&self) -> RecordTime {
+        RecordTime::new(self.reserve_time, { self.flags }.contains(RecordFlag::UTC))
+    }
+
+    /// 録画開始時刻
+    /// 時刻で指定されていない場合は None を返します
+    pub fn start_at(&self) -> Option<RecordTime> {
+        if { self.start_time_spec } == RecordStart::Time {
+            RecordTime::new(unsafe { self.start_time.time }, { self.flags }.contains(RecordFlag::UTC)).into()
+        } else {
+            None
+        }
+    }
+
+    /// 録画停止時刻
+    /// 時刻で指定されていない場合は None を返します
+    pub fn stop_at(&self) -> Option<RecordTime> {
+        if { self.stop_time_spec } == RecordStop::Time {
+            RecordTime::new(unsafe { self.stop_time.time }, { self.flags }.contains(RecordFlag::UTC)).into()
+        } else {
+            None
+        }
+    }
+}
+
+/// 録画の日時
+/// RecordFlag::UTC の指定によって UTC かローカル時刻になります
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
+pub enum RecordTime {
+    Utc(UtcFileTime),
+    Local(LocalFileTime),
+}
+
+impl RecordTime {
+    fn new(time: FILETIME, utc: bool) -> Self {
+        if utc {
+            RecordTime::Utc(UtcFileTime(time))
+        } else {
+            RecordTime::Local(LocalFileTime(time))
+        }
+    }
+
+    /// std::time::SystemTime に変換します
+    /// ローカル時刻の場合は TVTest で UTC に変換します
+    pub fn to_system_time(&self, api: &PluginApi) -> Option<SystemTime> {
+        match self {
+            RecordTime::Utc(time) => time.to_system_time(),
+            RecordTime::Local(time) => api.convert_time::<_, UtcFileTime>(time)?.to_system_time(),
+        }
+    }
+}
+
 /// 録画の状態
 #[repr(u32)]
-#[derive(UnsafeFromPrimitive)]
+#[derive(Copy, Clone, Eq, PartialEq, UnsafeFromPrimitive, TryFromPrimitive)]
+#[cfg_attr(test, derive(Debug))]
 pub enum RecordStatus {
     /// 録画していない
     NotRecording,
@@ -136,12 +195,13 @@ pub enum RecordStatus {
 }
 
 /// 録画ステータス情報
-#[repr(C)]
+#[repr(C, packed)]
 pub struct RecordStatusInfo {
     /// 構造体のサイズ
     pub size: u32,
-    /// 状態
-    pub status: RecordStatus,
+    /// 状態 (RecordStatus の値)
+    /// 未知の値が返される可能性があるため、status() で取得してください
+    pub status: u32,
     /// 録画開始時刻
     /// ローカル時刻(RECORD_STATUS_FLAG_UTC が指定されていれば UTC)
     pub start_time: FILETIME,
@@ -149,8 +209,9 @@ pub struct RecordStatusInfo {
     pub record_time: u32,
     /// 一時停止時間(ms)
     pub pause_time: u32,
-    /// 録画停止時間の指定方法
-    pub stop_time_spec: RecordStop,
+    /// 録画停止時間の指定方法 (RecordStop の値)
+    /// 未知の値が返される可能性があるため、stop_time_spec() で取得してください
+    pub stop_time_spec: u32,
     pub stop_time: RecordStopTime,
     /// ファイルパス
     pub filename: WideStringPtr,
@@ -158,6 +219,38 @@ pub struct RecordStatusInfo {
     pub max_filename: i32,
 }
 
+impl Default for RecordStatusInfo {
+    fn default() -> Self {
+        Self {
+            size: size_of::<Self>() as u32,
+            status: RecordStatus::NotRecording as u32,
+            start_time: FILETIME::default(),
+            record_time: 0,
+            pause_time: 0,
+            stop_time_spec: RecordStop::NotSpecified as u32,
+            stop_time: RecordStopTime {
+                time: FILETIME::default()
+            },
+            filename: Default::default(),
+            max_filename: 0,
+        }
+    }
+}
+
+impl RecordStatusInfo {
+    /// 録画の状態
+    /// 未知の値の場合は None を返します
+    pub fn status(&self) -> Option<RecordStatus> {
+        RecordStatus::try_from(self.status).ok()
+    }
+
+    /// 録画停止時間の指定方法
+    /// 未知の値の場合は None を返します
+    pub fn stop_time_spec(&self) -> Option<RecordStop> {
+        RecordStop::try_from(self.stop_time_spec).ok()
+    }
+}
+
 /// 録画ステータス取得フラグ
 #[repr(u32)]
 pub enum RecordStatusFlag {
@@ -183,6 +276,8 @@ pub struct VideoInfo {
 
 /// 録画のクライアント
 #[repr(u32)]
+#[derive(Copy, Clone, PartialEq, Eq, TryFromPrimitive)]
+#[cfg_attr(test, derive(Debug))]
 pub enum RecordClient {
     /// ユーザーの操作
     User,
@@ -203,7 +298,7 @@ pub enum StartRecordModified {
 
 /// 録画開始情報
 /// EVENT_STARTRECORD で渡されます。
-#[repr(C)]
+#[repr(C, packed)]
 pub struct StartRecordInfo {
     /// 構造体のサイズ
     pub size: u32,
@@ -211,18 +306,66 @@ pub struct StartRecordInfo {
     pub flags: u32,
     /// 変更した項目
     pub modified: BitFlags<StartRecordModified>,
-    /// 録画のクライアント
-    pub client: RecordClient,
+    /// 録画のクライアント (RecordClient の値)
+    /// 未知の値が渡される可能性があるため、client() で取得してください
+    pub client: u32,
     /// ファイル名
     pub filename: WideStringPtr,
     /// ファイル名の最大長
     pub max_filename: u32,
-    /// 開始時間の指定方法
-    pub start_time_spec: RecordStart,
+    /// 開始時間の指定方法 (RecordStart の値)
+    /// 未知の値が渡される可能性があるため、start_time_spec() で取得してください
+    pub start_time_spec: u32,
     /// 指定された開始時刻(ローカル時刻)
     /// StartTimeSpec!=RECORD_START_NOTSPECIFIED の場合のみ有効
-    pub start_time: FILETIME,
-    /// 停止時間の指定方法
-    pub stop_time_spec: RecordStop,
+    pub start_time: LocalFileTime,
+    /// 停止時間の指定方法 (RecordStop の値)
+    /// 未知の値が渡される可能性があるため、stop_time_spec() で取得してください
+    pub stop_time_spec: u32,
     pub stop_time: RecordStopTime,
 }
+
+impl StartRecordInfo {
+    /// 録画のクライアント
+    /// 未知の値の場合は None を返します
+    pub fn client(&self) -> Option<RecordClient> {
+        RecordClient::try_from(self.client).ok()
+    }
+
+    /// 開始時間の指定方法
+    /// 未知の値の場合は None を返します
+    pub fn start_time_spec(&self) -> Option<RecordStart> {
+        RecordStart::try_from(self.start_time_spec).ok()
+    }
+
+    /// 停止時間の指定方法
+    /// 未知の値の場合は None を返します
+    pub fn stop_time_spec(&self) -> Option<RecordStop> {
+        RecordStop::try_from(self.stop_time_spec).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Win32::Foundation::LRESULT;
+    use crate::message::Message;
+    use crate::mock::MockHost;
+    use super::*;
+
+    #[test]
+    fn record_status_keeps_unknown_stop_time_spec() {
+        let host = MockHost::new();
+        host.on(Message::GetRecordStatus, |info, _| {
+            let info = unsafe { &mut *(info.0 as *mut RecordStatusInfo) };
+            info.status = RecordStatus::Recording as u32;
+            info.stop_time_spec = 0x100;
+            LRESULT(1)
+        });
+        let api = host.api();
+
+        // 未知の停止時間の指定方法は None になる
+        let info = api.record_status().unwrap();
+        assert_eq!(info.status(), Some(RecordStatus::Recording));
+        assert_eq!(info.stop_time_spec(), None);
+    }
+}
@@ -0,0 +1,381 @@
+use std::future::poll_fn;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use ::futures_core::Stream;
+use ::tokio::runtime::{Builder, Handle, Runtime};
+use ::tokio::sync::broadcast;
+use ::tokio::task::JoinHandle;
+use ::tokio_stream::wrappers::BroadcastStream;
+use ::tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use crate::api::PluginApi;
+use crate::api_handle::{ApiDispatcher, ApiHandle, ApiHandleError};
+use crate::channel::ChannelInfo;
+use crate::event::{Event, PluginEvent};
+use crate::log::LogKind;
+use crate::record::RecordStatus;
+use crate::service::{GetServiceInfo, ServiceInfo};
+use crate::status::StatusInfo;
+use crate::stereo_mode::StereoMode;
+use crate::win32::UnsafeIntoRustString;
+
+/// 終了時に非同期タスクの終了を待つ既定の時間
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 非同期のイベントストリームに保持できるイベントの数
+/// 受け取りが追いつかない場合は古いイベントから破棄されます
+const EVENT_CAPACITY: usize = 256;
+
+/// 非同期タスクに配信されるイベント
+/// ホストのメモリを参照するパラメータを持つイベントは Other として種類のみ配信されます
+#[derive(Clone)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub enum AsyncEvent {
+    /// 有効状態が変化した
+    PluginEnable(bool),
+    /// チャンネルが変更された
+    ChannelChange,
+    /// サービスが変更された
+    ServiceChange,
+    /// ドライバが変更された
+    DriverChange,
+    /// サービスの構成が変化した
+    ServiceUpdate,
+    /// 録画状態が変化した
+    RecordStatusChange(RecordStatus),
+    /// 全画面表示状態が変化した
+    FullScreenChange(bool),
+    /// プレビュー表示状態が変化した
+    PreviewChange(bool),
+    /// 音量が変化した
+    VolumeChange { volume: i32, is_mute: bool },
+    /// ステレオモードが変化した
+    StereoModeChange(StereoMode),
+    /// 待機状態が変化した
+    StandBy(bool),
+    /// コマンドが選択された
+    Command(i32),
+    /// 複数起動禁止時に複数起動された
+    Execute(Option<String>),
+    /// 音声ストリームが変更された
+    AudioStreamChange(i32),
+    /// 録画ファイルが切り替えられた
+    RelayRecord(Option<String>),
+    /// ワンセグモードが変わった
+    OneSegModeChanged(bool),
+    /// その他のイベント
+    Other(Event),
+}
+
+impl From<&PluginEvent<'_>> for AsyncEvent {
+    fn from(event: &PluginEvent) -> Self {
+        match event {
+            PluginEvent::PluginEnable(is_enable) => AsyncEvent::PluginEnable(*is_enable),
+            PluginEvent::ChannelChange => AsyncEvent::ChannelChange,
+            PluginEvent::ServiceChange => AsyncEvent::ServiceChange,
+            PluginEvent::DriverChange => AsyncEvent::DriverChange,
+            PluginEvent::ServiceUpdate => AsyncEvent::ServiceUpdate,
+            PluginEvent::RecordStatusChange(status) => AsyncEvent::RecordStatusChange(*status),
+            PluginEvent::FullScreenChange(is_fullscreen) => AsyncEvent::FullScreenChange(*is_fullscreen),
+            PluginEvent::PreviewChange(is_preview) => AsyncEvent::PreviewChange(*is_preview),
+            PluginEvent::VolumeChange { volume, is_mute } => AsyncEvent::VolumeChange { volume: *volume, is_mute: *is_mute },
+            PluginEvent::StereoModeChange(mode) => AsyncEvent::StereoModeChange(*mode),
+            PluginEvent::StandBy(is_standby) => AsyncEvent::StandBy(*is_standby),
+            PluginEvent::Command(id) => AsyncEvent::Command(*id),
+            PluginEvent::Execute(command_line) => AsyncEvent::Execute(command_line.read_string()),
+            PluginEvent::AudioStreamChange(stream) => AsyncEvent::AudioStreamChange(*stream),
+            PluginEvent::RelayRecord(file_name) => AsyncEvent::RelayRecord(file_name.read_string()),
+            PluginEvent::OneSegModeChanged(mode) => AsyncEvent::OneSegModeChanged(*mode),
+            event => AsyncEvent::Other(event.kind()),
+        }
+    }
+}
+
+/// 非同期タスクに配信されるイベントのストリーム
+/// futures_core::Stream を実装しているため、StreamExt などと組み合わせて使用できます
+pub struct EventStream {
+    inner: BroadcastStream<AsyncEvent>,
+}
+
+impl EventStream {
+    /// 次のイベントを待ちます
+    /// PluginRuntime が終了すると None を返します
+    pub async fn next(&mut self) -> Option<AsyncEvent> {
+        poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl Stream for EventStream {
+    type Item = AsyncEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<AsyncEvent>> {
+        loop {
+            match Pin::new(&mut self.inner).poll_next(cx) {
+                // 受け取れなかったイベントは読み飛ばす
+                Poll::Ready(Some(Err(BroadcastStreamRecvError::Lagged(_)))) => continue,
+                Poll::Ready(Some(Ok(event))) => return Poll::Ready(Some(event)),
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// UI スレッドで実行される PluginApi の非同期版
+/// 任意のスレッドやタスクから呼び出せます
+#[derive(Clone)]
+pub struct AsyncApi {
+    handle: ApiHandle,
+}
+
+impl AsyncApi {
+    pub fn new(handle: ApiHandle) -> Self {
+        Self { handle }
+    }
+
+    /// UI スレッドで任意の処理を実行します
+    pub async fn call<R, F>(&self, f: F) -> Result<R, ApiHandleError>
+        where R: Send + 'static,
+              F: FnOnce(&PluginApi) -> R + Send + 'static
+    {
+        self.handle.call(f).await
+    }
+
+    /// 現在のチャンネルの情報を取得する
+    pub async fn get_current_channel_info(&self) -> Result<Option<ChannelInfo>, ApiHandleError> {
+        self.call(PluginApi::get_current_channel_info).await
+    }
+
+    /// チャンネルを設定する
+    pub async fn set_channel(&self, space: i32, channel: i32) -> Result<bool, ApiHandleError> {
+        self.call(move |api| api.set_channel(space, channel)).await
+    }
+
+    /// 現在のサービス及びサービス数を取得する
+    pub async fn get_service(&self) -> Result<Option<GetServiceInfo>, ApiHandleError> {
+        self.call(PluginApi::get_service).await
+    }
+
+    /// サービスの情報を取得する
+    pub async fn get_service_info(&self, index: i32) -> Result<Option<ServiceInfo>, ApiHandleError> {
+        self.call(move |api| api.get_service_info(index)).await
+    }
+
+    /// サービスを設定する
+    pub async fn set_service_by_id(&self, service_id: i32) -> Result<bool, ApiHandleError> {
+        self.call(move |api| api.set_service_by_id(service_id)).await
+    }
+
+    /// BonDriverのファイル名を取得する
+    pub async fn get_driver_name(&self) -> Result<Option<String>, ApiHandleError> {
+        self.call(PluginApi::get_driver_name).await
+    }
+
+    /// 音量を取得する(0-100)
+    pub async fn get_volume(&self) -> Result<i32, ApiHandleError> {
+        self.call(PluginApi::get_volume).await
+    }
+
+    /// 消音状態であるか取得する
+    pub async fn is_mute(&self) -> Result<bool, ApiHandleError> {
+        self.call(PluginApi::is_mute).await
+    }
+
+    /// ステレオモードを取得する
+    pub async fn get_stereo_mode(&self) -> Result<Option<StereoMode>, ApiHandleError> {
+        self.call(PluginApi::get_stereo_mode).await
+    }
+
+    /// 全画面表示の状態を取得する
+    pub async fn is_fullscreen(&self) -> Result<bool, ApiHandleError> {
+        self.call(PluginApi::is_fullscreen).await
+    }
+
+    /// 再生が有効か取得する
+    pub async fn is_preview(&self) -> Result<bool, ApiHandleError> {
+        self.call(PluginApi::is_preview).await
+    }
+
+    /// 待機状態であるか取得する
+    pub async fn is_standby(&self) -> Result<bool, ApiHandleError> {
+        self.call(PluginApi::is_standby).await
+    }
+
+    /// ワンセグモードであるか取得する
+    pub async fn is_one_seg_mode(&self) -> Result<bool, ApiHandleError> {
+        self.call(PluginApi::is_one_seg_mode).await
+    }
+
+    /// 録画の状態を取得する
+    pub async fn get_record_status(&self) -> Result<Option<RecordStatus>, ApiHandleError> {
+        self.call(|api| api.record_status().and_then(|info| info.status())).await
+    }
+
+    /// ステータスを取得する
+    pub async fn status(&self) -> Result<Option<StatusInfo>, ApiHandleError> {
+        self.call(PluginApi::status).await
+    }
+
+    /// ログを記録する
+    pub async fn add_log(&self, text: String, kind: LogKind) -> Result<bool, ApiHandleError> {
+        self.call(move |api| api.add_log_with_kind(text, kind)).await
+    }
+}
+
+/// プラグインの非同期ランタイム
+/// export_plugin! が TVTInitialize で開始して TVTestPlugin::on_runtime_start に渡し、TVTFinalize で finalize の後に破棄します
+/// プラグインのイベントは TVTestEventHandler に渡されるのと同時に events() のストリームにも配信されます
+///
+/// 破棄すると UI スレッドへの呼び出しを終了させてから、タスクの終了を待ちます
+/// 待っている間は UI スレッドがブロックされるため (既定では最大 5 秒)、
+/// 時間のかかるタスクは finalize までに終了させるか、shutdown_timeout で待つ時間を短くしてください
+pub struct PluginRuntime {
+    runtime: Option<Runtime>,
+    dispatcher: ApiDispatcher,
+    events: broadcast::Sender<AsyncEvent>,
+    shutdown_timeout: Duration,
+}
+
+impl PluginRuntime {
+    /// ウィンドウメッセージコールバックを使用してランタイムを開始します
+    pub fn start(api: &PluginApi) -> io::Result<Self> {
+        let dispatcher = ApiDispatcher::install(api)
+            .ok_or_else(|| io::Error::other("failed to set the window message callback"))?;
+
+        Self::with_dispatcher(dispatcher)
+    }
+
+    /// ディスパッチャを指定してランタイムを開始します
+    pub fn with_dispatcher(dispatcher: ApiDispatcher) -> io::Result<Self> {
+        let runtime = Builder::new_multi_thread()
+            .thread_name("tvtest-plugin")
+            .enable_time()
+            .build()?;
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+
+        Ok(Self {
+            runtime: runtime.into(),
+            dispatcher,
+            events,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+        })
+    }
+
+    /// 終了時にタスクの終了を待つ時間を設定します
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// ランタイムのハンドル
+    pub fn handle(&self) -> &Handle {
+        self.runtime.as_ref().unwrap().handle()
+    }
+
+    /// タスクを開始します
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+        where F: std::future::Future + Send + 'static,
+              F::Output: Send + 'static
+    {
+        self.handle().spawn(future)
+    }
+
+    /// 非同期版の PluginApi
+    pub fn api(&self) -> AsyncApi {
+        AsyncApi::new(self.dispatcher.handle())
+    }
+
+    /// ディスパッチャ
+    /// handle_message をウィンドウメッセージコールバックから呼ぶ場合などに使用します
+    pub fn dispatcher(&self) -> &ApiDispatcher {
+        &self.dispatcher
+    }
+
+    /// イベントのストリームを生成します
+    /// 生成した後に配信されたイベントを受け取ります
+    pub fn events(&self) -> EventStream {
+        EventStream {
+            inner: BroadcastStream::new(self.events.subscribe()),
+        }
+    }
+
+    /// イベントを非同期タスクに配信します
+    /// TVTestEventHandler::on_event などから呼び出します
+    pub fn notify(&self, event: &PluginEvent) {
+        // 受信側がいない場合は破棄される
+        let _ = self.events.send(AsyncEvent::from(event));
+    }
+}
+
+impl Drop for PluginRuntime {
+    fn drop(&mut self) {
+        // UI スレッドを待っているタスクが終われるように先にディスパッチャを終了する
+        self.dispatcher.shutdown();
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_timeout(self.shutdown_timeout);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::sync::Mutex;
+    use windows::Win32::Foundation::LRESULT;
+    use crate::message::Message;
+    use crate::mock::MockHost;
+    use super::*;
+
+    #[test]
+    fn async_api_and_events() {
+        let host = MockHost::new();
+        host.on(Message::GetVolume, |_, _| LRESULT(70));
+        let api = host.api();
+
+        let (wake_sender, wake) = mpsc::channel();
+        let wake_sender = Mutex::new(wake_sender);
+        let dispatcher = ApiDispatcher::with_waker(&api, move || {
+            let _ = wake_sender.lock().unwrap().send(());
+        });
+        let runtime = PluginRuntime::with_dispatcher(dispatcher).unwrap().shutdown_timeout(Duration::from_secs(1));
+
+        let async_api = runtime.api();
+        let mut events = runtime.events();
+        let task = runtime.spawn(async move {
+            let volume = async_api.get_volume().await;
+            let event = events.next().await;
+            (volume, event)
+        });
+
+        // UI スレッドの処理
+        wake.recv().unwrap();
+        assert_eq!(runtime.dispatcher().process(), 1);
+        runtime.notify(&PluginEvent::VolumeChange { volume: 30, is_mute: true });
+
+        let (volume, event) = runtime.handle().block_on(task).unwrap();
+        assert_eq!(volume, Ok(70));
+        assert_eq!(event, Some(AsyncEvent::VolumeChange { volume: 30, is_mute: true }));
+    }
+
+    #[test]
+    fn event_stream_skips_lagged_events() {
+        let (sender, receiver) = broadcast::channel(2);
+        let mut stream = EventStream { inner: BroadcastStream::new(receiver) };
+        for id in 0..3 {
+            sender.send(AsyncEvent::Command(id)).unwrap();
+        }
+        drop(sender);
+
+        // Stream として受け取り、受け取れなかった最初のイベントは読み飛ばされる
+        let runtime = Builder::new_current_thread().build().unwrap();
+        let events = runtime.block_on(async {
+            let mut events = Vec::new();
+            while let Some(event) = poll_fn(|cx| Stream::poll_next(Pin::new(&mut stream), cx)).await {
+                events.push(event);
+            }
+            events
+        });
+        assert_eq!(events, vec![AsyncEvent::Command(1), AsyncEvent::Command(2)]);
+    }
+}
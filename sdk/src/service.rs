@@ -1,6 +1,7 @@
 use std::mem::size_of;
 use crate::win32::FixedWideString;
 
+#[derive(Copy, Clone, Eq, PartialEq)]
 #[cfg_attr(test, derive(Debug))]
 pub struct GetServiceInfo {
     pub index: i32,
@@ -9,6 +10,7 @@ pub struct GetServiceInfo {
 
 /// サービスの情報
 #[repr(C)]
+#[derive(Clone)]
 #[cfg_attr(test, derive(Debug))]
 pub struct ServiceInfo {
     /// 構造体のサイズ
@@ -1,8 +1,31 @@
 use std::ffi::c_void;
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::ptr::{self, NonNull};
+use crate::api::PluginApi;
+use crate::win32::{IntoRustString, IntoWideString, WStr};
 use crate::WideStringPtr;
 
+/// BonDriver の検索ディレクトリ (文字列)
+pub const DRIVER_DIRECTORY: &str = "DriverDirectory";
+/// Ini ファイルのパス (文字列)
+pub const INI_FILE_PATH: &str = "IniFilePath";
+/// 録画時の保存先フォルダ (文字列)
+pub const RECORD_FOLDER: &str = "RecordFolder";
+/// 録画のファイル名 (文字列)
+/// %event-name% などの変数が含まれている可能性があります
+pub const RECORD_FILE_NAME: &str = "RecordFileName";
+/// キャプチャの保存先フォルダ (文字列)
+/// 相対パスの可能性があります。その場合実行ファイルの場所が基準です
+pub const CAPTURE_FOLDER: &str = "CaptureFolder";
+/// キャプチャのファイル名 (文字列)
+/// %event-name% などの変数が含まれている可能性があります
+pub const CAPTURE_FILE_NAME: &str = "CaptureFileName";
+
 /// 設定の値の型
 #[repr(u32)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
 pub enum SettingKind {
     /// 未定義
     Undefined,
@@ -25,11 +48,13 @@ pub union SettingValue {
     /// 文字列
     pub string: WideStringPtr,
     /// データ
-    pub data: *const c_void,
+    pub data: *mut c_void,
 }
 
 /// 設定の情報
-pub struct SettingInfo {
+/// 設定名を借用している間だけ使用できます
+#[repr(C, packed)]
+pub struct SettingInfo<'a> {
     /// 設定名
     pub name: WideStringPtr,
     /// 値の型
@@ -37,4 +62,146 @@ pub struct SettingInfo {
     pub value: SettingValue,
     // 値のサイズ (バイト単位)
     pub value_size: u32,
+    _marker: PhantomData<WStr<'a>>,
+}
+
+impl<'a> SettingInfo<'a> {
+    /// 設定名と値の型を指定して生成します
+    pub fn new(name: WStr<'a>, kind: SettingKind, value_size: u32) -> Self {
+        Self {
+            name: name.as_ptr(),
+            kind,
+            value: SettingValue {
+                data: ptr::null_mut(),
+            },
+            value_size,
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// `PluginApi::get_setting` で取得できる型
+pub trait FromSetting: Sized {
+    /// 設定を取得します
+    /// 設定が存在しないか、値の型が一致しない場合は None を返します
+    fn get_setting(api: &PluginApi, name: &str) -> Option<Self>;
+}
+
+impl FromSetting for i32 {
+    fn get_setting(api: &PluginApi, name: &str) -> Option<Self> {
+        let name = name.into_wide_string();
+        let mut info = SettingInfo::new(name.as_wstr()?, SettingKind::Int, size_of::<i32>() as u32);
+
+        if api.get_setting_info(&mut info) && { info.kind } == SettingKind::Int {
+            unsafe { info.value.int }.into()
+        } else {
+            None
+        }
+    }
+}
+
+impl FromSetting for u32 {
+    fn get_setting(api: &PluginApi, name: &str) -> Option<Self> {
+        let name = name.into_wide_string();
+        let mut info = SettingInfo::new(name.as_wstr()?, SettingKind::UnsignedInt, size_of::<u32>() as u32);
+
+        if api.get_setting_info(&mut info) && { info.kind } == SettingKind::UnsignedInt {
+            unsafe { info.value.uint }.into()
+        } else {
+            None
+        }
+    }
+}
+
+impl FromSetting for String {
+    fn get_setting(api: &PluginApi, name: &str) -> Option<Self> {
+        let name = name.into_wide_string();
+
+        // バッファを nullptr にして必要なバイト数 (終端の Null を含む) を取得する
+        let mut info = SettingInfo::new(name.as_wstr()?, SettingKind::String, 0);
+        if !api.get_setting_info(&mut info) || { info.kind } != SettingKind::String {
+            return None;
+        }
+
+        let mut buffer = vec![0u16; info.value_size as usize / size_of::<u16>()];
+        if buffer.is_empty() {
+            return String::new().into();
+        }
+
+        let mut info = SettingInfo::new(name.as_wstr()?, SettingKind::String, (buffer.len() * size_of::<u16>()) as u32);
+        info.value.string = WideStringPtr(NonNull::new(buffer.as_mut_ptr()));
+        if !api.get_setting_info(&mut info) || { info.kind } != SettingKind::String {
+            return None;
+        }
+
+        // 終端の Null 以降は含めない
+        let length = (info.value_size as usize / size_of::<u16>()).min(buffer.len());
+        let end = buffer[..length].iter().position(|c| *c == 0).unwrap_or(length);
+
+        buffer[..end].into_string().into()
+    }
+}
+
+impl FromSetting for Vec<u8> {
+    fn get_setting(api: &PluginApi, name: &str) -> Option<Self> {
+        let name = name.into_wide_string();
+
+        // バッファを nullptr にして必要なバイト数を取得する
+        let mut info = SettingInfo::new(name.as_wstr()?, SettingKind::Data, 0);
+        if !api.get_setting_info(&mut info) || { info.kind } != SettingKind::Data {
+            return None;
+        }
+
+        let mut buffer = vec![0u8; info.value_size as usize];
+        if buffer.is_empty() {
+            return buffer.into();
+        }
+
+        let mut info = SettingInfo::new(name.as_wstr()?, SettingKind::Data, buffer.len() as u32);
+        info.value.data = buffer.as_mut_ptr() as *mut c_void;
+        if !api.get_setting_info(&mut info) || { info.kind } != SettingKind::Data {
+            return None;
+        }
+
+        buffer.truncate(info.value_size as usize);
+        buffer.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Win32::Foundation::LRESULT;
+    use crate::message::Message;
+    use crate::mock::MockHost;
+    use crate::win32::UnsafeIntoRustString;
+    use super::*;
+
+    #[test]
+    fn get_settings() {
+        let host = MockHost::new();
+        host.on(Message::GetSetting, |info, _| {
+            let info = unsafe { &mut *(info.0 as *mut SettingInfo) };
+            let value = "C:\\Rec";
+            match ({ info.name }.read_string().as_deref(), { info.kind }) {
+                (Some("RecordFolder"), SettingKind::String) => {
+                    let size = (value.len() + 1) * size_of::<u16>();
+                    if let Some(buffer) = unsafe { info.value.string }.0 {
+                        let wide = value.into_wide_string();
+                        assert_eq!(info.value_size as usize, size);
+                        unsafe { ptr::copy_nonoverlapping(wide.0.as_ptr(), buffer.as_ptr(), wide.0.len()) };
+                    }
+                    info.value_size = size as u32;
+                },
+                (Some("Volume"), SettingKind::Int) => info.value.int = -3,
+                _ => return LRESULT(0),
+            }
+            LRESULT(1)
+        });
+        let api = host.api();
+
+        assert_eq!(api.get_setting::<i32>("Volume"), Some(-3));
+        assert_eq!(api.get_setting::<String>(RECORD_FOLDER).as_deref(), Some("C:\\Rec"));
+        assert_eq!(api.get_setting::<u32>("Volume"), None);
+        assert_eq!(api.get_setting::<Vec<u8>>("Unknown"), None);
+    }
 }
@@ -0,0 +1,391 @@
+use std::cell::{Cell, RefCell};
+use std::mem::replace;
+use std::rc::Rc;
+use enumflags2::BitFlags;
+use crate::api::PluginApi;
+use crate::channel::ChannelInfo;
+use crate::event::{Event, PluginEvent};
+use crate::event_bus::{EventBus, SubscriptionId};
+use crate::record::RecordStatus;
+use crate::service::{GetServiceInfo, ServiceInfo};
+use crate::stereo_mode::StereoMode;
+
+/// 状態の項目
+#[bitflags]
+#[derive(Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+#[repr(u32)]
+pub enum StateField {
+    /// チャンネル
+    Channel = 0x0001,
+    /// サービス
+    Service = 0x0002,
+    /// BonDriver
+    Driver = 0x0004,
+    /// 音量と消音状態
+    Volume = 0x0008,
+    /// ステレオモード
+    StereoMode = 0x0010,
+    /// 録画状態
+    RecordStatus = 0x0020,
+    /// 全画面表示
+    FullScreen = 0x0040,
+    /// プレビュー表示
+    Preview = 0x0080,
+    /// 待機状態
+    Standby = 0x0100,
+    /// ワンセグモード
+    OneSegMode = 0x0200,
+}
+
+/// TVTest の状態
+#[derive(Clone)]
+#[cfg_attr(test, derive(Debug))]
+pub struct TVTestState {
+    /// 現在のチャンネル
+    pub channel: Option<ChannelInfo>,
+    /// 現在のサービスのインデックスとサービス数
+    pub service: Option<GetServiceInfo>,
+    /// 現在のチャンネルのサービス
+    pub services: Vec<ServiceInfo>,
+    /// BonDriver のファイル名
+    pub driver_name: Option<String>,
+    /// 音量 (0-100)
+    pub volume: i32,
+    /// 消音状態
+    pub is_mute: bool,
+    /// ステレオモード
+    pub stereo_mode: Option<StereoMode>,
+    /// 録画状態
+    pub record_status: Option<RecordStatus>,
+    /// 全画面表示
+    pub is_fullscreen: bool,
+    /// プレビュー表示
+    pub is_preview: bool,
+    /// 待機状態
+    pub is_standby: bool,
+    /// ワンセグモード
+    pub is_one_seg_mode: bool,
+}
+
+impl TVTestState {
+    /// TVTest からすべての項目を取得します
+    pub fn query(api: &PluginApi) -> Self {
+        let mut state = Self {
+            channel: None,
+            service: None,
+            services: Vec::new(),
+            driver_name: None,
+            volume: 0,
+            is_mute: false,
+            stereo_mode: None,
+            record_status: None,
+            is_fullscreen: false,
+            is_preview: false,
+            is_standby: false,
+            is_one_seg_mode: false,
+        };
+        state.refresh(api, BitFlags::all());
+        state
+    }
+
+    /// 指定された項目を TVTest から取得し直します
+    pub fn refresh(&mut self, api: &PluginApi, fields: BitFlags<StateField>) {
+        if fields.contains(StateField::Channel) {
+            self.channel = api.get_current_channel_info();
+        }
+        if fields.contains(StateField::Service) {
+            self.service = api.get_service();
+            let count = self.service.map(|service| service.num_services).unwrap_or(0);
+            self.services = (0..count).filter_map(|index| api.get_service_info(index)).collect();
+        }
+        if fields.contains(StateField::Driver) {
+            self.driver_name = api.get_driver_name();
+        }
+        if fields.contains(StateField::Volume) {
+            self.volume = api.get_volume();
+            self.is_mute = api.is_mute();
+        }
+        if fields.contains(StateField::StereoMode) {
+            self.stereo_mode = api.get_stereo_mode();
+        }
+        if fields.contains(StateField::RecordStatus) {
+            self.record_status = api.record_status().and_then(|info| info.status());
+        }
+        if fields.contains(StateField::FullScreen) {
+            self.is_fullscreen = api.is_fullscreen();
+        }
+        if fields.contains(StateField::Preview) {
+            self.is_preview = api.is_preview();
+        }
+        if fields.contains(StateField::Standby) {
+            self.is_standby = api.is_standby();
+        }
+        if fields.contains(StateField::OneSegMode) {
+            self.is_one_seg_mode = api.is_one_seg_mode();
+        }
+    }
+}
+
+/// 状態の変化を受け取るクロージャ
+pub type StateSubscriber = dyn Fn(&StateModel, StateField);
+
+/// イベントから更新される TVTest の状態のキャッシュ
+/// 値を伴うイベントはその値で更新し、値を伴わないイベントは次に参照されたときに TVTest から取得し直します
+pub struct StateModel {
+    api: PluginApi,
+    state: RefCell<TVTestState>,
+    stale: Cell<BitFlags<StateField>>,
+    next_id: Cell<u64>,
+    subscribers: RefCell<Vec<(SubscriptionId, Rc<StateSubscriber>)>>,
+}
+
+impl StateModel {
+    /// 現在の状態を取得して生成します
+    pub fn new(api: &PluginApi) -> Self {
+        Self {
+            api: api.clone(),
+            state: RefCell::new(TVTestState::query(api)),
+            stale: Cell::new(BitFlags::empty()),
+            next_id: Cell::new(1),
+            subscribers: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// EventBus のイベントで更新されるようにします
+    pub fn attach(model: &Rc<Self>, bus: &EventBus) -> Vec<SubscriptionId> {
+        const EVENTS: [Event; 12] = [
+            Event::ChannelChange,
+            Event::ServiceChange,
+            Event::DriverChange,
+            Event::ServiceUpdate,
+            Event::RecordStatusChange,
+            Event::FullScreenChange,
+            Event::PreviewChange,
+            Event::VolumeChange,
+            Event::StereoModeChange,
+            Event::StandBy,
+            Event::Reset,
+            Event::OneSegModeChanged,
+        ];
+
+        EVENTS.iter().map(|&kind| {
+            let model = Rc::clone(model);
+            bus.subscribe(kind, move |event| {
+                model.update(event);
+                0
+            })
+        }).collect()
+    }
+
+    /// 状態の変化を購読します
+    /// 取得し直す必要がある項目は、参照したときに TVTest から取得されます
+    pub fn subscribe<F: Fn(&StateModel, StateField) + 'static>(&self, callback: F) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id.get());
+        self.next_id.set(id.0 + 1);
+        self.subscribers.borrow_mut().push((id, Rc::new(callback)));
+        id
+    }
+
+    /// 購読を解除します
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        let mut subscribers = self.subscribers.borrow_mut();
+        let len = subscribers.len();
+        subscribers.retain(|(subscriber, _)| *subscriber != id);
+        subscribers.len() != len
+    }
+
+    /// イベントで状態を更新します
+    /// EventBus を使わない場合は TVTestEventHandler::on_event から呼び出します
+    pub fn update(&self, event: &PluginEvent) {
+        let changed = match event {
+            PluginEvent::ChannelChange => self.invalidate(StateField::Channel | StateField::Service),
+            PluginEvent::ServiceChange | PluginEvent::ServiceUpdate => self.invalidate(StateField::Service.into()),
+            PluginEvent::DriverChange => self.invalidate(StateField::Driver | StateField::Channel | StateField::Service),
+            PluginEvent::Reset => self.invalidate(BitFlags::all()),
+            PluginEvent::VolumeChange { volume, is_mute } => self.set(StateField::Volume, |state| {
+                let volume = replace(&mut state.volume, *volume) != *volume;
+                let mute = replace(&mut state.is_mute, *is_mute) != *is_mute;
+                volume || mute
+            }),
+            PluginEvent::StereoModeChange(mode) => self.set(StateField::StereoMode, |state| state.stereo_mode.replace(*mode) != Some(*mode)),
+            PluginEvent::RecordStatusChange(status) => self.set(StateField::RecordStatus, |state| state.record_status.replace(*status) != Some(*status)),
+            PluginEvent::FullScreenChange(value) => self.set(StateField::FullScreen, |state| replace(&mut state.is_fullscreen, *value) != *value),
+            PluginEvent::PreviewChange(value) => self.set(StateField::Preview, |state| replace(&mut state.is_preview, *value) != *value),
+            PluginEvent::StandBy(value) => self.set(StateField::Standby, |state| replace(&mut state.is_standby, *value) != *value),
+            PluginEvent::OneSegModeChanged(value) => self.set(StateField::OneSegMode, |state| replace(&mut state.is_one_seg_mode, *value) != *value),
+            _ => BitFlags::empty(),
+        };
+
+        self.notify(changed);
+    }
+
+    /// 項目を次に参照したときに TVTest から取得し直すようにします
+    pub fn invalidate(&self, fields: BitFlags<StateField>) -> BitFlags<StateField> {
+        self.stale.set(self.stale.get() | fields);
+        fields
+    }
+
+    fn set<F: FnOnce(&mut TVTestState) -> bool>(&self, field: StateField, update: F) -> BitFlags<StateField> {
+        // イベントの値の方が新しいので取得し直す必要はない
+        self.stale.set(self.stale.get() & !BitFlags::from(field));
+
+        if update(&mut self.state.borrow_mut()) {
+            field.into()
+        } else {
+            BitFlags::empty()
+        }
+    }
+
+    fn notify(&self, fields: BitFlags<StateField>) {
+        if fields.is_empty() {
+            return;
+        }
+
+        // 購読者の中から状態を参照できるように借用を解放しておく
+        let subscribers: Vec<_> = self.subscribers.borrow().iter().map(|(_, callback)| Rc::clone(callback)).collect();
+        for field in fields.iter() {
+            for callback in &subscribers {
+                callback(self, field);
+            }
+        }
+    }
+
+    fn read<T, F: FnOnce(&TVTestState) -> T>(&self, fields: BitFlags<StateField>, read: F) -> T {
+        let stale = self.stale.get() & fields;
+        if !stale.is_empty() {
+            self.state.borrow_mut().refresh(&self.api, stale);
+            self.stale.set(self.stale.get() & !stale);
+        }
+
+        read(&self.state.borrow())
+    }
+
+    /// 現在の状態
+    pub fn snapshot(&self) -> TVTestState {
+        self.read(BitFlags::all(), TVTestState::clone)
+    }
+
+    /// 現在のチャンネル
+    pub fn channel(&self) -> Option<ChannelInfo> {
+        self.read(StateField::Channel.into(), |state| state.channel.clone())
+    }
+
+    /// 現在のサービスのインデックスとサービス数
+    pub fn service(&self) -> Option<GetServiceInfo> {
+        self.read(StateField::Service.into(), |state| state.service)
+    }
+
+    /// 現在のチャンネルのサービス
+    pub fn services(&self) -> Vec<ServiceInfo> {
+        self.read(StateField::Service.into(), |state| state.services.clone())
+    }
+
+    /// BonDriver のファイル名
+    pub fn driver_name(&self) -> Option<String> {
+        self.read(StateField::Driver.into(), |state| state.driver_name.clone())
+    }
+
+    /// 音量 (0-100)
+    pub fn volume(&self) -> i32 {
+        self.read(StateField::Volume.into(), |state| state.volume)
+    }
+
+    /// 消音状態
+    pub fn is_mute(&self) -> bool {
+        self.read(StateField::Volume.into(), |state| state.is_mute)
+    }
+
+    /// ステレオモード
+    pub fn stereo_mode(&self) -> Option<StereoMode> {
+        self.read(StateField::StereoMode.into(), |state| state.stereo_mode)
+    }
+
+    /// 録画状態
+    pub fn record_status(&self) -> Option<RecordStatus> {
+        self.read(StateField::RecordStatus.into(), |state| state.record_status)
+    }
+
+    /// 全画面表示
+    pub fn is_fullscreen(&self) -> bool {
+        self.read(StateField::FullScreen.into(), |state| state.is_fullscreen)
+    }
+
+    /// プレビュー表示
+    pub fn is_preview(&self) -> bool {
+        self.read(StateField::Preview.into(), |state| state.is_preview)
+    }
+
+    /// 待機状態
+    pub fn is_standby(&self) -> bool {
+        self.read(StateField::Standby.into(), |state| state.is_standby)
+    }
+
+    /// ワンセグモード
+    pub fn is_one_seg_mode(&self) -> bool {
+        self.read(StateField::OneSegMode.into(), |state| state.is_one_seg_mode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use windows::Win32::Foundation::LRESULT;
+    use crate::message::Message;
+    use crate::mock::MockHost;
+    use super::*;
+
+    #[test]
+    fn update_from_events() {
+        let host = MockHost::new();
+        let channel = Rc::new(Cell::new(10));
+        let current = Rc::clone(&channel);
+        host.on(Message::GetCurrentChannelInfo, move |info, _| {
+            let info = unsafe { &mut *(info.0 as *mut ChannelInfo) };
+            info.channel = current.get();
+            LRESULT(1)
+        });
+        host.on(Message::GetService, |num, _| {
+            unsafe { *(num.0 as *mut i32) = 2 };
+            LRESULT(1)
+        });
+        host.on(Message::GetServiceInfo, |index, info| {
+            let info = unsafe { &mut *(info.0 as *mut ServiceInfo) };
+            info.service_id = 100 + index.0 as u16;
+            LRESULT(1)
+        });
+        host.on(Message::GetVolume, |_, _| LRESULT(50 | (1 << 16)));
+        host.on(Message::GetPreview, |_, _| LRESULT(1));
+        let api = host.api();
+
+        let model = Rc::new(StateModel::new(&api));
+        let state = model.snapshot();
+        assert_eq!(state.channel.map(|channel| channel.channel), Some(10));
+        assert_eq!(state.services.iter().map(|service| service.service_id).collect::<Vec<_>>(), [100, 101]);
+        assert_eq!((state.volume, state.is_mute, state.is_preview), (50, true, true));
+
+        let bus = EventBus::new();
+        StateModel::attach(&model, &bus);
+        let changes = Rc::new(RefCell::new(Vec::new()));
+        let log = Rc::clone(&changes);
+        model.subscribe(move |model, field| {
+            if field == StateField::Channel {
+                log.borrow_mut().push(format!("channel:{}", model.channel().unwrap().channel));
+            } else {
+                log.borrow_mut().push(format!("{:?}", field));
+            }
+        });
+
+        // 値を伴うイベントは TVTest に問い合わせずに更新する
+        let queries = host.sent(Message::GetVolume).len();
+        bus.dispatch(PluginEvent::VolumeChange { volume: 30, is_mute: false });
+        bus.dispatch(PluginEvent::VolumeChange { volume: 30, is_mute: false });
+        assert_eq!((model.volume(), model.is_mute()), (30, false));
+        assert_eq!(host.sent(Message::GetVolume).len(), queries);
+
+        // 値を伴わないイベントは参照されたときに取得し直す
+        channel.set(20);
+        bus.dispatch(PluginEvent::ChannelChange);
+        assert_eq!(*changes.borrow(), ["Volume", "channel:20", "Service"]);
+    }
+}
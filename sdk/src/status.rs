@@ -1,5 +1,13 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::mem::size_of;
+use std::time::{Duration, Instant};
+use crate::api::PluginApi;
+use crate::log::LogKind;
+
 /// ステータス情報
 #[repr(C)]
+#[derive(Copy, Clone)]
 pub struct StatusInfo {
     /// 構造体のサイズ
     pub size: u32,
@@ -17,3 +25,428 @@ pub struct StatusInfo {
     // 予約
     pub reserved: u32,
 }
+
+impl Default for StatusInfo {
+    fn default() -> Self {
+        Self {
+            size: size_of::<StatusInfo>() as u32,
+            signal_level: 0.0,
+            bit_rate: 0,
+            error_packet_count: 0,
+            scramble_packet_count: 0,
+            drop_packet_count: 0,
+            reserved: 0,
+        }
+    }
+}
+
+/// ステータスの標本
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+pub struct StatusSample {
+    /// 取得した時刻
+    pub time: Instant,
+    /// 信号レベル(dB)
+    pub signal_level: f32,
+    /// ビットレート(Bits/Sec)
+    pub bit_rate: u32,
+    /// エラーパケット数(ドロップパケット数を含む)
+    pub error_packet_count: u32,
+    /// 復号漏れパケット数
+    pub scramble_packet_count: u32,
+    /// ドロップパケット数
+    pub drop_packet_count: u32,
+}
+
+impl StatusSample {
+    /// StatusInfo から生成します
+    pub fn new(time: Instant, info: &StatusInfo) -> Self {
+        Self {
+            time,
+            signal_level: info.signal_level,
+            bit_rate: info.bit_rate,
+            error_packet_count: info.error_packet_count,
+            scramble_packet_count: info.scramble_packet_count,
+            drop_packet_count: info.drop_packet_count,
+        }
+    }
+}
+
+/// 前回の標本からの変化
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+pub struct StatusDelta {
+    /// 前回の標本からの経過時間
+    pub interval: Duration,
+    /// 信号レベル(dB)
+    pub signal_level: f32,
+    /// ビットレート(Bits/Sec)
+    pub bit_rate: u32,
+    /// 増えたエラーパケット数
+    pub error_packets: u32,
+    /// 増えた復号漏れパケット数
+    pub scramble_packets: u32,
+    /// 増えたドロップパケット数
+    pub drop_packets: u32,
+}
+
+impl StatusDelta {
+    fn between(previous: &StatusSample, current: &StatusSample) -> Self {
+        // カウンタが減っている場合はリセットされたものとして扱う
+        fn diff(previous: u32, current: u32) -> u32 {
+            current.checked_sub(previous).unwrap_or(current)
+        }
+
+        Self {
+            interval: current.time.saturating_duration_since(previous.time),
+            signal_level: current.signal_level,
+            bit_rate: current.bit_rate,
+            error_packets: diff(previous.error_packet_count, current.error_packet_count),
+            scramble_packets: diff(previous.scramble_packet_count, current.scramble_packet_count),
+            drop_packets: diff(previous.drop_packet_count, current.drop_packet_count),
+        }
+    }
+}
+
+/// ステータスの履歴
+/// 指定された数の標本と、標本間の変化をリングバッファで保持します
+pub struct StatusHistory {
+    capacity: usize,
+    samples: VecDeque<StatusSample>,
+    deltas: VecDeque<StatusDelta>,
+}
+
+impl StatusHistory {
+    /// 保持する標本の数を指定して生成します
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+
+        Self {
+            capacity,
+            samples: VecDeque::with_capacity(capacity),
+            deltas: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// 標本を追加し、前回の標本からの変化を返します
+    pub fn push(&mut self, sample: StatusSample) -> Option<StatusDelta> {
+        let delta = self.samples.back().map(|previous| StatusDelta::between(previous, &sample));
+
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+        if let Some(delta) = delta {
+            if self.deltas.len() == self.capacity {
+                self.deltas.pop_front();
+            }
+            self.deltas.push_back(delta);
+        }
+
+        delta
+    }
+
+    /// 履歴を消去します
+    /// ステータスがリセットされた場合などに呼び出します
+    pub fn clear(&mut self) {
+        self.samples.clear();
+        self.deltas.clear();
+    }
+
+    /// 古い順の標本
+    pub fn samples(&self) -> impl Iterator<Item = &StatusSample> {
+        self.samples.iter()
+    }
+
+    /// 古い順の変化
+    pub fn deltas(&self) -> impl Iterator<Item = &StatusDelta> {
+        self.deltas.iter()
+    }
+
+    /// 最新の標本
+    pub fn latest(&self) -> Option<&StatusSample> {
+        self.samples.back()
+    }
+
+    /// 保持している変化の合計
+    /// interval は合計時間、signal_level と bit_rate は平均になります
+    pub fn total(&self) -> Option<StatusDelta> {
+        let count = self.deltas.len();
+        if count == 0 {
+            return None;
+        }
+
+        let mut total = StatusDelta {
+            interval: Duration::ZERO,
+            signal_level: 0.0,
+            bit_rate: 0,
+            error_packets: 0,
+            scramble_packets: 0,
+            drop_packets: 0,
+        };
+        let mut bit_rate = 0u64;
+        for delta in &self.deltas {
+            total.interval += delta.interval;
+            total.signal_level += delta.signal_level;
+            bit_rate += delta.bit_rate as u64;
+            total.error_packets = total.error_packets.saturating_add(delta.error_packets);
+            total.scramble_packets = total.scramble_packets.saturating_add(delta.scramble_packets);
+            total.drop_packets = total.drop_packets.saturating_add(delta.drop_packets);
+        }
+        total.signal_level /= count as f32;
+        total.bit_rate = (bit_rate / count as u64) as u32;
+
+        total.into()
+    }
+}
+
+/// 警告の閾値
+/// 設定されていない項目は判定されません
+#[derive(Copy, Clone, core::default::Default)]
+pub struct StatusThresholds {
+    /// 信号レベルの下限(dB)
+    pub min_signal_level: Option<f32>,
+    /// ビットレートの下限(Bits/Sec)
+    pub min_bit_rate: Option<u32>,
+    /// 1回の間隔で増えたエラーパケット数の上限
+    pub max_error_packets: Option<u32>,
+    /// 1回の間隔で増えた復号漏れパケット数の上限
+    pub max_scramble_packets: Option<u32>,
+    /// 1回の間隔で増えたドロップパケット数の上限
+    pub max_drop_packets: Option<u32>,
+}
+
+impl StatusThresholds {
+    /// 閾値を超えた項目を返します
+    pub fn check(&self, delta: &StatusDelta) -> Vec<StatusAlarm> {
+        let mut alarms = Vec::new();
+
+        if self.min_signal_level.is_some_and(|min| delta.signal_level < min) {
+            alarms.push(StatusAlarm::LowSignalLevel(delta.signal_level));
+        }
+        if self.min_bit_rate.is_some_and(|min| delta.bit_rate < min) {
+            alarms.push(StatusAlarm::LowBitRate(delta.bit_rate));
+        }
+        if self.max_error_packets.is_some_and(|max| delta.error_packets > max) {
+            alarms.push(StatusAlarm::ErrorPackets(delta.error_packets));
+        }
+        if self.max_scramble_packets.is_some_and(|max| delta.scramble_packets > max) {
+            alarms.push(StatusAlarm::ScramblePackets(delta.scramble_packets));
+        }
+        if self.max_drop_packets.is_some_and(|max| delta.drop_packets > max) {
+            alarms.push(StatusAlarm::DropPackets(delta.drop_packets));
+        }
+
+        alarms
+    }
+}
+
+/// 閾値を超えた項目
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+pub enum StatusAlarm {
+    /// 信号レベルが下限を下回った
+    LowSignalLevel(f32),
+    /// ビットレートが下限を下回った
+    LowBitRate(u32),
+    /// エラーパケットが上限を超えて増えた
+    ErrorPackets(u32),
+    /// 復号漏れパケットが上限を超えて増えた
+    ScramblePackets(u32),
+    /// ドロップパケットが上限を超えて増えた
+    DropPackets(u32),
+}
+
+impl fmt::Display for StatusAlarm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StatusAlarm::LowSignalLevel(level) => write!(f, "信号レベルが低下しています ({:.2} dB)", level),
+            StatusAlarm::LowBitRate(bit_rate) => write!(f, "ビットレートが低下しています ({:.2} Mbps)", *bit_rate as f64 / 1_000_000.0),
+            StatusAlarm::ErrorPackets(count) => write!(f, "エラーパケットが {} 個増えました", count),
+            StatusAlarm::ScramblePackets(count) => write!(f, "復号漏れパケットが {} 個増えました", count),
+            StatusAlarm::DropPackets(count) => write!(f, "ドロップパケットが {} 個増えました", count),
+        }
+    }
+}
+
+type StatusAlarmHandler = Box<dyn FnMut(&StatusAlarm, &StatusDelta)>;
+
+/// 信号とストリームの状態の監視
+/// タイマーなどから poll() を定期的に呼び出すと、間隔ごとにステータスを取得して履歴に追加し、
+/// 閾値を超えた場合はログへの記録やコールバックで通知します
+/// ステータスがリセットされた場合は on_status_reset から reset() を呼び出します
+pub struct StatusMonitor {
+    interval: Duration,
+    history: StatusHistory,
+    thresholds: StatusThresholds,
+    handler: Option<StatusAlarmHandler>,
+    log: bool,
+    last_sampled: Option<Instant>,
+}
+
+impl StatusMonitor {
+    /// 取得する間隔と、履歴に保持する標本の数を指定して生成します
+    pub fn new(interval: Duration, capacity: usize) -> Self {
+        Self {
+            interval,
+            history: StatusHistory::new(capacity),
+            thresholds: StatusThresholds::default(),
+            handler: None,
+            log: false,
+            last_sampled: None,
+        }
+    }
+
+    /// 警告の閾値を設定します
+    pub fn thresholds(mut self, thresholds: StatusThresholds) -> Self {
+        self.thresholds = thresholds;
+        self
+    }
+
+    /// 閾値を超えた場合に呼ばれるクロージャを設定します
+    pub fn on_alarm<F>(mut self, handler: F) -> Self
+        where F: FnMut(&StatusAlarm, &StatusDelta) + 'static
+    {
+        self.handler = Some(Box::new(handler));
+        self
+    }
+
+    /// 閾値を超えた場合に TVTest のログに警告として記録します
+    pub fn log_alarms(mut self) -> Self {
+        self.log = true;
+        self
+    }
+
+    /// ステータスの履歴
+    pub fn history(&self) -> &StatusHistory {
+        &self.history
+    }
+
+    /// 標本を追加し、閾値を超えた項目を返します
+    /// ログへの記録は行われず、コールバックのみ呼ばれます
+    pub fn record(&mut self, sample: StatusSample) -> Vec<StatusAlarm> {
+        self.last_sampled = sample.time.into();
+        let delta = match self.history.push(sample) {
+            Some(delta) => delta,
+            None => return Vec::new(),
+        };
+
+        let alarms = self.thresholds.check(&delta);
+        if let Some(handler) = &mut self.handler {
+            for alarm in &alarms {
+                handler(alarm, &delta);
+            }
+        }
+
+        alarms
+    }
+
+    /// 前回の取得から間隔が経過していればステータスを取得します
+    /// 取得した場合は閾値を超えた項目を返します
+    pub fn poll(&mut self, api: &PluginApi) -> Option<Vec<StatusAlarm>> {
+        let now = Instant::now();
+        if self.last_sampled.is_some_and(|last| now.saturating_duration_since(last) < self.interval) {
+            return None;
+        }
+
+        self.sample(api, now)
+    }
+
+    /// ステータスを取得して標本を追加します
+    pub fn sample(&mut self, api: &PluginApi, time: Instant) -> Option<Vec<StatusAlarm>> {
+        let info = api.status()?;
+        let alarms = self.record(StatusSample::new(time, &info));
+
+        if self.log {
+            for alarm in &alarms {
+                api.add_log_with_kind(alarm.to_string(), LogKind::Warning);
+            }
+        }
+
+        alarms.into()
+    }
+
+    /// 履歴を消去します
+    /// 次の標本からカウンタの変化を計算し直します
+    pub fn reset(&mut self) {
+        self.history.clear();
+        self.last_sampled = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use windows::Win32::Foundation::{LPARAM, LRESULT};
+    use crate::message::Message;
+    use crate::mock::MockHost;
+    use super::*;
+
+    fn sample(start: Instant, seconds: u64, errors: u32, drops: u32, signal_level: f32) -> StatusSample {
+        StatusSample {
+            time: start + Duration::from_secs(seconds),
+            signal_level,
+            bit_rate: 16_000_000,
+            error_packet_count: errors,
+            scramble_packet_count: 0,
+            drop_packet_count: drops,
+        }
+    }
+
+    #[test]
+    fn history_and_alarms() {
+        let start = Instant::now();
+        let alarms = Rc::new(RefCell::new(Vec::new()));
+        let received = Rc::clone(&alarms);
+        let mut monitor = StatusMonitor::new(Duration::from_secs(1), 3)
+            .thresholds(StatusThresholds {
+                min_signal_level: Some(20.0),
+                max_error_packets: Some(10),
+                ..StatusThresholds::default()
+            })
+            .on_alarm(move |alarm, delta| received.borrow_mut().push((*alarm, delta.interval)));
+
+        assert!(monitor.record(sample(start, 0, 5, 1, 30.0)).is_empty());
+        assert!(monitor.record(sample(start, 1, 10, 1, 30.0)).is_empty());
+        assert_eq!(monitor.record(sample(start, 3, 30, 4, 15.0)), vec![StatusAlarm::LowSignalLevel(15.0), StatusAlarm::ErrorPackets(20)]);
+        // カウンタがリセットされた場合は現在の値を増分とする
+        assert!(monitor.record(sample(start, 4, 2, 0, 30.0)).is_empty());
+
+        let history = monitor.history();
+        assert_eq!(history.samples().count(), 3);
+        assert_eq!(history.deltas().map(|delta| delta.error_packets).collect::<Vec<_>>(), vec![5, 20, 2]);
+        let total = history.total().unwrap();
+        assert_eq!((total.interval, total.error_packets, total.drop_packets, total.signal_level), (Duration::from_secs(4), 27, 3, 25.0));
+        assert_eq!(*alarms.borrow(), vec![(StatusAlarm::LowSignalLevel(15.0), Duration::from_secs(2)), (StatusAlarm::ErrorPackets(20), Duration::from_secs(2))]);
+    }
+
+    #[test]
+    fn sample_and_log() {
+        let host = MockHost::new();
+        let errors = Rc::new(RefCell::new(0));
+        let counter = Rc::clone(&errors);
+        host.on(Message::GetStatus, move |info, _| {
+            let info = unsafe { &mut *(info.0 as *mut StatusInfo) };
+            assert_eq!(info.size, size_of::<StatusInfo>() as u32);
+            *counter.borrow_mut() += 100;
+            info.error_packet_count = *counter.borrow();
+            LRESULT(1)
+        });
+        host.on(Message::AddLog, |_, _| LRESULT(1));
+        let api = host.api();
+
+        let start = Instant::now();
+        let mut monitor = StatusMonitor::new(Duration::from_secs(1), 10)
+            .thresholds(StatusThresholds { max_error_packets: Some(50), ..StatusThresholds::default() })
+            .log_alarms();
+        assert_eq!(monitor.sample(&api, start), Some(vec![]));
+        assert_eq!(monitor.sample(&api, start + Duration::from_secs(1)), Some(vec![StatusAlarm::ErrorPackets(100)]));
+        // 間隔が経過していないので取得しない
+        assert_eq!(monitor.poll(&api), None);
+
+        let logs = host.sent(Message::AddLog);
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].1, LPARAM(LogKind::Warning as isize));
+    }
+}
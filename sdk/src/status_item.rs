@@ -1,4 +1,9 @@
+use std::mem::size_of;
 use enumflags2::BitFlags;
+use windows::Win32::Foundation::{HWND, LPARAM, POINT, RECT};
+use windows::Win32::Graphics::Gdi::HDC;
+use crate::api::PluginApi;
+use crate::win32::{IntoWideString, WideString};
 use crate::WideStringPtr;
 
 /// ステータス項目のフラグ
@@ -48,14 +53,490 @@ pub struct StatusItemInfo {
     pub min_height: i32,
 }
 
-#[repr(C)]
+/// ステータス項目の幅をフォントサイズから求めます
+pub const fn status_item_width_by_font_size(size: i32) -> i32 {
+    size * -1000
+}
+
+/// ステータス項目の状態フラグ
+#[bitflags]
+#[derive(Copy, Clone, Eq, PartialEq)]
 #[cfg_attr(test, derive(Debug))]
-pub struct StatusItemDrawInfo;
+#[repr(u32)]
+pub enum StatusItemState {
+    /// 可視
+    Visible = 0x00000001,
+    /// フォーカスが当たっている
+    Hot     = 0x00000002,
+}
 
+/// ステータス項目の設定のマスク
+#[bitflags]
+#[derive(Copy, Clone, Eq, PartialEq)]
+#[repr(u32)]
+pub enum StatusItemSetInfoMask {
+    /// state_mask / state を設定
+    State = 0x00000001,
+    /// style_mask / style を設定
+    Style = 0x00000002,
+}
+
+/// ステータス項目の設定の情報
 #[repr(C)]
+pub struct StatusItemSetInfo {
+    /// 構造体のサイズ
+    pub size: u32,
+    /// 設定する情報のマスク
+    pub mask: BitFlags<StatusItemSetInfoMask>,
+    /// 項目の識別子
+    pub id: i32,
+    /// 状態フラグのマスク
+    pub state_mask: BitFlags<StatusItemState>,
+    /// 状態フラグ
+    pub state: BitFlags<StatusItemState>,
+    /// スタイルフラグのマスク
+    pub style_mask: BitFlags<StatusItemStyle>,
+    /// スタイルフラグ
+    pub style: BitFlags<StatusItemStyle>,
+}
+
+impl StatusItemSetInfo {
+    /// 項目の識別子を指定して生成します
+    pub fn new(id: i32) -> Self {
+        Self {
+            size: size_of::<StatusItemSetInfo>() as u32,
+            mask: BitFlags::empty(),
+            id,
+            state_mask: BitFlags::empty(),
+            state: BitFlags::empty(),
+            style_mask: BitFlags::empty(),
+            style: BitFlags::empty(),
+        }
+    }
+}
+
+/// ステータス項目の情報取得のマスク
+#[bitflags]
+#[derive(Copy, Clone, Eq, PartialEq)]
+#[repr(u32)]
+pub enum StatusItemGetInfoMask {
+    /// state を取得
+    State       = 0x00000001,
+    /// hwnd を取得
+    Hwnd        = 0x00000002,
+    /// item_rect を取得
+    ItemRect    = 0x00000004,
+    /// content_rect を取得
+    ContentRect = 0x00000008,
+    /// style を取得
+    Style       = 0x00000010,
+}
+
+/// ステータス項目の情報取得
+#[repr(C, packed)]
+pub struct StatusItemGetInfo {
+    /// 構造体のサイズ
+    pub size: u32,
+    /// 取得する情報のマスク
+    pub mask: BitFlags<StatusItemGetInfoMask>,
+    /// 項目の識別子
+    pub id: i32,
+    /// 状態フラグ
+    pub state: BitFlags<StatusItemState>,
+    /// ウィンドウハンドル
+    pub hwnd: HWND,
+    /// 項目の領域
+    pub item_rect: RECT,
+    /// 項目の余白を除いた領域
+    pub content_rect: RECT,
+    /// スタイルフラグ
+    pub style: BitFlags<StatusItemStyle>,
+}
+
+impl StatusItemGetInfo {
+    /// 項目の識別子と取得する情報を指定して生成します
+    pub fn new(id: i32, mask: BitFlags<StatusItemGetInfoMask>) -> Self {
+        Self {
+            size: size_of::<StatusItemGetInfo>() as u32,
+            mask,
+            id,
+            state: BitFlags::empty(),
+            hwnd: HWND(0),
+            item_rect: RECT::default(),
+            content_rect: RECT::default(),
+            style: BitFlags::empty(),
+        }
+    }
+}
+
+/// ステータス項目の通知
+#[repr(u32)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
+pub enum StatusItemNotify {
+    /// 再描画する
+    Redraw,
+}
+
+/// ステータス項目描画フラグ
+#[bitflags]
+#[derive(Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+#[repr(u16)]
+pub enum StatusItemDrawFlag {
+    /// プレビュー(設定ダイアログでの表示)
+    Preview = 0x0001,
+}
+
+/// ステータス項目描画状態フラグ
+#[bitflags]
+#[derive(Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+#[repr(u16)]
+pub enum StatusItemDrawState {
+    /// フォーカスが当たっている
+    Hot = 0x0001,
+}
+
+/// ステータス項目の描画情報
+/// 描画はダブルバッファリングによって行われるため、
+/// item_rect / draw_rect で渡される位置は表示上の位置とは異なっています
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+#[cfg_attr(test, derive(Debug))]
+pub struct StatusItemDrawInfo {
+    /// 項目の識別子
+    pub id: i32,
+    /// 各種フラグ
+    pub flags: BitFlags<StatusItemDrawFlag>,
+    /// 状態フラグ
+    pub state: BitFlags<StatusItemDrawState>,
+    /// スタイル
+    pub style: WideStringPtr,
+    /// 描画先DC
+    pub hdc: HDC,
+    /// 項目の領域
+    pub item_rect: RECT,
+    /// 描画する領域
+    pub draw_rect: RECT,
+    /// 色
+    pub color: u32,
+}
+
+/// ステータス項目のイベント
+#[repr(u32)]
+#[derive(Copy, Clone, PartialEq, Eq, TryFromPrimitive)]
 #[cfg_attr(test, derive(Debug))]
-pub struct StatusItemEventInfo;
+pub enum StatusItemEvent {
+    /// 項目が作成された
+    Created = 1,
+    /// 項目の表示状態が変わった
+    VisibilityChanged,
+    /// フォーカスが当たった
+    Enter,
+    /// フォーカスが離れた
+    Leave,
+    /// 項目の大きさが変わった
+    SizeChanged,
+    /// 更新タイマー
+    UpdateTimer,
+    /// スタイルが変わった(DPI の変更など)
+    StyleChanged,
+    /// フォントが変わった
+    FontChanged,
+}
 
+/// ステータス項目の通知情報
 #[repr(C)]
 #[cfg_attr(test, derive(Debug))]
-pub struct StatusItemMouseEventInfo;
+pub struct StatusItemEventInfo {
+    /// 項目の識別子
+    pub id: i32,
+    /// イベントの種類 (StatusItemEvent の値)
+    /// 未知の値が渡される可能性があるため、event() で取得してください
+    pub event: u32,
+    /// パラメータ
+    pub param: LPARAM,
+}
+
+impl StatusItemEventInfo {
+    /// イベントの種類
+    /// 未知の値の場合は None を返します
+    pub fn event(&self) -> Option<StatusItemEvent> {
+        StatusItemEvent::try_from(self.event).ok()
+    }
+}
+
+/// ステータス項目のマウス操作の種類
+/// Down か DoubleClick が送られた際に SetCapture() でマウスキャプチャが行われると、
+/// キャプチャが解除された時に CaptureRelease が送られます
+#[repr(u32)]
+#[derive(Copy, Clone, PartialEq, Eq, TryFromPrimitive)]
+#[cfg_attr(test, derive(Debug))]
+pub enum StatusItemMouseAction {
+    /// 左ボタンが押された
+    LeftDown = 1,
+    /// 左ボタンが離された
+    LeftUp,
+    /// 左ダブルクリック
+    LeftDoubleClick,
+    /// 右ボタンが押された
+    RightDown,
+    /// 右ボタンが離された
+    RightUp,
+    /// 右ダブルクリック
+    RightDoubleClick,
+    /// 中央ボタンが押された
+    MiddleDown,
+    /// 中央ボタンが離された
+    MiddleUp,
+    /// 中央ダブルクリック
+    MiddleDoubleClick,
+    /// カーソル移動
+    Move,
+    /// ホイール
+    Wheel,
+    /// 横ホイール
+    HorizontalWheel,
+    /// キャプチャが解除された
+    CaptureRelease,
+}
+
+/// ステータス項目のマウスイベント情報
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+#[cfg_attr(test, derive(Debug))]
+pub struct StatusItemMouseEventInfo {
+    /// 項目の識別子
+    pub id: i32,
+    /// マウス操作の種類 (StatusItemMouseAction の値)
+    /// 未知の値が渡される可能性があるため、action() で取得してください
+    pub action: u32,
+    /// ウィンドウハンドル
+    pub hwnd: HWND,
+    /// カーソル位置(クライアント座標)
+    pub cursor_pos: POINT,
+    /// 項目の領域
+    pub item_rect: RECT,
+    /// 項目の余白を除いた領域
+    pub content_rect: RECT,
+    /// ホイール移動量
+    pub wheel_delta: i32,
+}
+
+impl StatusItemMouseEventInfo {
+    /// マウス操作の種類
+    /// 未知の値の場合は None を返します
+    pub fn action(&self) -> Option<StatusItemMouseAction> {
+        StatusItemMouseAction::try_from(self.action).ok()
+    }
+}
+
+/// ステータス項目の処理
+/// StatusItems に登録すると、識別子の一致するイベントが振り分けられます
+#[allow(unused_variables)]
+pub trait StatusItem {
+    /// 項目を描画します
+    fn draw(&self, info: &StatusItemDrawInfo) -> bool;
+    /// 更新タイマー (StatusItemFlag::TimerUpdate を指定した場合に定期的に呼ばれます)
+    fn update_timer(&self) -> bool { false }
+    /// 項目の表示状態が変わった
+    fn visibility_changed(&self, visible: bool) -> bool { false }
+    /// マウスイベント
+    fn mouse_event(&self, info: &StatusItemMouseEventInfo) -> bool { false }
+    /// その他の通知
+    fn notify(&self, info: &StatusItemEventInfo) -> bool { false }
+}
+
+/// ステータス項目の登録内容
+pub struct StatusItemBuilder {
+    id: i32,
+    id_text: String,
+    name: String,
+    flags: BitFlags<StatusItemFlag>,
+    style: BitFlags<StatusItemStyle>,
+    min_width: i32,
+    max_width: i32,
+    default_width: i32,
+    min_height: i32,
+}
+
+impl StatusItemBuilder {
+    /// 識別子、識別子文字列、名前を指定します
+    pub fn new(id: i32, id_text: &str, name: &str) -> Self {
+        Self {
+            id,
+            id_text: id_text.to_string(),
+            name: name.to_string(),
+            flags: BitFlags::empty(),
+            style: BitFlags::empty(),
+            min_width: 0,
+            max_width: -1,
+            default_width: 0,
+            min_height: 0,
+        }
+    }
+
+    /// 各種フラグを設定します
+    pub fn flags(mut self, flags: BitFlags<StatusItemFlag>) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// スタイルフラグを設定します
+    pub fn style(mut self, style: BitFlags<StatusItemStyle>) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// 幅を設定します
+    /// `max_width` に -1 を指定すると制限なし、`default_width` に負数を指定するとフォントの高さの-1/1000単位になります
+    pub fn width(mut self, min_width: i32, max_width: i32, default_width: i32) -> Self {
+        self.min_width = min_width;
+        self.max_width = max_width;
+        self.default_width = default_width;
+        self
+    }
+
+    /// 最小の高さを設定します
+    pub fn min_height(mut self, min_height: i32) -> Self {
+        self.min_height = min_height;
+        self
+    }
+}
+
+struct RegisteredStatusItem {
+    id: i32,
+    item: Box<dyn StatusItem>,
+}
+
+/// ステータス項目の一覧
+/// on_status_item_draw / on_status_item_notify / on_status_item_mouse_event から
+/// draw() / notify() / mouse_event() を呼び出して各項目に振り分けます
+#[derive(core::default::Default)]
+pub struct StatusItems {
+    items: Vec<RegisteredStatusItem>,
+}
+
+impl StatusItems {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// TVTest にステータス項目を登録します
+    pub fn register<T: StatusItem + 'static>(&mut self, api: &PluginApi, builder: StatusItemBuilder, item: T) -> bool {
+        let id_text: WideString = builder.id_text.into_wide_string();
+        let name: WideString = builder.name.into_wide_string();
+        let info = StatusItemInfo {
+            size: size_of::<StatusItemInfo>() as u32,
+            flags: builder.flags,
+            style: builder.style,
+            id: builder.id,
+            id_text: id_text.to_wide_string_ptr(),
+            name: name.to_wide_string_ptr(),
+            min_width: builder.min_width,
+            max_width: builder.max_width,
+            default_width: builder.default_width,
+            min_height: builder.min_height,
+        };
+        if !api.register_status_item(&info) {
+            return false;
+        }
+
+        self.items.retain(|registered| registered.id != builder.id);
+        self.items.push(RegisteredStatusItem {
+            id: builder.id,
+            item: Box::new(item),
+        });
+        true
+    }
+
+    fn find(&self, id: i32) -> Option<&dyn StatusItem> {
+        self.items.iter()
+            .find(|registered| registered.id == id)
+            .map(|registered| registered.item.as_ref())
+    }
+
+    /// 項目を描画します
+    pub fn draw(&self, info: &StatusItemDrawInfo) -> bool {
+        self.find(info.id).is_some_and(|item| item.draw(info))
+    }
+
+    /// 通知を振り分けます
+    pub fn notify(&self, info: &StatusItemEventInfo) -> bool {
+        self.find(info.id).is_some_and(|item| match info.event() {
+            Some(StatusItemEvent::UpdateTimer) => item.update_timer(),
+            Some(StatusItemEvent::VisibilityChanged) => item.visibility_changed(info.param.0 != 0),
+            _ => item.notify(info),
+        })
+    }
+
+    /// マウスイベントを振り分けます
+    pub fn mouse_event(&self, info: &StatusItemMouseEventInfo) -> bool {
+        self.find(info.id).is_some_and(|item| item.mouse_event(info))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use windows::Win32::Foundation::LRESULT;
+    use crate::message::Message;
+    use crate::mock::MockHost;
+    use super::*;
+
+    struct Clock {
+        updates: Rc<Cell<u32>>,
+        visible: Rc<Cell<bool>>,
+    }
+
+    impl StatusItem for Clock {
+        fn draw(&self, _: &StatusItemDrawInfo) -> bool { true }
+
+        fn update_timer(&self) -> bool {
+            self.updates.set(self.updates.get() + 1);
+            true
+        }
+
+        fn visibility_changed(&self, visible: bool) -> bool {
+            self.visible.set(visible);
+            true
+        }
+    }
+
+    #[test]
+    fn route_events_by_id() {
+        let host = MockHost::new();
+        host.on(Message::RegisterStatusItem, |info, _| {
+            let info = unsafe { &*(info.0 as *const StatusItemInfo) };
+            assert_eq!(info.size, size_of::<StatusItemInfo>() as u32);
+            assert!(info.flags.contains(StatusItemFlag::TimerUpdate));
+            LRESULT(1)
+        });
+        let api = host.api();
+
+        let (updates, visible) = (Rc::new(Cell::new(0)), Rc::new(Cell::new(false)));
+        let mut items = StatusItems::new();
+        let builder = StatusItemBuilder::new(1, "Clock", "時計")
+            .flags(StatusItemFlag::TimerUpdate.into())
+            .width(0, -1, status_item_width_by_font_size(5));
+        assert!(items.register(&api, builder, Clock {
+            updates: Rc::clone(&updates),
+            visible: Rc::clone(&visible),
+        }));
+
+        let event = |id, event: StatusItemEvent, param| StatusItemEventInfo { id, event: event as u32, param: LPARAM(param) };
+        assert!(items.notify(&event(1, StatusItemEvent::UpdateTimer, 0)));
+        assert!(items.notify(&event(1, StatusItemEvent::VisibilityChanged, 1)));
+        assert!(!items.notify(&event(2, StatusItemEvent::UpdateTimer, 0)));
+        assert!(!items.notify(&event(1, StatusItemEvent::FontChanged, 0)));
+        assert_eq!(updates.get(), 1);
+        assert!(visible.get());
+
+        // 未知のイベントは項目の notify() に渡される
+        let unknown = StatusItemEventInfo { id: 1, event: 0x100, param: LPARAM(0) };
+        assert_eq!(unknown.event(), None);
+        assert!(!items.notify(&unknown));
+    }
+}
@@ -1,6 +1,7 @@
 /// ステレオモード
 #[repr(isize)]
-#[derive(UnsafeFromPrimitive)]
+#[derive(Copy, Clone, Eq, PartialEq, UnsafeFromPrimitive, TryFromPrimitive)]
+#[cfg_attr(test, derive(Debug))]
 pub enum StereoMode {
     /// ステレオ
     Stereo,
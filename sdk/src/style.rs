@@ -1,7 +1,12 @@
+use std::marker::PhantomData;
+use std::mem::size_of;
+use crate::win32::WStr;
 use crate::WideStringPtr;
 
 /// スタイル値の単位
 #[repr(u32)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
 pub enum StyleUnit {
     /// 未定義
     Undefined,
@@ -16,8 +21,8 @@ pub enum StyleUnit {
 }
 
 /// スタイル値の情報
-#[repr(C)]
-pub struct StyleValueInfo {
+#[repr(C, packed)]
+pub struct StyleValueInfo<'a> {
     /// 構造体のサイズ
     pub size: u32,
     /// 各種フラグ(現在は常に0)
@@ -30,4 +35,47 @@ pub struct StyleValueInfo {
     pub dpi: i32,
     /// 取得された値
     pub value: i32,
+    _marker: PhantomData<WStr<'a>>,
+}
+
+impl<'a> StyleValueInfo<'a> {
+    /// スタイル名と取得する値の単位を指定して生成します
+    pub fn new(name: WStr<'a>, unit: StyleUnit, dpi: i32) -> Self {
+        Self {
+            size: size_of::<StyleValueInfo>() as u32,
+            flags: 0,
+            name: name.as_ptr(),
+            unit,
+            dpi,
+            value: 0,
+            _marker: PhantomData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Win32::Foundation::LRESULT;
+    use crate::message::Message;
+    use crate::mock::MockHost;
+    use super::*;
+
+    #[test]
+    fn get_style_value_pixels() {
+        let host = MockHost::new();
+        host.on(Message::GetStyleValue, |info, _| {
+            let info = unsafe { &mut *(info.0 as *mut StyleValueInfo) };
+            if { info.unit } != StyleUnit::PhysicalPixel {
+                return LRESULT(0);
+            }
+            // 論理ピクセルの 4 を DPI に応じて変換する
+            info.value = 4 * info.dpi / 96;
+            LRESULT(1)
+        });
+        let api = host.api();
+
+        assert_eq!(api.get_style_value_pixels("status-bar.item.padding.left", 144), Some(6));
+        assert_eq!(api.get_style_value("status-bar.item.padding.left", StyleUnit::Point, 96), None);
+        assert_eq!(host.sent(Message::GetStyleValue).len(), 2);
+    }
 }
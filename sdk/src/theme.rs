@@ -1,8 +1,14 @@
+use std::marker::PhantomData;
+use std::mem::size_of;
 use enumflags2::BitFlags;
 use windows::Win32::Foundation::RECT;
 use windows::Win32::Graphics::Gdi::{HBITMAP, HDC};
+use crate::win32::WStr;
 use crate::WideStringPtr;
 
+/// デフォルトの色を表す COLORREF
+pub const CLR_INVALID: u32 = 0xFFFFFFFF;
+
 /// テーマ描画フラグ
 #[bitflags]
 #[derive(Copy, Clone, Eq, PartialEq)]
@@ -13,8 +19,8 @@ pub enum ThemeDrawBackgroundFlag {
 }
 
 /// テーマの背景描画情報
-#[repr(C)]
-pub struct ThemeDrawBackgroundInfo {
+#[repr(C, packed)]
+pub struct ThemeDrawBackgroundInfo<'a> {
     /// 構造体のサイズ
     pub size: u32,
     /// 各種フラグ
@@ -27,11 +33,26 @@ pub struct ThemeDrawBackgroundInfo {
     pub draw_rect: RECT,
     /// DPI の指定(0でメインウィンドウと同じ)
     pub dpi: i32,
+    _marker: PhantomData<WStr<'a>>,
+}
+
+impl<'a> ThemeDrawBackgroundInfo<'a> {
+    pub fn new(style: WStr<'a>, hdc: HDC, draw_rect: RECT, dpi: i32) -> Self {
+        Self {
+            size: size_of::<ThemeDrawBackgroundInfo>() as u32,
+            flags: BitFlags::empty(),
+            style: style.as_ptr(),
+            hdc,
+            draw_rect,
+            dpi,
+            _marker: PhantomData,
+        }
+    }
 }
 
 /// テーマの文字列描画情報
-#[repr(C)]
-pub struct ThemeDrawTextInfo {
+#[repr(C, packed)]
+pub struct ThemeDrawTextInfo<'a> {
     /// 構造体のサイズ
     pub size: u32,
     /// 各種フラグ(現在は常に0)
@@ -48,11 +69,28 @@ pub struct ThemeDrawTextInfo {
     pub draw_flags: u32,
     /// 描画する色(CLR_INVALID でデフォルトの色)
     pub color: u32,
+    _marker: PhantomData<WStr<'a>>,
+}
+
+impl<'a> ThemeDrawTextInfo<'a> {
+    pub fn new(style: WStr<'a>, hdc: HDC, text: WStr<'a>, draw_rect: RECT, draw_flags: u32, color: Option<u32>) -> Self {
+        Self {
+            size: size_of::<ThemeDrawTextInfo>() as u32,
+            flags: 0,
+            style: style.as_ptr(),
+            hdc,
+            text: text.as_ptr(),
+            draw_rect,
+            draw_flags,
+            color: color.unwrap_or(CLR_INVALID),
+            _marker: PhantomData,
+        }
+    }
 }
 
 /// テーマのアイコン描画情報
-#[repr(C)]
-pub struct ThemeDrawIconInfo {
+#[repr(C, packed)]
+pub struct ThemeDrawIconInfo<'a> {
     /// 構造体のサイズ
     pub size: u32,
     /// 各種フラグ(現在は常に0)
@@ -73,4 +111,50 @@ pub struct ThemeDrawIconInfo {
     pub opacity: u8,
     /// 予約領域
     pub reserved: [u8; 3],
+    _marker: PhantomData<WStr<'a>>,
+}
+
+impl<'a> ThemeDrawIconInfo<'a> {
+    pub fn new(style: WStr<'a>, hdc: HDC, dest_rect: RECT, hbm: HBITMAP, src_rect: RECT, color: Option<u32>, opacity: u8) -> Self {
+        Self {
+            size: size_of::<ThemeDrawIconInfo>() as u32,
+            flags: 0,
+            style: style.as_ptr(),
+            hdc,
+            hbm,
+            dest_rect,
+            src_rect,
+            color: color.unwrap_or(CLR_INVALID),
+            opacity,
+            reserved: [0; 3],
+            _marker: PhantomData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Win32::Foundation::LRESULT;
+    use crate::message::Message;
+    use crate::mock::MockHost;
+    use crate::win32::UnsafeIntoRustString;
+    use super::*;
+
+    #[test]
+    fn theme_draw_text_passes_strings() {
+        let host = MockHost::new();
+        host.on(Message::ThemeDrawText, |info, _| {
+            let info = unsafe { &*(info.0 as *const ThemeDrawTextInfo) };
+            let (style, text) = (info.style.read_string(), info.text.read_string());
+            if (style.as_deref(), text.as_deref(), { info.color }) != (Some("panel.tab"), Some("番組表"), CLR_INVALID) {
+                return LRESULT(0);
+            }
+            LRESULT(1)
+        });
+        let api = host.api();
+
+        assert!(api.theme_draw_text("panel.tab", HDC::default(), "番組表", &RECT::default(), 0, None));
+        assert!(!api.theme_draw_text("panel.tab", HDC::default(), "番組表", &RECT::default(), 0, Some(0)));
+        assert_eq!(host.sent(Message::ThemeDrawText).len(), 2);
+    }
 }
@@ -0,0 +1,559 @@
+use std::mem::size_of;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use enumflags2::BitFlags;
+use windows::Win32::Foundation::{FILETIME, SYSTEMTIME};
+
+/// EPG 日時の UTC からのオフセット(秒)
+pub const EPG_TIME_OFFSET: i64 = 9 * 60 * 60;
+
+/// 100ナノ秒単位
+const TICKS_PER_SECOND: i64 = 10_000_000;
+const TICKS_PER_MILLISECOND: i64 = 10_000;
+/// 1601/01/01 から 1970/01/01 までの 100ナノ秒単位の時間
+const FILETIME_UNIX_EPOCH: i64 = 116_444_736_000_000_000;
+
+/// 日時変換のフラグ
+#[bitflags]
+#[derive(Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+#[repr(u32)]
+pub enum ConvertTimeFlag {
+    /// FILETIME から変換
+    FromFileTime = 0x0001,
+    /// FILETIME へ変換
+    ToFileTime = 0x0002,
+    /// オフセットの指定
+    Offset = 0x0004,
+}
+
+/// 日時変換の種類
+#[repr(u32)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
+pub enum ConvertTimeType {
+    /// UTC
+    Utc,
+    /// ローカル
+    Local,
+    /// EPG 日時(UTC+9)
+    Epg,
+    /// EPG の表示用(変換先としてのみ指定可能)
+    EpgDisplay,
+}
+
+/// 各種日時
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union TimeUnion {
+    pub system_time: SYSTEMTIME,
+    pub file_time: FILETIME,
+}
+
+/// 日時変換の情報
+#[repr(C)]
+pub struct ConvertTimeInfo {
+    /// 構造体のサイズ
+    pub size: u32,
+    /// 各種フラグ
+    pub flags: BitFlags<ConvertTimeFlag>,
+    /// 変換元の種類
+    pub type_from: ConvertTimeType,
+    /// 変換先の種類
+    pub type_to: ConvertTimeType,
+    /// 変換元の日時
+    pub from: TimeUnion,
+    /// 変換先の日時
+    pub to: TimeUnion,
+    /// オフセット(ms単位、ConvertTimeFlag::Offset が指定されている場合のみ)
+    pub offset: i64,
+}
+
+impl ConvertTimeInfo {
+    /// 変換元と変換先の種類を指定して生成します
+    pub fn new<F: TVTestTime, T: TVTestTime>(from: &F) -> Self {
+        let mut flags = BitFlags::empty();
+        if F::FILE_TIME {
+            flags |= ConvertTimeFlag::FromFileTime;
+        }
+        if T::FILE_TIME {
+            flags |= ConvertTimeFlag::ToFileTime;
+        }
+
+        Self {
+            size: size_of::<ConvertTimeInfo>() as u32,
+            flags,
+            type_from: F::TYPE,
+            type_to: T::TYPE,
+            from: from.to_union(),
+            to: TimeUnion { file_time: FILETIME::default() },
+            offset: 0,
+        }
+    }
+}
+
+/// TVTest で扱う日時
+/// 日時の種類ごとに型が分かれており、PluginApi::convert_time で相互に変換できます
+#[allow(unused_variables)]
+pub trait TVTestTime: Copy {
+    /// 日時の種類
+    const TYPE: ConvertTimeType;
+    /// FILETIME で表されるか
+    const FILE_TIME: bool;
+
+    fn to_union(&self) -> TimeUnion;
+
+    /// # Safety
+    /// `time` は FILE_TIME に応じたフィールドが有効である必要があります
+    unsafe fn from_union(time: &TimeUnion) -> Self;
+
+    /// std::time::SystemTime に変換します
+    /// ローカル時刻など、TVTest なしでは変換できない場合は None を返します
+    fn to_system_time(&self) -> Option<SystemTime> { None }
+
+    /// std::time::SystemTime から変換します
+    /// 範囲外の日時や、TVTest なしでは変換できない場合は None を返します
+    fn from_system_time(time: SystemTime) -> Option<Self> { None }
+
+    /// TVTest を使用せずに別の種類の日時に変換します
+    /// ローカル時刻は変換できないため、PluginApi::convert_time を使用します
+    fn convert<T: TVTestTime>(&self) -> Option<T> {
+        T::from_system_time(self.to_system_time()?)
+    }
+}
+
+/// EPG 日時(UTC+9 の SYSTEMTIME)
+/// EpgEventInfo や ProgramInfo の開始日時です
+#[repr(transparent)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
+pub struct EpgTime(pub SYSTEMTIME);
+
+/// UTC の SYSTEMTIME
+#[repr(transparent)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
+pub struct UtcTime(pub SYSTEMTIME);
+
+/// ローカル時刻の SYSTEMTIME
+#[repr(transparent)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
+pub struct LocalTime(pub SYSTEMTIME);
+
+/// EPG の表示用の SYSTEMTIME
+/// TVTest の設定によって EPG 日時かローカル時刻になります
+#[repr(transparent)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
+pub struct EpgDisplayTime(pub SYSTEMTIME);
+
+/// UTC の FILETIME
+#[repr(transparent)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
+pub struct UtcFileTime(pub FILETIME);
+
+/// ローカル時刻の FILETIME
+/// 録画の日時は RecordFlag::UTC を指定しない場合はこの形式です
+#[repr(transparent)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
+pub struct LocalFileTime(pub FILETIME);
+
+macro_rules! impl_system_time {
+    ($name:ident, $kind:expr, $offset:tt) => {
+        impl TVTestTime for $name {
+            const TYPE: ConvertTimeType = $kind;
+            const FILE_TIME: bool = false;
+
+            fn to_union(&self) -> TimeUnion {
+                TimeUnion { system_time: self.0 }
+            }
+
+            unsafe fn from_union(time: &TimeUnion) -> Self {
+                $name(time.system_time)
+            }
+
+            impl_system_time!(@convert $offset);
+        }
+    };
+    (@convert None) => {};
+    (@convert $offset:tt) => {
+        fn to_system_time(&self) -> Option<SystemTime> {
+            ticks_to_system_time(system_time_to_ticks(&self.0)? - $offset * TICKS_PER_SECOND)
+        }
+
+        fn from_system_time(time: SystemTime) -> Option<Self> {
+            ticks_to_wall_clock(system_time_to_unix_ticks(time)? + $offset * TICKS_PER_SECOND).map(Self)
+        }
+    };
+}
+
+impl_system_time!(EpgTime, ConvertTimeType::Epg, EPG_TIME_OFFSET);
+impl_system_time!(UtcTime, ConvertTimeType::Utc, 0);
+impl_system_time!(LocalTime, ConvertTimeType::Local, None);
+impl_system_time!(EpgDisplayTime, ConvertTimeType::EpgDisplay, None);
+
+impl TVTestTime for UtcFileTime {
+    const TYPE: ConvertTimeType = ConvertTimeType::Utc;
+    const FILE_TIME: bool = true;
+
+    fn to_union(&self) -> TimeUnion {
+        TimeUnion { file_time: self.0 }
+    }
+
+    unsafe fn from_union(time: &TimeUnion) -> Self {
+        UtcFileTime(time.file_time)
+    }
+
+    fn to_system_time(&self) -> Option<SystemTime> {
+        ticks_to_system_time(file_time_to_ticks(&self.0)?)
+    }
+
+    fn from_system_time(time: SystemTime) -> Option<Self> {
+        ticks_to_file_time(system_time_to_unix_ticks(time)?).map(UtcFileTime)
+    }
+}
+
+impl TVTestTime for LocalFileTime {
+    const TYPE: ConvertTimeType = ConvertTimeType::Local;
+    const FILE_TIME: bool = true;
+
+    fn to_union(&self) -> TimeUnion {
+        TimeUnion { file_time: self.0 }
+    }
+
+    unsafe fn from_union(time: &TimeUnion) -> Self {
+        LocalFileTime(time.file_time)
+    }
+}
+
+/// 日時の各フィールドから SYSTEMTIME を生成します
+/// 曜日は自動で設定され、不正な日時の場合は None を返します
+pub fn make_system_time(year: u16, month: u16, day: u16, hour: u16, minute: u16, second: u16, milliseconds: u16) -> Option<SYSTEMTIME> {
+    let time = SYSTEMTIME {
+        wYear: year,
+        wMonth: month,
+        wDayOfWeek: 0,
+        wDay: day,
+        wHour: hour,
+        wMinute: minute,
+        wSecond: second,
+        wMilliseconds: milliseconds,
+    };
+    let days = system_time_to_ticks(&time)?.div_euclid(TICKS_PER_SECOND * 86400);
+
+    Some(SYSTEMTIME {
+        // 1970/01/01 は木曜日
+        wDayOfWeek: (days + 4).rem_euclid(7) as u16,
+        ..time
+    })
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// 1970/01/01 からの日数
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+
+    (year_of_era + era * 400 + (month <= 2) as i64, month, day)
+}
+
+/// SYSTEMTIME の表す日時を、タイムゾーンを考慮せずに 1970/01/01 からの 100ナノ秒単位の時間に変換します
+fn system_time_to_ticks(time: &SYSTEMTIME) -> Option<i64> {
+    let (year, month, day) = (time.wYear as i64, time.wMonth as i64, time.wDay as i64);
+    if !(1601..=30827).contains(&year) || !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month)
+        || time.wHour >= 24 || time.wMinute >= 60 || time.wSecond >= 60 || time.wMilliseconds >= 1000 {
+        return None;
+    }
+
+    let seconds = days_from_civil(year, month, day) * 86400
+        + time.wHour as i64 * 3600 + time.wMinute as i64 * 60 + time.wSecond as i64;
+    Some(seconds * TICKS_PER_SECOND + time.wMilliseconds as i64 * TICKS_PER_MILLISECOND)
+}
+
+/// 1970/01/01 からの 100ナノ秒単位の時間を SYSTEMTIME に変換します
+/// ミリ秒未満は切り捨てられます
+fn ticks_to_wall_clock(ticks: i64) -> Option<SYSTEMTIME> {
+    let milliseconds = ticks.div_euclid(TICKS_PER_MILLISECOND);
+    let seconds = milliseconds.div_euclid(1000);
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let second_of_day = seconds.rem_euclid(86400);
+    if !(1601..=30827).contains(&year) {
+        return None;
+    }
+
+    make_system_time(
+        year as u16,
+        month as u16,
+        day as u16,
+        (second_of_day / 3600) as u16,
+        (second_of_day / 60 % 60) as u16,
+        (second_of_day % 60) as u16,
+        milliseconds.rem_euclid(1000) as u16,
+    )
+}
+
+fn file_time_to_ticks(time: &FILETIME) -> Option<i64> {
+    let value = (time.dwHighDateTime as u64) << 32 | time.dwLowDateTime as u64;
+
+    i64::try_from(value).ok().map(|value| value - FILETIME_UNIX_EPOCH)
+}
+
+fn ticks_to_file_time(ticks: i64) -> Option<FILETIME> {
+    let value = u64::try_from(ticks.checked_add(FILETIME_UNIX_EPOCH)?).ok()?;
+
+    Some(FILETIME {
+        dwLowDateTime: value as u32,
+        dwHighDateTime: (value >> 32) as u32,
+    })
+}
+
+/// SystemTime を 1970/01/01 からの 100ナノ秒単位の時間に変換します
+/// 100ナノ秒未満は切り捨てられます
+fn system_time_to_unix_ticks(time: SystemTime) -> Option<i64> {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => i64::try_from(duration.as_nanos() / 100).ok(),
+        Err(e) => i64::try_from(e.duration().as_nanos().div_ceil(100)).ok().map(|ticks| -ticks),
+    }
+}
+
+fn ticks_to_system_time(ticks: i64) -> Option<SystemTime> {
+    let duration = Duration::from_nanos(ticks.unsigned_abs().checked_mul(100)?);
+
+    if ticks >= 0 {
+        UNIX_EPOCH.checked_add(duration)
+    } else {
+        UNIX_EPOCH.checked_sub(duration)
+    }
+}
+
+#[cfg(feature = "chrono")]
+mod chrono_impl {
+    use ::chrono::{Datelike, DateTime, FixedOffset, Local, NaiveDateTime, TimeZone, Timelike, Utc};
+    use super::*;
+
+    fn to_naive(time: &SYSTEMTIME) -> Option<NaiveDateTime> {
+        system_time_to_ticks(time)?;
+
+        ::chrono::NaiveDate::from_ymd_opt(time.wYear as i32, time.wMonth as u32, time.wDay as u32)?
+            .and_hms_milli_opt(time.wHour as u32, time.wMinute as u32, time.wSecond as u32, time.wMilliseconds as u32)
+    }
+
+    fn from_naive(time: &NaiveDateTime) -> Option<SYSTEMTIME> {
+        make_system_time(
+            u16::try_from(time.year()).ok()?,
+            time.month() as u16,
+            time.day() as u16,
+            time.hour() as u16,
+            time.minute() as u16,
+            time.second() as u16,
+            // うるう秒の場合は 1000 以上になる
+            (time.nanosecond() / 1_000_000).min(999) as u16,
+        )
+    }
+
+    impl EpgTime {
+        /// UTC+9 の chrono::DateTime に変換します
+        pub fn to_chrono(&self) -> Option<DateTime<FixedOffset>> {
+            FixedOffset::east_opt(EPG_TIME_OFFSET as i32)?.from_local_datetime(&to_naive(&self.0)?).single()
+        }
+
+        /// chrono::DateTime から変換します
+        pub fn from_chrono<Tz: TimeZone>(time: &DateTime<Tz>) -> Option<Self> {
+            Self::from_system_time(SystemTime::from(time.clone()))
+        }
+    }
+
+    impl UtcTime {
+        /// chrono::DateTime<Utc> に変換します
+        pub fn to_chrono(&self) -> Option<DateTime<Utc>> {
+            Some(Utc.from_utc_datetime(&to_naive(&self.0)?))
+        }
+
+        /// chrono::DateTime から変換します
+        pub fn from_chrono<Tz: TimeZone>(time: &DateTime<Tz>) -> Option<Self> {
+            Self::from_system_time(SystemTime::from(time.clone()))
+        }
+    }
+
+    impl LocalTime {
+        /// chrono::DateTime<Local> に変換します
+        /// 夏時間の切り替えなどで曖昧な場合は早い方の日時になります
+        pub fn to_chrono(&self) -> Option<DateTime<Local>> {
+            Local.from_local_datetime(&to_naive(&self.0)?).earliest()
+        }
+
+        /// chrono::DateTime<Local> から変換します
+        pub fn from_chrono(time: &DateTime<Local>) -> Option<Self> {
+            from_naive(&time.naive_local()).map(Self)
+        }
+    }
+
+    impl UtcFileTime {
+        /// chrono::DateTime<Utc> に変換します
+        pub fn to_chrono(&self) -> Option<DateTime<Utc>> {
+            self.to_system_time().map(DateTime::<Utc>::from)
+        }
+
+        /// chrono::DateTime から変換します
+        pub fn from_chrono<Tz: TimeZone>(time: &DateTime<Tz>) -> Option<Self> {
+            Self::from_system_time(SystemTime::from(time.clone()))
+        }
+    }
+}
+
+#[cfg(feature = "time")]
+mod time_impl {
+    use ::time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset};
+    use super::*;
+
+    fn to_primitive(time: &SYSTEMTIME) -> Option<PrimitiveDateTime> {
+        let date = Date::from_calendar_date(time.wYear as i32, Month::try_from(time.wMonth as u8).ok()?, time.wDay as u8).ok()?;
+        let time = Time::from_hms_milli(time.wHour as u8, time.wMinute as u8, time.wSecond as u8, time.wMilliseconds).ok()?;
+
+        Some(PrimitiveDateTime::new(date, time))
+    }
+
+    fn from_primitive(time: &PrimitiveDateTime) -> Option<SYSTEMTIME> {
+        make_system_time(
+            u16::try_from(time.year()).ok()?,
+            time.month() as u16,
+            time.day() as u16,
+            time.hour() as u16,
+            time.minute() as u16,
+            time.second() as u16,
+            time.millisecond(),
+        )
+    }
+
+    impl EpgTime {
+        /// UTC+9 の time::OffsetDateTime に変換します
+        pub fn to_offset_date_time(&self) -> Option<OffsetDateTime> {
+            Some(to_primitive(&self.0)?.assume_offset(UtcOffset::from_whole_seconds(EPG_TIME_OFFSET as i32).ok()?))
+        }
+
+        /// time::OffsetDateTime から変換します
+        pub fn from_offset_date_time(time: &OffsetDateTime) -> Option<Self> {
+            Self::from_system_time(SystemTime::from(*time))
+        }
+    }
+
+    impl UtcTime {
+        /// UTC の time::OffsetDateTime に変換します
+        pub fn to_offset_date_time(&self) -> Option<OffsetDateTime> {
+            Some(to_primitive(&self.0)?.assume_utc())
+        }
+
+        /// time::OffsetDateTime から変換します
+        pub fn from_offset_date_time(time: &OffsetDateTime) -> Option<Self> {
+            Self::from_system_time(SystemTime::from(*time))
+        }
+    }
+
+    impl LocalTime {
+        /// time::PrimitiveDateTime に変換します
+        /// time ではローカル時刻のオフセットを安全に取得できないため、オフセットなしの日時になります
+        pub fn to_primitive_date_time(&self) -> Option<PrimitiveDateTime> {
+            to_primitive(&self.0)
+        }
+
+        /// time::PrimitiveDateTime から変換します
+        pub fn from_primitive_date_time(time: &PrimitiveDateTime) -> Option<Self> {
+            from_primitive(time).map(Self)
+        }
+    }
+
+    impl UtcFileTime {
+        /// UTC の time::OffsetDateTime に変換します
+        pub fn to_offset_date_time(&self) -> Option<OffsetDateTime> {
+            OffsetDateTime::from_unix_timestamp_nanos(file_time_to_ticks(&self.0)? as i128 * 100).ok()
+        }
+
+        /// time::OffsetDateTime から変換します
+        pub fn from_offset_date_time(time: &OffsetDateTime) -> Option<Self> {
+            Self::from_system_time(SystemTime::from(*time))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Win32::Foundation::LRESULT;
+    use crate::message::Message;
+    use crate::mock::MockHost;
+    use super::*;
+
+    fn jst(year: u16, month: u16, day: u16, hour: u16, minute: u16) -> EpgTime {
+        EpgTime(make_system_time(year, month, day, hour, minute, 0, 0).unwrap())
+    }
+
+    #[test]
+    fn convert_without_host() {
+        // 2022/01/01 00:00 JST = 2021/12/31 15:00 UTC
+        let epg = jst(2022, 1, 1, 0, 0);
+        assert_eq!(epg.0.wDayOfWeek, 6);
+        let system_time = epg.to_system_time().unwrap();
+        assert_eq!(system_time.duration_since(UNIX_EPOCH).unwrap().as_secs(), 1640962800);
+
+        let utc: UtcTime = epg.convert().unwrap();
+        assert_eq!((utc.0.wYear, utc.0.wMonth, utc.0.wDay, utc.0.wHour, utc.0.wDayOfWeek), (2021, 12, 31, 15, 5));
+        assert_eq!(utc.convert::<EpgTime>(), Some(epg));
+
+        let file_time: UtcFileTime = epg.convert().unwrap();
+        assert_eq!(file_time_to_ticks(&file_time.0), Some(1640962800 * TICKS_PER_SECOND));
+        assert_eq!(file_time.convert::<EpgTime>(), Some(epg));
+
+        // うるう年と範囲外
+        assert!(make_system_time(2024, 2, 29, 0, 0, 0, 0).is_some());
+        assert!(make_system_time(2100, 2, 29, 0, 0, 0, 0).is_none());
+        assert_eq!(UtcTime::from_system_time(UNIX_EPOCH - Duration::from_secs(400 * 365 * 86400)), None);
+        // ローカル時刻は TVTest なしでは変換できない
+        assert_eq!(epg.convert::<LocalTime>(), None);
+    }
+
+    #[test]
+    fn convert_with_host() {
+        let host = MockHost::new();
+        host.on(Message::QueryMessage, |message, _| LRESULT((message.0 == Message::ConvertTime as isize) as isize));
+        host.on(Message::ConvertTime, |info, _| {
+            let info = unsafe { &mut *(info.0 as *mut ConvertTimeInfo) };
+            assert_eq!(info.size, size_of::<ConvertTimeInfo>() as u32);
+            assert_eq!((info.type_from, info.type_to, info.flags), (ConvertTimeType::Epg, ConvertTimeType::Local, BitFlags::empty()));
+            // ローカル時刻が UTC+1 とする
+            let utc: UtcTime = unsafe { EpgTime::from_union(&info.from) }.convert().unwrap();
+            let local = UtcTime::from_system_time(utc.to_system_time().unwrap() + Duration::from_secs(3600)).unwrap();
+            info.to = local.to_union();
+            LRESULT(1)
+        });
+        let api = host.api();
+
+        let local: LocalTime = api.convert_time(&jst(2022, 1, 1, 0, 0)).unwrap();
+        assert_eq!((local.0.wDay, local.0.wHour), (31, 16));
+        assert_eq!(host.sent(Message::ConvertTime).len(), 1);
+    }
+}
@@ -0,0 +1,177 @@
+// TSプロセッサのパケット処理
+// COM に依存しないため、TVTest を介さずに Windows 以外でもテストできます。
+// TVTest への登録は ts_processor::TsProcessorRegistration を使用します。
+
+use std::ffi::c_void;
+use std::ptr;
+
+/// TS パケットの同期バイト
+pub const TS_SYNC_BYTE: u8 = 0x47;
+/// TS パケットのサイズ
+pub const TS_PACKET_SIZE: usize = 188;
+
+/// TSプロセッサに入力されたパケット
+/// 接続位置が Source 以外の場合は 188 バイトのパケットを表します
+pub struct TsPacket<'a> {
+    pub(crate) data: &'a mut [u8],
+    pub(crate) modified: bool,
+    // 入力元の ITSPacket (TVTest から渡されたパケット以外は null)
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub(crate) source: *mut c_void,
+}
+
+impl<'a> TsPacket<'a> {
+    /// バッファからパケットを生成します
+    /// TVTest を介さずにパケットの処理をテストする場合などに使用します
+    pub fn new(data: &'a mut [u8]) -> Self {
+        Self {
+            data,
+            modified: false,
+            source: ptr::null_mut(),
+        }
+    }
+
+    /// パケットのデータ
+    pub fn data(&self) -> &[u8] {
+        self.data
+    }
+
+    /// パケットのデータを書き換えます
+    /// パケットのヘッダを書き換えた場合は set_modified(true) を呼び出す必要があります
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        self.data
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// パケットが変更されたことを設定します
+    pub fn set_modified(&mut self, modified: bool) {
+        self.modified = modified;
+    }
+
+    /// パケットが変更されているか
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    /// PID を取得します
+    /// 188 バイトのパケットでない場合は None を返します
+    pub fn pid(&self) -> Option<u16> {
+        match *self.data() {
+            [TS_SYNC_BYTE, high, low, ..] if self.len() == TS_PACKET_SIZE => (u16::from(high & 0x1F) << 8 | u16::from(low)).into(),
+            _ => None,
+        }
+    }
+}
+
+/// TSプロセッサの出力先
+pub trait TsOutput {
+    /// パケットを出力します
+    fn output_packet(&mut self, packet: &mut TsPacket) -> bool;
+}
+
+/// 出力されたパケットを保持します
+/// TVTest を介さずにパケットの処理をテストする場合などに使用します
+impl TsOutput for Vec<Vec<u8>> {
+    fn output_packet(&mut self, packet: &mut TsPacket) -> bool {
+        self.push(packet.data().to_vec());
+        true
+    }
+}
+
+/// ストリーミングのクライアント
+/// TsProcessor::initialize で渡され、ログの出力や通知に使用します
+/// TVTest からのみ渡されるため、操作は ts_processor で実装しています
+pub struct StreamingClient(#[cfg_attr(not(windows), allow(dead_code))] pub(crate) *mut c_void);
+
+unsafe impl Send for StreamingClient {}
+
+/// TSプロセッサ
+/// TsProcessorRegistration::new で TVTest に登録します
+/// initialize → start_streaming → input_packet (繰り返し) → stop_streaming → finalize の順に呼ばれます
+#[allow(unused_variables)]
+pub trait TsProcessor: Send + 'static {
+    /// TSプロセッサの識別子 (GUID を u128 で表した値)
+    fn guid(&self) -> u128;
+    /// TSプロセッサの名前
+    fn name(&self) -> String;
+    /// 初期化処理
+    fn initialize(&mut self, client: Option<StreamingClient>) -> bool { true }
+    /// 終了処理
+    fn finalize(&mut self) {}
+    /// ストリーミングの開始
+    fn start_streaming(&mut self) -> bool { true }
+    /// ストリーミングの終了
+    fn stop_streaming(&mut self) {}
+    /// パケットを処理して output に出力します
+    /// パケットを破棄する場合は出力しません
+    fn input_packet(&mut self, packet: &mut TsPacket, output: &mut dyn TsOutput) -> bool {
+        output.output_packet(packet)
+    }
+    /// 状態のリセット
+    fn reset(&mut self) {}
+    /// 視聴中のサービスが変わった
+    fn set_active_service_id(&mut self, service_id: u16) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 指定した PID のパケットを破棄し、それ以外の PID を書き換える
+    struct PidFilter {
+        drop_pid: u16,
+    }
+
+    impl TsProcessor for PidFilter {
+        fn guid(&self) -> u128 {
+            0x0123456789ABCDEF0123456789ABCDEF
+        }
+
+        fn name(&self) -> String {
+            "PID Filter".to_string()
+        }
+
+        fn input_packet(&mut self, packet: &mut TsPacket, output: &mut dyn TsOutput) -> bool {
+            match packet.pid() {
+                Some(pid) if pid == self.drop_pid => true,
+                Some(_) => {
+                    packet.data_mut()[2] = 0xFF;
+                    packet.set_modified(true);
+                    output.output_packet(packet)
+                }
+                None => false,
+            }
+        }
+    }
+
+    fn make_packet(pid: u16) -> Vec<u8> {
+        let mut packet = vec![0xFF; TS_PACKET_SIZE];
+        packet[..4].copy_from_slice(&[TS_SYNC_BYTE, (pid >> 8) as u8 & 0x1F, pid as u8, 0x10]);
+        packet
+    }
+
+    #[test]
+    fn process_packets_without_com() {
+        let mut processor = PidFilter { drop_pid: 0x1FFF };
+        let mut output: Vec<Vec<u8>> = Vec::new();
+
+        for pid in [0x0000, 0x1FFF, 0x0111] {
+            let mut data = make_packet(pid);
+            let mut packet = TsPacket::new(&mut data);
+            assert!(processor.input_packet(&mut packet, &mut output));
+        }
+        let mut invalid = vec![0u8; 10];
+        assert!(!processor.input_packet(&mut TsPacket::new(&mut invalid), &mut output));
+
+        assert_eq!(output.len(), 2);
+        assert_eq!(TsPacket::new(&mut output[0]).pid(), Some(0x00FF));
+        assert_eq!(TsPacket::new(&mut output[1]).pid(), Some(0x01FF));
+    }
+}
@@ -0,0 +1,647 @@
+use std::ffi::c_void;
+use std::mem::size_of;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, Ordering};
+use std::sync::Mutex;
+use windows::core::{GUID, HRESULT};
+use windows::Win32::Foundation::{BOOL, BSTR, E_FAIL, E_NOINTERFACE, E_POINTER, S_OK};
+use crate::api::PluginApi;
+use crate::interface::{ErrorInfo, IID_ITSPROCESSOR, IID_IUNKNOWN, IStreamingClient, ITSOutput, ITSPacket, ITSProcessor, ITSProcessorVtbl, IUnknownVtbl, LogType, NotifyType};
+use crate::win32::IntoWideString;
+use crate::WideStringPtr;
+pub use crate::ts_packet::{StreamingClient, TS_PACKET_SIZE, TS_SYNC_BYTE, TsOutput, TsPacket, TsProcessor};
+
+/// TSプロセッサの接続位置
+/// 詳細は TVTestInterface.h を参照してください。
+#[repr(u32)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
+pub enum TsProcessorConnectPosition {
+    /// ソース(チューナー等からストリームが入力された後)
+    /// 入力されるデータのサイズは不定です
+    Source,
+    /// 前処理(TSを解析する前)
+    PreProcessing,
+    /// 後処理(TSを解析した後)
+    PostProcessing,
+    /// ビューア(再生の前)
+    Viewer,
+    /// レコーダ(ストリーム書き出しの前)
+    Recorder,
+}
+
+/// TSプロセッサの情報
+/// ヘッダでは 1 バイト境界でパックされているため、size もパックされたサイズになります
+#[repr(C, packed)]
+pub struct TsProcessorInfo {
+    /// 構造体のサイズ
+    pub size: u32,
+    /// 各種フラグ(現在は常に0)
+    pub flags: u32,
+    /// 接続する ITSProcessor
+    pub ts_processor: *mut ITSProcessor,
+    /// 接続位置
+    pub connect_position: TsProcessorConnectPosition,
+}
+
+impl TsProcessorInfo {
+    pub fn new(ts_processor: *mut ITSProcessor, connect_position: TsProcessorConnectPosition) -> Self {
+        Self {
+            size: size_of::<TsProcessorInfo>() as u32,
+            flags: 0,
+            ts_processor,
+            connect_position,
+        }
+    }
+}
+
+impl<'a> TsPacket<'a> {
+    /// ITSPacket からパケットを生成します
+    ///
+    /// # Safety
+    /// `packet` は有効な ITSPacket で、TsPacket を使い終わるまで生存している必要があります
+    pub unsafe fn from_raw(packet: *mut ITSPacket) -> Option<Self> {
+        if packet.is_null() {
+            return None;
+        }
+
+        let vtable = &*(*packet).vtable;
+        let mut data = ptr::null_mut();
+        let mut size = 0;
+        if (vtable.get_data)(packet, &mut data).is_err() || (vtable.get_size)(packet, &mut size).is_err() {
+            return None;
+        }
+        let data = if data.is_null() { &mut [][..] } else { slice::from_raw_parts_mut(data, size as usize) };
+
+        Self {
+            data,
+            modified: (vtable.get_modified)(packet) == S_OK,
+            source: packet as *mut c_void,
+        }.into()
+    }
+
+    /// ITSPacket に変更フラグを反映し、元の ITSPacket を返します
+    fn commit(&self) -> *mut ITSPacket {
+        let source = self.source as *mut ITSPacket;
+        if !source.is_null() {
+            unsafe {
+                let _ = ((*(*source).vtable).set_modified)(source, self.modified.into());
+            }
+        }
+
+        source
+    }
+}
+
+/// ITSOutput への出力
+struct ComTsOutput(*mut ITSOutput);
+
+impl TsOutput for ComTsOutput {
+    fn output_packet(&mut self, packet: &mut TsPacket) -> bool {
+        let source = packet.commit();
+        if source.is_null() {
+            // TVTest から渡されたパケット以外は出力できない
+            return false;
+        }
+
+        unsafe { ((*(*self.0).vtable).output_packet)(self.0, source) }.is_ok()
+    }
+}
+
+impl StreamingClient {
+    fn as_raw(&self) -> *mut IStreamingClient {
+        self.0 as *mut IStreamingClient
+    }
+
+    /// ログを出力します
+    pub fn out_log(&self, kind: LogType, message: &str) -> bool {
+        let message = message.into_wide_string();
+
+        unsafe { ((*(*self.as_raw()).vtable).out_log)(self.as_raw(), kind, message.to_wide_string_ptr()) }.is_ok()
+    }
+
+    /// ユーザーに通知します
+    pub fn notify(&self, kind: NotifyType, message: &str) -> bool {
+        let message = message.into_wide_string();
+
+        unsafe { ((*(*self.as_raw()).vtable).notify)(self.as_raw(), kind, message.to_wide_string_ptr()) }.is_ok()
+    }
+
+    /// エラーを通知します
+    pub fn on_error(&self, hr: HRESULT, text: &str, advise: Option<&str>) -> bool {
+        let text = text.into_wide_string();
+        let advise = advise.map(IntoWideString::into_wide_string);
+        let info = ErrorInfo {
+            hr,
+            text: text.to_wide_string_ptr(),
+            advise: advise.as_ref().map(|advise| advise.to_wide_string_ptr()).unwrap_or_default(),
+            system_message: WideStringPtr::default(),
+        };
+
+        unsafe { ((*(*self.as_raw()).vtable).on_error)(self.as_raw(), &info) }.is_ok()
+    }
+}
+
+impl Drop for StreamingClient {
+    fn drop(&mut self) {
+        unsafe {
+            ((*(*self.as_raw()).vtable).unknown.release)(self.0);
+        }
+    }
+}
+
+struct TsProcessorState<T> {
+    processor: T,
+    output: Option<ComTsOutput>,
+}
+
+/// TsProcessor を実装した ITSProcessor
+#[repr(C)]
+struct TsProcessorObject<T: TsProcessor> {
+    vtable: *const ITSProcessorVtbl,
+    ref_count: AtomicU32,
+    enable_processing: AtomicBool,
+    active_service_id: AtomicU16,
+    state: Mutex<TsProcessorState<T>>,
+}
+
+impl<T: TsProcessor> TsProcessorObject<T> {
+    const VTABLE: ITSProcessorVtbl = ITSProcessorVtbl {
+        unknown: IUnknownVtbl {
+            query_interface: Self::query_interface,
+            add_ref: Self::add_ref,
+            release: Self::release,
+        },
+        get_guid: Self::get_guid,
+        get_name: Self::get_name,
+        initialize: Self::initialize,
+        finalize: Self::finalize,
+        start_streaming: Self::start_streaming,
+        stop_streaming: Self::stop_streaming,
+        input_packet: Self::input_packet,
+        reset: Self::reset,
+        set_enable_processing: Self::set_enable_processing,
+        get_enable_processing: Self::get_enable_processing,
+        set_active_service_id: Self::set_active_service_id,
+        get_active_service_id: Self::get_active_service_id,
+    };
+
+    /// 参照カウント 1 で生成します
+    fn create(processor: T) -> *mut ITSProcessor {
+        let object = Box::new(Self {
+            vtable: &Self::VTABLE,
+            ref_count: AtomicU32::new(1),
+            enable_processing: AtomicBool::new(true),
+            active_service_id: AtomicU16::new(0),
+            state: Mutex::new(TsProcessorState {
+                processor,
+                output: None,
+            }),
+        });
+
+        Box::into_raw(object) as *mut ITSProcessor
+    }
+
+    unsafe fn from_this<'a>(this: *mut c_void) -> &'a Self {
+        &*(this as *const Self)
+    }
+
+    /// 状態をロックして処理を行います
+    /// パニックは TVTest 側に伝播させずに E_FAIL を返します
+    unsafe fn with_state<F>(this: *mut ITSProcessor, f: F) -> HRESULT
+        where F: FnOnce(&Self, &mut TsProcessorState<T>) -> bool
+    {
+        let object = Self::from_this(this as *mut c_void);
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            let mut state = object.state.lock().unwrap_or_else(|e| e.into_inner());
+            f(object, &mut state)
+        }));
+
+        match result {
+            Ok(true) => S_OK,
+            _ => E_FAIL,
+        }
+    }
+
+    unsafe extern "system" fn query_interface(this: *mut c_void, iid: *const GUID, object: *mut *mut c_void) -> HRESULT {
+        if iid.is_null() || object.is_null() {
+            return E_POINTER;
+        }
+
+        if *iid == IID_IUNKNOWN || *iid == IID_ITSPROCESSOR {
+            Self::add_ref(this);
+            *object = this;
+            S_OK
+        } else {
+            *object = ptr::null_mut();
+            E_NOINTERFACE
+        }
+    }
+
+    unsafe extern "system" fn add_ref(this: *mut c_void) -> u32 {
+        Self::from_this(this).ref_count.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    unsafe extern "system" fn release(this: *mut c_void) -> u32 {
+        let count = Self::from_this(this).ref_count.fetch_sub(1, Ordering::Release) - 1;
+        if count == 0 {
+            std::sync::atomic::fence(Ordering::Acquire);
+            drop(Box::from_raw(this as *mut Self));
+        }
+
+        count
+    }
+
+    unsafe extern "system" fn get_guid(this: *mut ITSProcessor, guid: *mut GUID) -> HRESULT {
+        if guid.is_null() {
+            return E_POINTER;
+        }
+
+        Self::with_state(this, |_, state| {
+            *guid = GUID::from_u128(state.processor.guid());
+            true
+        })
+    }
+
+    unsafe extern "system" fn get_name(this: *mut ITSProcessor, name: *mut *const u16) -> HRESULT {
+        if name.is_null() {
+            return E_POINTER;
+        }
+
+        Self::with_state(this, |_, state| {
+            *name = BSTR::from(state.processor.name().as_str()).into_raw();
+            true
+        })
+    }
+
+    unsafe extern "system" fn initialize(this: *mut ITSProcessor, client: *mut IStreamingClient) -> HRESULT {
+        let client = if client.is_null() {
+            None
+        } else {
+            ((*(*client).vtable).unknown.add_ref)(client as *mut c_void);
+            StreamingClient(client as *mut c_void).into()
+        };
+
+        Self::with_state(this, |_, state| state.processor.initialize(client))
+    }
+
+    unsafe extern "system" fn finalize(this: *mut ITSProcessor) -> HRESULT {
+        Self::with_state(this, |_, state| {
+            state.processor.finalize();
+            true
+        })
+    }
+
+    unsafe extern "system" fn start_streaming(this: *mut ITSProcessor, output: *mut ITSOutput) -> HRESULT {
+        if output.is_null() {
+            return E_POINTER;
+        }
+
+        Self::with_state(this, |_, state| {
+            ((*(*output).vtable).unknown.add_ref)(output as *mut c_void);
+            if let Some(ComTsOutput(previous)) = state.output.replace(ComTsOutput(output)) {
+                ((*(*previous).vtable).unknown.release)(previous as *mut c_void);
+            }
+
+            state.processor.start_streaming()
+        })
+    }
+
+    unsafe extern "system" fn stop_streaming(this: *mut ITSProcessor) -> HRESULT {
+        Self::with_state(this, |_, state| {
+            state.processor.stop_streaming();
+            if let Some(ComTsOutput(output)) = state.output.take() {
+                ((*(*output).vtable).unknown.release)(output as *mut c_void);
+            }
+
+            true
+        })
+    }
+
+    unsafe extern "system" fn input_packet(this: *mut ITSProcessor, packet: *mut ITSPacket) -> HRESULT {
+        let mut packet = match TsPacket::from_raw(packet) {
+            Some(packet) => packet,
+            None => return E_POINTER,
+        };
+
+        Self::with_state(this, |object, state| {
+            let TsProcessorState { processor, output } = state;
+            let output = match output {
+                Some(output) => output,
+                None => return false,
+            };
+
+            // 処理が無効の場合はそのまま出力する
+            if object.enable_processing.load(Ordering::Relaxed) {
+                processor.input_packet(&mut packet, output)
+            } else {
+                output.output_packet(&mut packet)
+            }
+        })
+    }
+
+    unsafe extern "system" fn reset(this: *mut ITSProcessor) -> HRESULT {
+        Self::with_state(this, |_, state| {
+            state.processor.reset();
+            true
+        })
+    }
+
+    unsafe extern "system" fn set_enable_processing(this: *mut ITSProcessor, enable: BOOL) -> HRESULT {
+        Self::from_this(this as *mut c_void).enable_processing.store(enable.as_bool(), Ordering::Relaxed);
+
+        S_OK
+    }
+
+    unsafe extern "system" fn get_enable_processing(this: *mut ITSProcessor, enable: *mut BOOL) -> HRESULT {
+        if enable.is_null() {
+            return E_POINTER;
+        }
+        *enable = Self::from_this(this as *mut c_void).enable_processing.load(Ordering::Relaxed).into();
+
+        S_OK
+    }
+
+    unsafe extern "system" fn set_active_service_id(this: *mut ITSProcessor, service_id: u16) -> HRESULT {
+        Self::from_this(this as *mut c_void).active_service_id.store(service_id, Ordering::Relaxed);
+
+        Self::with_state(this, |_, state| {
+            state.processor.set_active_service_id(service_id);
+            true
+        })
+    }
+
+    unsafe extern "system" fn get_active_service_id(this: *mut ITSProcessor, service_id: *mut u16) -> HRESULT {
+        if service_id.is_null() {
+            return E_POINTER;
+        }
+        *service_id = Self::from_this(this as *mut c_void).active_service_id.load(Ordering::Relaxed);
+
+        S_OK
+    }
+}
+
+/// 登録した TSプロセッサ
+/// TSプロセッサへの参照を保持し、Drop 時に解放します
+pub struct TsProcessorRegistration(*mut ITSProcessor);
+
+impl TsProcessorRegistration {
+    /// TsProcessor を ITSProcessor として TVTest に登録します
+    /// プラグインの終了まで保持してください
+    pub fn new<T: TsProcessor>(api: &PluginApi, processor: T, connect_position: TsProcessorConnectPosition) -> Option<Self> {
+        // 登録に失敗した場合も Drop で解放される
+        let registration = Self(TsProcessorObject::create(processor));
+        let info = TsProcessorInfo::new(registration.0, connect_position);
+
+        if api.register_ts_processor(&info) {
+            registration.into()
+        } else {
+            None
+        }
+    }
+
+    /// ITSProcessor を返します
+    pub fn as_raw(&self) -> *mut ITSProcessor {
+        self.0
+    }
+}
+
+impl Drop for TsProcessorRegistration {
+    fn drop(&mut self) {
+        unsafe {
+            ((*(*self.0).vtable).unknown.release)(self.0 as *mut c_void);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::sync::Arc;
+    use windows::Win32::Foundation::S_FALSE;
+    use crate::interface::{IID_ITSOUTPUT, ITSOutputVtbl, ITSPacketVtbl};
+    use super::*;
+
+    /// 指定した PID のパケットを破棄し、それ以外の PID を書き換える
+    struct PidFilter {
+        drop_pid: u16,
+    }
+
+    impl TsProcessor for PidFilter {
+        fn guid(&self) -> u128 {
+            0x0123456789ABCDEF0123456789ABCDEF
+        }
+
+        fn name(&self) -> String {
+            "PID Filter".to_string()
+        }
+
+        fn input_packet(&mut self, packet: &mut TsPacket, output: &mut dyn TsOutput) -> bool {
+            match packet.pid() {
+                Some(pid) if pid == self.drop_pid => true,
+                Some(_) => {
+                    packet.data_mut()[2] = 0xFF;
+                    packet.set_modified(true);
+                    output.output_packet(packet)
+                }
+                None => false,
+            }
+        }
+    }
+
+    fn make_packet(pid: u16) -> Vec<u8> {
+        let mut packet = vec![0xFF; TS_PACKET_SIZE];
+        packet[..4].copy_from_slice(&[TS_SYNC_BYTE, (pid >> 8) as u8 & 0x1F, pid as u8, 0x10]);
+        packet
+    }
+
+    // テスト用の ITSPacket
+    #[repr(C)]
+    struct MockPacket {
+        vtable: *const ITSPacketVtbl,
+        data: Vec<u8>,
+        modified: bool,
+    }
+
+    // テスト用の ITSOutput
+    // 出力されたパケットのデータと変更フラグを保持します
+    #[repr(C)]
+    struct MockOutput {
+        vtable: *const ITSOutputVtbl,
+        ref_count: Cell<u32>,
+        packets: RefCell<Vec<(Vec<u8>, bool)>>,
+    }
+
+    unsafe extern "system" fn no_interface(_: *mut c_void, _: *const GUID, _: *mut *mut c_void) -> HRESULT {
+        E_NOINTERFACE
+    }
+
+    unsafe extern "system" fn packet_ref(_: *mut c_void) -> u32 {
+        1
+    }
+
+    unsafe extern "system" fn packet_get_data(this: *mut ITSPacket, data: *mut *mut u8) -> HRESULT {
+        *data = (*(this as *mut MockPacket)).data.as_mut_ptr();
+        S_OK
+    }
+
+    unsafe extern "system" fn packet_get_size(this: *mut ITSPacket, size: *mut u32) -> HRESULT {
+        *size = (*(this as *mut MockPacket)).data.len() as u32;
+        S_OK
+    }
+
+    unsafe extern "system" fn packet_set_modified(this: *mut ITSPacket, modified: BOOL) -> HRESULT {
+        (*(this as *mut MockPacket)).modified = modified.as_bool();
+        S_OK
+    }
+
+    unsafe extern "system" fn packet_get_modified(this: *mut ITSPacket) -> HRESULT {
+        if (*(this as *mut MockPacket)).modified { S_OK } else { S_FALSE }
+    }
+
+    static PACKET_VTABLE: ITSPacketVtbl = ITSPacketVtbl {
+        unknown: IUnknownVtbl {
+            query_interface: no_interface,
+            add_ref: packet_ref,
+            release: packet_ref,
+        },
+        get_data: packet_get_data,
+        get_size: packet_get_size,
+        set_modified: packet_set_modified,
+        get_modified: packet_get_modified,
+    };
+
+    unsafe extern "system" fn output_add_ref(this: *mut c_void) -> u32 {
+        let output = &*(this as *const MockOutput);
+        output.ref_count.set(output.ref_count.get() + 1);
+        output.ref_count.get()
+    }
+
+    unsafe extern "system" fn output_release(this: *mut c_void) -> u32 {
+        let output = &*(this as *const MockOutput);
+        output.ref_count.set(output.ref_count.get() - 1);
+        output.ref_count.get()
+    }
+
+    unsafe extern "system" fn output_packet(this: *mut ITSOutput, packet: *mut ITSPacket) -> HRESULT {
+        let packet = &*(packet as *const MockPacket);
+        (*(this as *const MockOutput)).packets.borrow_mut().push((packet.data.clone(), packet.modified));
+        S_OK
+    }
+
+    static OUTPUT_VTABLE: ITSOutputVtbl = ITSOutputVtbl {
+        unknown: IUnknownVtbl {
+            query_interface: no_interface,
+            add_ref: output_add_ref,
+            release: output_release,
+        },
+        output_packet,
+    };
+
+    // 破棄されたことを記録する TsProcessor
+    struct Tracked {
+        filter: PidFilter,
+        dropped: Arc<AtomicBool>,
+    }
+
+    impl TsProcessor for Tracked {
+        fn guid(&self) -> u128 {
+            self.filter.guid()
+        }
+
+        fn name(&self) -> String {
+            self.filter.name()
+        }
+
+        fn input_packet(&mut self, packet: &mut TsPacket, output: &mut dyn TsOutput) -> bool {
+            self.filter.input_packet(packet, output)
+        }
+    }
+
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            self.dropped.store(true, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn process_packets_through_vtable() {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let processor = TsProcessorObject::create(Tracked {
+            filter: PidFilter { drop_pid: 0x1FFF },
+            dropped: Arc::clone(&dropped),
+        });
+        let vtable = unsafe { &*(*processor).vtable };
+        let mut output = MockOutput {
+            vtable: &OUTPUT_VTABLE,
+            ref_count: Cell::new(1),
+            packets: RefCell::new(Vec::new()),
+        };
+        let output_ptr = &mut output as *mut MockOutput as *mut ITSOutput;
+        let input = |pid| {
+            let mut packet = MockPacket { vtable: &PACKET_VTABLE, data: make_packet(pid), modified: false };
+            let hr = unsafe { (vtable.input_packet)(processor, &mut packet as *mut MockPacket as *mut ITSPacket) };
+            (hr, packet)
+        };
+
+        unsafe {
+            // QueryInterface で参照カウントが増える
+            let mut object = ptr::null_mut();
+            assert_eq!((vtable.unknown.query_interface)(processor as *mut c_void, &IID_ITSPROCESSOR, &mut object), S_OK);
+            assert_eq!(object, processor as *mut c_void);
+            assert_eq!((vtable.unknown.query_interface)(processor as *mut c_void, &IID_ITSOUTPUT, &mut object), E_NOINTERFACE);
+            assert!(object.is_null());
+
+            let mut guid = GUID::zeroed();
+            assert_eq!((vtable.get_guid)(processor, &mut guid), S_OK);
+            assert_eq!(guid, GUID::from_u128(0x0123456789ABCDEF0123456789ABCDEF));
+            assert_eq!((vtable.initialize)(processor, ptr::null_mut()), S_OK);
+
+            // 出力先が無い状態では失敗する
+            assert_eq!(input(0x0100).0, E_FAIL);
+
+            assert_eq!((vtable.start_streaming)(processor, output_ptr), S_OK);
+            assert_eq!(output.ref_count.get(), 2);
+        }
+
+        // 処理されたパケットは変更フラグ付きで出力され、破棄されたパケットは出力されない
+        let (hr, packet) = input(0x0100);
+        assert_eq!(hr, S_OK);
+        assert!(packet.modified);
+        assert_eq!(input(0x1FFF).0, S_OK);
+        assert_eq!(output.packets.borrow().len(), 1);
+        assert_eq!(output.packets.borrow()[0], (packet.data, true));
+
+        unsafe {
+            // 処理が無効の場合はそのまま出力される
+            assert_eq!((vtable.set_enable_processing)(processor, false.into()), S_OK);
+            let mut enable = BOOL(1);
+            assert_eq!((vtable.get_enable_processing)(processor, &mut enable), S_OK);
+            assert!(!enable.as_bool());
+        }
+        let (hr, packet) = input(0x1FFF);
+        assert_eq!(hr, S_OK);
+        assert!(!packet.modified);
+        assert_eq!(output.packets.borrow().last(), Some(&(make_packet(0x1FFF), false)));
+
+        unsafe {
+            let mut service_id = 0;
+            assert_eq!((vtable.set_active_service_id)(processor, 0x0400), S_OK);
+            assert_eq!((vtable.get_active_service_id)(processor, &mut service_id), S_OK);
+            assert_eq!(service_id, 0x0400);
+
+            // 出力先は stop_streaming で解放される
+            assert_eq!((vtable.stop_streaming)(processor), S_OK);
+            assert_eq!(output.ref_count.get(), 1);
+            assert_eq!((vtable.finalize)(processor), S_OK);
+
+            // 参照カウントが 0 になると破棄される
+            assert_eq!((vtable.unknown.release)(processor as *mut c_void), 1);
+            assert!(!dropped.load(Ordering::Relaxed));
+            assert_eq!((vtable.unknown.release)(processor as *mut c_void), 0);
+        }
+        assert!(dropped.load(Ordering::Relaxed));
+    }
+}
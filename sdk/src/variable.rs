@@ -1,4 +1,222 @@
-use crate::win32::WideStringPtr;
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+use std::ptr::NonNull;
+use std::sync::Arc;
+use enumflags2::BitFlags;
+use windows::Win32::Foundation::{BOOL, LPARAM};
+use crate::api::PluginApi;
+use crate::ClientData;
+use crate::memory::HostWideString;
+use crate::message::Message;
+use crate::plugin::PluginParam;
+use crate::win32::{IntoWideString, UnsafeIntoRustString, WStr, WideStringPtr};
+
+/// 変数文字列のコンテキスト
+/// TVTest 内部の構造体なので、ポインタとしてのみ扱います
+#[repr(C)]
+pub struct VarStringContextHandle {
+    _private: [u8; 0],
+}
+
+/// 変数文字列のマップ関数
+/// 変数を置き換える場合は、TVTest のメモリ確保関数で確保した文字列を string に設定して TRUE を返します
+pub type VarStringMapFunc = unsafe extern "system" fn(
+    var: WideStringPtr,
+    string: *mut WideStringPtr,
+    client_data: ClientData,
+) -> BOOL;
+
+/// 変数文字列のフォーマットフラグ
+#[bitflags]
+#[derive(Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+#[repr(u32)]
+pub enum VarStringFormatFlag {
+    /// ファイル名用(ファイル名に使えない文字が全角になる)
+    FileName = 0x0001,
+}
+
+/// 変数文字列のフォーマット情報
+#[repr(C)]
+pub struct VarStringFormatInfo<'a> {
+    /// 構造体のサイズ
+    pub size: u32,
+    /// 各種フラグ
+    pub flags: BitFlags<VarStringFormatFlag>,
+    /// フォーマット文字列
+    pub format: WideStringPtr,
+    /// コンテキスト(nullptr で現在のコンテキスト)
+    pub context: *const VarStringContextHandle,
+    /// マップ関数(必要なければ None)
+    pub map_func: Option<VarStringMapFunc>,
+    /// マップ関数に渡す任意データ
+    pub client_data: ClientData,
+    /// 変換結果の文字列
+    /// 不要になったら PluginApi::memory_free で解放します
+    pub result: WideStringPtr,
+    _marker: PhantomData<WStr<'a>>,
+}
+
+impl<'a> VarStringFormatInfo<'a> {
+    /// フォーマット文字列を指定して生成します
+    pub fn new(format: WStr<'a>) -> Self {
+        Self {
+            size: size_of::<VarStringFormatInfo>() as u32,
+            flags: BitFlags::empty(),
+            format: format.as_ptr(),
+            context: ptr::null(),
+            map_func: None,
+            client_data: None,
+            result: WideStringPtr::default(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// 変数文字列のコンテキスト
+/// PluginApi::get_var_string_context で取得した時点の番組や日時などの情報で、Drop 時に解放されます
+pub struct VarStringContext {
+    param: Arc<PluginParam>,
+    handle: NonNull<VarStringContextHandle>,
+}
+
+impl VarStringContext {
+    pub(crate) fn new(param: Arc<PluginParam>, handle: NonNull<VarStringContextHandle>) -> Self {
+        Self {
+            param,
+            handle,
+        }
+    }
+
+    /// コンテキストのポインタ
+    pub fn as_ptr(&self) -> *const VarStringContextHandle {
+        self.handle.as_ptr()
+    }
+}
+
+impl Drop for VarStringContext {
+    fn drop(&mut self) {
+        self.param.send_message(Message::FreeVarStringContext, LPARAM(self.handle.as_ptr() as isize), LPARAM(0));
+    }
+}
+
+/// 変数の値を返すクロージャ
+/// 置き換えない場合は None を返します
+pub type VarStringMapper<'a> = dyn Fn(&str) -> Option<String> + 'a;
+
+/// 変数文字列のフォーマットの設定
+/// PluginApi::format_var_string_with に渡します
+#[derive(core::default::Default)]
+pub struct VarStringFormat<'a> {
+    context: Option<&'a VarStringContext>,
+    flags: BitFlags<VarStringFormatFlag>,
+    map: Option<Box<VarStringMapper<'a>>>,
+}
+
+impl<'a> VarStringFormat<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// コンテキストを設定します
+    /// 設定しない場合は現在のコンテキストが使われます
+    pub fn context(mut self, context: &'a VarStringContext) -> Self {
+        self.context = context.into();
+        self
+    }
+
+    /// ファイル名用にフォーマットします
+    /// ファイル名に使えない文字が全角になります
+    pub fn file_name(mut self) -> Self {
+        self.flags |= VarStringFormatFlag::FileName;
+        self
+    }
+
+    /// 任意の変数を置き換えるクロージャを設定します
+    /// クロージャには % を除いた変数名が渡されます
+    pub fn map<F>(mut self, map: F) -> Self
+        where F: Fn(&str) -> Option<String> + 'a
+    {
+        self.map = Some(Box::new(map));
+        self
+    }
+
+    pub(crate) fn format(&self, api: &PluginApi, format: &str) -> Option<String> {
+        let format = format.into_wide_string();
+        let mut info = VarStringFormatInfo::new(format.as_wstr()?);
+        info.flags = self.flags;
+        info.context = self.context.map(VarStringContext::as_ptr).unwrap_or(ptr::null());
+
+        let state;
+        if let Some(map) = &self.map {
+            state = MapState {
+                api,
+                map: map.as_ref(),
+            };
+            info.map_func = Some(var_string_map_callback);
+            info.client_data = NonNull::new(&state as *const MapState as *mut _);
+        }
+
+        if !api.format_var_string_info(&mut info) {
+            return None;
+        }
+
+        let result = unsafe { HostWideString::from_raw(api, info.result) }?;
+        result.to_string().into()
+    }
+}
+
+struct MapState<'a, 'b> {
+    api: &'a PluginApi,
+    map: &'a VarStringMapper<'b>,
+}
+
+unsafe extern "system" fn var_string_map_callback(var: WideStringPtr, string: *mut WideStringPtr, client_data: ClientData) -> BOOL {
+    let state = match client_data {
+        Some(p) => &*(p.as_ptr() as *const MapState),
+        None => return BOOL(0),
+    };
+    let var = match var.read_string() {
+        Some(var) => var,
+        None => return BOOL(0),
+    };
+
+    // パニックした場合は置き換えなかったものとして扱う
+    match catch_unwind(AssertUnwindSafe(|| (state.map)(&var))) {
+        Ok(Some(value)) if !string.is_null() => {
+            *string = state.api.string_duplicate(&value);
+            BOOL(!string.read().is_null() as i32)
+        },
+        _ => BOOL(0),
+    }
+}
+
+/// 変数登録のフラグ
+#[bitflags]
+#[derive(Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+#[repr(u32)]
+pub enum RegisterVariableFlag {
+    /// デフォルトの変数を上書き
+    Override = 0x0001,
+}
+
+/// 変数登録の情報
+#[repr(C)]
+pub struct RegisterVariableInfo {
+    /// 構造体のサイズ
+    pub size: u32,
+    /// フラグ
+    pub flags: BitFlags<RegisterVariableFlag>,
+    /// 識別子
+    pub keyword: WideStringPtr,
+    /// 説明文
+    pub description: WideStringPtr,
+    /// 変数の値(nullptr で Event::GetVariable で動的に取得)
+    pub value: WideStringPtr,
+}
 
 /// 変数取得の情報
 /// Event::GetVariable で渡されます。
@@ -9,5 +227,157 @@ pub struct GetVariableInfo {
     pub keyword: WideStringPtr,
 
     // 値
+    // TVTest のメモリ確保関数で確保した文字列を設定します
     pub value: WideStringPtr,
 }
+
+impl GetVariableInfo {
+    /// 値を設定します
+    /// 文字列は TVTest のメモリ確保関数で確保され、TVTest が解放します
+    pub fn set_value(&mut self, api: &PluginApi, value: &str) -> bool {
+        self.value = api.string_duplicate(value);
+
+        !self.value.is_null()
+    }
+}
+
+/// 変数の登録内容
+pub struct VariableBuilder {
+    keyword: String,
+    description: String,
+    flags: BitFlags<RegisterVariableFlag>,
+}
+
+impl VariableBuilder {
+    /// 識別子と説明文を指定します
+    /// 識別子は半角のアルファベットと数字、-記号のみ使用できます
+    pub fn new(keyword: &str, description: &str) -> Self {
+        Self {
+            keyword: keyword.to_string(),
+            description: description.to_string(),
+            flags: BitFlags::empty(),
+        }
+    }
+
+    /// TVTest で定義されている同じ識別子の変数を上書きします
+    pub fn override_default(mut self) -> Self {
+        self.flags |= RegisterVariableFlag::Override;
+        self
+    }
+}
+
+struct RegisteredVariable {
+    keyword: String,
+    value: Box<dyn Fn() -> String>,
+}
+
+/// 変数の一覧
+/// on_get_variable から get_variable() を呼び出して各変数の値を返します
+#[derive(core::default::Default)]
+pub struct Variables {
+    variables: Vec<RegisteredVariable>,
+}
+
+impl Variables {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// TVTest に値が動的に決まる変数を登録します
+    /// 変数文字列の中で %識別子% を使うと、value の返す値に置き換えられます
+    pub fn register<F>(&mut self, api: &PluginApi, builder: VariableBuilder, value: F) -> bool
+        where F: Fn() -> String + 'static
+    {
+        let keyword = builder.keyword.as_str().into_wide_string();
+        let description = builder.description.as_str().into_wide_string();
+        let info = RegisterVariableInfo {
+            size: size_of::<RegisterVariableInfo>() as u32,
+            flags: builder.flags,
+            keyword: keyword.to_wide_string_ptr(),
+            description: description.to_wide_string_ptr(),
+            value: WideStringPtr::default(),
+        };
+        if !api.register_variable_info(&info) {
+            return false;
+        }
+
+        self.variables.retain(|registered| !registered.keyword.eq_ignore_ascii_case(&builder.keyword));
+        self.variables.push(RegisteredVariable {
+            keyword: builder.keyword,
+            value: Box::new(value),
+        });
+        true
+    }
+
+    /// 変数の値を返します
+    /// 識別子の大文字と小文字は区別されません
+    pub fn get_variable(&self, api: &PluginApi, info: &mut GetVariableInfo) -> bool {
+        let keyword = match info.keyword.read_string() {
+            Some(keyword) => keyword,
+            None => return false,
+        };
+
+        self.variables.iter()
+            .find(|registered| registered.keyword.eq_ignore_ascii_case(&keyword))
+            .is_some_and(|registered| info.set_value(api, &(registered.value)()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use windows::Win32::Foundation::LRESULT;
+    use crate::mock::MockHost;
+    use super::*;
+
+    #[test]
+    fn format_and_register_variables() {
+        let host = MockHost::new();
+        host.track_allocations();
+        host.on(Message::GetVarStringContext, |_, _| LRESULT(0x1234));
+        host.on(Message::RegisterVariable, |_, _| LRESULT(1));
+        let param = Rc::new(RefCell::new(None));
+        let sent = Rc::clone(&param);
+        let inner = host.api();
+        host.on(Message::FormatVarString, move |info, _| {
+            let info = unsafe { &mut *(info.0 as *mut VarStringFormatInfo) };
+            *sent.borrow_mut() = Some((info.flags, info.context as isize));
+            let var = "my-var".into_wide_string();
+            let mut mapped = WideStringPtr::default();
+            let replaced = match info.map_func {
+                Some(func) if unsafe { func(var.to_wide_string_ptr(), &mut mapped, info.client_data) }.as_bool() => {
+                    let value = mapped.read_string().unwrap();
+                    // 置き換えられた文字列は TVTest 側で解放する
+                    inner.memory_free(mapped.0.map(NonNull::cast));
+                    value
+                },
+                // マップ関数で置き換えられなかった変数はそのまま残す
+                _ => "%my-var%".to_string(),
+            };
+            info.result = inner.string_duplicate(&info.format.read_string().unwrap().replace("%my-var%", &replaced));
+            LRESULT(1)
+        });
+        let api = host.api();
+
+        assert_eq!(api.format_var_string("%my-var%.ts").as_deref(), Some("%my-var%.ts"));
+        {
+            let context = api.get_var_string_context().unwrap();
+            let format = VarStringFormat::new().context(&context).file_name().map(|var| (var == "my-var").then(|| "録画".to_string()));
+            assert_eq!(api.format_var_string_with("%my-var%.ts", &format).as_deref(), Some("録画.ts"));
+            assert_eq!(*param.borrow(), Some((VarStringFormatFlag::FileName.into(), 0x1234)));
+        }
+        assert_eq!(host.sent(Message::FreeVarStringContext), vec![(LPARAM(0x1234), LPARAM(0))]);
+
+        let mut variables = Variables::new();
+        assert!(variables.register(&api, VariableBuilder::new("tick-count", "Tick count"), || "42".to_string()));
+        let keyword = "Tick-Count".into_wide_string();
+        let mut info = GetVariableInfo { keyword: keyword.to_wide_string_ptr(), value: WideStringPtr::default() };
+        assert!(variables.get_variable(&api, &mut info));
+        assert_eq!(info.value.read_string().as_deref(), Some("42"));
+        api.memory_free(info.value.0.map(NonNull::cast));
+
+        // 結果の文字列とマップ関数の文字列は解放されている
+        assert_eq!(host.allocations(), 0);
+    }
+}
@@ -1,3 +1,6 @@
+use std::fmt;
+use std::str::FromStr;
+
 pub const DEFAULT_API_VERSION: Version = Version {
     major: 0,
     minor: 0,
@@ -5,6 +8,7 @@ pub const DEFAULT_API_VERSION: Version = Version {
 };
 
 #[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[cfg_attr(test, derive(Debug))]
 pub struct Version {
     /// メジャーバージョン
@@ -16,6 +20,12 @@ pub struct Version {
 }
 
 impl Version {
+    pub const fn new(major: u32, minor: u32, build: u32) -> Self {
+        Version {
+            major, minor, build
+        }
+    }
+
     // 上位8ビットがメジャーバージョン
     #[inline]
     pub fn get_major(version: u32) -> u32 {
@@ -47,8 +57,83 @@ impl From<u32> for Version {
     }
 }
 
-impl Into<u32> for Version {
-    fn into(self) -> u32 {
-        (self.major << 24)  | (self.minor << 12) | self.build
+impl From<Version> for u32 {
+    fn from(version: Version) -> Self {
+        (version.major << 24)  | (version.minor << 12) | version.build
+    }
+}
+
+impl Default for Version {
+    fn default() -> Self {
+        Version::new(0, 0, 0)
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.build)
+    }
+}
+
+/// バージョン文字列の解析に失敗した
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ParseVersionError;
+
+impl fmt::Display for ParseVersionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid version text")
+    }
+}
+
+impl std::error::Error for ParseVersionError {}
+
+/// "1.2.0" のようなバージョン文字列を解析します
+/// 先頭の "v" と、数字の後に続く "-dev" や " (x64)" などの文字列は無視されます
+/// マイナーバージョンとビルドナンバーが省略された場合は 0 になります
+impl FromStr for Version {
+    type Err = ParseVersionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let s = s.strip_prefix(['v', 'V']).unwrap_or(s);
+        let end = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+
+        let mut components = [0u32; 3];
+        let mut parts = s[..end].split('.');
+        for (i, component) in components.iter_mut().enumerate() {
+            match parts.next() {
+                Some(part) if !part.is_empty() => *component = part.parse().map_err(|_| ParseVersionError)?,
+                // "1." のような末尾のドットは許容する
+                Some(_) if i > 0 => break,
+                None if i > 0 => break,
+                _ => return Err(ParseVersionError),
+            }
+        }
+
+        Ok(Version::new(components[0], components[1], components[2]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_version_text() {
+        assert_eq!("0.10.0".parse(), Ok(Version::new(0, 10, 0)));
+        assert_eq!("v0.9.0-dev".parse(), Ok(Version::new(0, 9, 0)));
+        assert_eq!("1.2 (x64)".parse(), Ok(Version::new(1, 2, 0)));
+        assert_eq!("".parse::<Version>(), Err(ParseVersionError));
+        assert_eq!("TVTest".parse::<Version>(), Err(ParseVersionError));
+    }
+
+    #[test]
+    fn compare_and_display() {
+        assert!(Version::new(0, 10, 0) > Version::new(0, 9, 99));
+        assert!(Version::new(1, 0, 0) > Version::new(0, 10, 0));
+        assert_eq!(Version::new(0, 10, 0).to_string(), "0.10.0");
+
+        let version: u32 = Version::new(0, 0, 14).into();
+        assert_eq!(Version::from(version), DEFAULT_API_VERSION);
     }
 }
@@ -1,14 +1,19 @@
-use std::ffi::{OsStr, OsString};
-use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::cell::RefCell;
+use std::fmt;
 use std::slice;
 use std::ptr::NonNull;
 use windows::Win32::Foundation::LPARAM;
 
 pub type UnsafePtr<T> = Option<NonNull<T>>;
 
+/// 長さを指定せずにポインタから読み取る文字列の最大長 (UTF-16 単位、終端の NULL 文字を含まない)
+/// この範囲に NULL 文字が見つからない場合は終端されていない文字列として扱います
+pub const MAX_WIDE_STRING_LENGTH: usize = 0x100000;
+
 /// NULL 終端なワイド文字列
 #[cfg_attr(test, derive(Debug))]
 #[repr(transparent)]
+#[derive(core::default::Default)]
 pub struct WideString(pub Vec<u16>);
 
 impl WideString {
@@ -26,11 +31,11 @@ impl WideString {
     pub fn to_wide_string_ptr(&self) -> WideStringPtr {
         WideStringPtr(self.as_ptr())
     }
-}
 
-impl Default for WideString {
-    fn default() -> Self {
-        WideString(Vec::default())
+    /// WStr として借用します
+    /// NULL 文字で終端されていない場合は None を返します
+    pub fn as_wstr(&self) -> Option<WStr<'_>> {
+        WStr::from_wide_with_nul(&self.0)
     }
 }
 
@@ -43,10 +48,10 @@ pub trait IntoWideString {
 
 impl IntoWideString for &str {
     fn into_wide_string(self) -> WideString {
-        let vec: Vec<u16> = OsStr::new(&self)
-            .encode_wide()
+        let vec: Vec<u16> = self
+            .encode_utf16()
             // 末尾に NULL 文字を付加
-            .chain(Some(0).into_iter())
+            .chain(Some(0))
             .collect();
 
         WideString(vec)
@@ -55,19 +60,19 @@ impl IntoWideString for &str {
 
 impl IntoWideString for String {
     fn into_wide_string(self) -> WideString {
-        let vec: Vec<u16> = OsString::from(self)
-            .encode_wide()
-            // 末尾に NULL 文字を付加
-            .chain(Some(0).into_iter())
-            .collect();
-
-        WideString(vec)
+        self.as_str().into_wide_string()
     }
 }
 
+/// 最初の NULL 文字までを複製し、末尾に NULL 文字を付加します
+/// NULL 文字がない場合はスライス全体を複製します
 impl IntoWideString for &[u16] {
     fn into_wide_string(self) -> WideString {
-        let vec: Vec<u16> = OsString::from_wide(self).encode_wide().collect();
+        // 最初の NULL 文字までをそのまま複製するので、対になっていないサロゲートも保持される
+        let vec: Vec<u16> = until_nul(self).iter()
+            .copied()
+            .chain(Some(0))
+            .collect();
 
         WideString(vec)
     }
@@ -79,50 +84,149 @@ impl IntoWideString for Vec<u16> {
     }
 }
 
+/// 最初の NULL 文字より前の部分
+fn until_nul(wide: &[u16]) -> &[u16] {
+    let len = wide.iter().position(|&c| c == 0).unwrap_or(wide.len());
+
+    &wide[..len]
+}
+
 pub trait IntoRustString {
     /// UTF-8 な Rust 文字列に変換します
+    /// 最初の NULL 文字までを変換し、対になっていないサロゲートは U+FFFD に置き換えられます
     fn into_string(self) -> String;
 }
 
 impl IntoRustString for WideString {
     fn into_string(self) -> String {
-        let slice = self.0.as_slice();
-
-        OsString::from_wide(slice).to_string_lossy().into_owned()
+        self.0.as_slice().into_string()
     }
 }
 
 impl IntoRustString for &[u16] {
     fn into_string(self) -> String {
-        self.into_wide_string().into_string()
+        String::from_utf16_lossy(until_nul(self))
     }
 }
 
 impl IntoRustString for Vec<u16> {
     fn into_string(self) -> String {
-        self.into_wide_string().into_string()
+        self.as_slice().into_string()
+    }
+}
+
+/// 対になっていないサロゲートを含むワイド文字列を変換しようとした
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct UnpairedSurrogateError {
+    /// 位置 (UTF-16 単位)
+    pub index: usize,
+    /// サロゲート
+    pub unit: u16,
+}
+
+impl fmt::Display for UnpairedSurrogateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unpaired surrogate 0x{:04X} at index {}", self.unit, self.index)
+    }
+}
+
+impl std::error::Error for UnpairedSurrogateError {}
+
+pub trait TryIntoRustString {
+    /// UTF-8 な Rust 文字列に変換します
+    /// 最初の NULL 文字までを変換し、対になっていないサロゲートがある場合はエラーを返します
+    fn try_into_string(self) -> Result<String, UnpairedSurrogateError>;
+}
+
+impl TryIntoRustString for &[u16] {
+    fn try_into_string(self) -> Result<String, UnpairedSurrogateError> {
+        let mut string = String::new();
+        let mut index = 0;
+        for c in char::decode_utf16(until_nul(self).iter().copied()) {
+            match c {
+                Ok(c) => {
+                    string.push(c);
+                    index += c.len_utf16();
+                },
+                Err(e) => return Err(UnpairedSurrogateError { index, unit: e.unpaired_surrogate() }),
+            }
+        }
+
+        Ok(string)
+    }
+}
+
+impl TryIntoRustString for &WideString {
+    fn try_into_string(self) -> Result<String, UnpairedSurrogateError> {
+        self.0.as_slice().try_into_string()
+    }
+}
+
+impl TryIntoRustString for WStr<'_> {
+    fn try_into_string(self) -> Result<String, UnpairedSurrogateError> {
+        self.as_wide().try_into_string()
+    }
+}
+
+impl<const N: usize> TryIntoRustString for &FixedWideString<N> {
+    fn try_into_string(self) -> Result<String, UnpairedSurrogateError> {
+        self.as_wide().try_into_string()
     }
 }
 
 /// 固定長な NULL 終端ワイド文字列
+/// 最初の NULL 文字までが文字列で、それ以降の内容は無視されます
+#[derive(Copy, Clone)]
 #[cfg_attr(test, derive(Debug))]
 #[repr(transparent)]
 pub struct FixedWideString<const N: usize>(pub [u16; N]);
 
 impl<const N: usize> FixedWideString<N> {
-    pub fn to_wide_string(&self) -> WideString {
-        let vec = self.0.into_iter()
-            // NULL 文字を除外する
-            .filter(|x| *x != 0)
-            .collect();
+    /// 文字列から生成します
+    /// 終端の NULL 文字を含めて収まらない場合は、文字の境界で切り詰められます
+    pub fn new(string: &str) -> Self {
+        let mut fixed = Self::default();
+        fixed.set(string);
 
-        WideString(vec)
+        fixed
+    }
+
+    /// 文字列を設定します
+    /// 終端の NULL 文字を含めて収まらない場合は文字の境界で切り詰め、false を返します
+    pub fn set(&mut self, string: &str) -> bool {
+        // 終端の NULL 文字の分を残す
+        let capacity = N.saturating_sub(1);
+        let mut len = 0;
+        // N が 0 の場合は NULL 文字も収まらない
+        let mut fits = N > 0;
+        for c in string.chars() {
+            // サロゲートペアを分割しない
+            if len + c.len_utf16() > capacity {
+                fits = false;
+                break;
+            }
+            len += c.encode_utf16(&mut self.0[len..]).len();
+        }
+
+        // 以前の内容が残らないように残りを埋める
+        self.0[len..].fill(0);
+        fits
+    }
+
+    /// 最初の NULL 文字より前のワイド文字列
+    pub fn as_wide(&self) -> &[u16] {
+        until_nul(&self.0)
+    }
+
+    /// 非ポインターな NULL 終端の WideString に変換します
+    pub fn to_wide_string(&self) -> WideString {
+        self.as_wide().into_wide_string()
     }
 }
 
 impl<const N: usize> IntoRustString for FixedWideString<N> {
     fn into_string(self) -> String {
-        self.to_wide_string().into_string()
+        self.as_wide().into_string()
     }
 }
 
@@ -133,7 +237,7 @@ impl<const N: usize> Default for FixedWideString<N> {
 }
 
 /// NULL 終端なワイド文字列ポインタ
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, core::default::Default)]
 #[cfg_attr(test, derive(Debug))]
 #[repr(transparent)]
 pub struct WideStringPtr(pub UnsafePtr<u16>);
@@ -147,16 +251,42 @@ impl WideStringPtr {
     }
 
     /// 文字列の長さを返します
+    /// ぬるぽの場合や、NULL 文字が MAX_WIDE_STRING_LENGTH 文字以内に見つからない場合は None を返します
+    ///
+    /// # Safety
+    /// ポインタは NULL 文字か MAX_WIDE_STRING_LENGTH 文字まで読み取り可能である必要があります
     pub unsafe fn get_length(&self) -> Option<usize> {
-        let ptr = self.0;
-        if let Some(p) = ptr {
-            (0..isize::MAX).position(|i| *p.as_ptr().offset(i) == 0).unwrap().into()
-        } else {
-            None
-        }
+        self.get_length_within(MAX_WIDE_STRING_LENGTH + 1)
     }
 
-    /// 文字列のスライスを取得します
+    /// `max_len` 文字以内で NULL 文字を探し、文字列の長さを返します
+    /// ぬるぽの場合や、NULL 文字が見つからない場合は None を返します
+    ///
+    /// # Safety
+    /// ポインタは NULL 文字か `max_len` 文字まで読み取り可能である必要があります
+    pub unsafe fn get_length_within(&self, max_len: usize) -> Option<usize> {
+        let p = self.0?;
+
+        (0..max_len).position(|i| *p.as_ptr().add(i) == 0)
+    }
+
+    /// 長さを確認して WStr として借用します
+    /// NULL 文字が `max_len` 文字以内に見つからない場合は None を返します
+    ///
+    /// # Safety
+    /// 文字列は `'a` の間有効である必要があります
+    pub unsafe fn as_wstr<'a>(&self, max_len: usize) -> Option<WStr<'a>> {
+        let len = self.get_length_within(max_len)?;
+        let p = self.0?;
+
+        WStr::from_wide_with_nul_unchecked(slice::from_raw_parts(p.as_ptr(), len + 1)).into()
+    }
+
+    /// 終端の NULL 文字を含まない文字列のスライスを取得します
+    /// 長さは get_length で確認するため、MAX_WIDE_STRING_LENGTH 文字を超える場合は None を返します
+    ///
+    /// # Safety
+    /// ポインタは get_length と同じ条件を満たし、文字列はスライスを使い終わるまで有効である必要があります
     pub unsafe fn as_slice(&self) -> Option<&[u16]> {
         let ptr = self.0;
         let len = self.get_length()?;
@@ -169,6 +299,9 @@ impl WideStringPtr {
     }
 
     /// 非ポインターな WideString に変換します
+    ///
+    /// # Safety
+    /// ポインタは get_length と同じ条件を満たす必要があります
     pub unsafe fn to_wide_string(&self) -> Option<WideString> {
         let slice = self.as_slice()?;
 
@@ -176,12 +309,11 @@ impl WideStringPtr {
     }
 }
 
-impl Default for WideStringPtr {
-    fn default() -> Self {
-        WideStringPtr(None)
-    }
-}
-
+/// ポインタ先のワイド文字列の読み取り
+/// 長さは WideStringPtr::get_length で確認するため、MAX_WIDE_STRING_LENGTH 文字を超える文字列は読み取れません
+///
+/// # Safety
+/// 実装する型は、NULL でなければ読み取り可能な文字列を指している必要があります
 pub unsafe trait UnsafeIntoRustString {
     /// NULL 終端なワイド文字列ポインタ先を読み取ります
     fn read_wide_string(self) -> Option<WideString>;
@@ -206,12 +338,200 @@ unsafe impl UnsafeIntoRustString for WideStringPtr {
     }
 }
 
-impl Into<WideStringPtr> for &str {
-    fn into(self) -> WideStringPtr {
-        self.into_wide_string().to_wide_string_ptr()
+impl From<WStr<'static>> for WideStringPtr {
+    fn from(string: WStr<'static>) -> Self {
+        string.as_ptr()
     }
 }
 
+/// 借用された NULL 終端なワイド文字列
+/// 長さが確認済みで、借用元が生存している間だけ使用できます
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct WStr<'a> {
+    // 終端の NULL 文字を含む
+    wide: &'a [u16],
+}
+
+impl<'a> WStr<'a> {
+    /// NULL 文字で終端されたワイド文字列から生成します
+    /// 最初の NULL 文字までを文字列とし、NULL 文字がない場合は None を返します
+    pub fn from_wide_with_nul(wide: &'a [u16]) -> Option<Self> {
+        let len = wide.iter().position(|&c| c == 0)?;
+
+        unsafe { Self::from_wide_with_nul_unchecked(&wide[..=len]) }.into()
+    }
+
+    /// 確認せずに生成します
+    ///
+    /// # Safety
+    /// `wide` は最後の要素だけが NULL 文字である必要があります
+    pub const unsafe fn from_wide_with_nul_unchecked(wide: &'a [u16]) -> Self {
+        Self { wide }
+    }
+
+    /// WideStringPtr に変換します
+    /// 借用元が生存している間だけ有効です
+    pub fn as_ptr(&self) -> WideStringPtr {
+        WideStringPtr(NonNull::new(self.wide.as_ptr() as *mut u16))
+    }
+
+    /// 終端の NULL 文字を含まないワイド文字列
+    pub fn as_wide(&self) -> &'a [u16] {
+        &self.wide[..self.wide.len() - 1]
+    }
+
+    /// 終端の NULL 文字を含むワイド文字列
+    pub fn as_wide_with_nul(&self) -> &'a [u16] {
+        self.wide
+    }
+
+    /// 文字列の長さ (UTF-16 単位)
+    pub fn len(&self) -> usize {
+        self.wide.len() - 1
+    }
+
+    /// 空文字列か
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 非ポインターな WideString に変換します
+    pub fn to_wide_string(&self) -> WideString {
+        WideString(self.wide.to_vec())
+    }
+}
+
+impl WStr<'static> {
+    /// 文字列を解放されないバッファに変換します
+    /// PluginInfo やコントローラの登録など、プラグインの終了まで TVTest が参照する文字列に使用します
+    /// 途中に NULL 文字がある場合はそこで終端されます
+    pub fn leak(string: &str) -> Self {
+        let wide: &'static [u16] = Vec::leak(string.into_wide_string().0);
+
+        // 末尾に NULL 文字が付加されているので必ず生成できる
+        WStr::from_wide_with_nul(wide).unwrap()
+    }
+}
+
+impl fmt::Display for WStr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&String::from_utf16_lossy(self.as_wide()))
+    }
+}
+
+impl fmt::Debug for WStr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&String::from_utf16_lossy(self.as_wide()), f)
+    }
+}
+
+/// ワイド文字列を所有するバッファ
+/// 追加した文字列は WideStringBuffer が破棄されるまで解放されないため、
+/// 借用した WStr を TVTest に渡す構造体に設定できます
+///
+/// ```ignore
+/// let strings = WideStringBuffer::new();
+/// let info = ThemeDrawTextInfo::new(strings.push("panel.tab"), hdc, strings.push("番組表"), rect, 0, None);
+/// ```
+#[derive(core::default::Default)]
+pub struct WideStringBuffer {
+    strings: RefCell<Vec<Box<[u16]>>>,
+}
+
+impl WideStringBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 文字列を NULL 終端なワイド文字列としてバッファに追加し、借用した WStr を返します
+    /// 途中に NULL 文字がある場合はそこで終端されます
+    pub fn push(&self, string: &str) -> WStr<'_> {
+        let wide = string.into_wide_string().0.into_boxed_slice();
+        let (ptr, len) = (wide.as_ptr(), wide.len());
+        self.strings.borrow_mut().push(wide);
+
+        // Box の中身は Vec が再確保されても移動せず、バッファが破棄されるまで解放されない
+        let wide = unsafe { slice::from_raw_parts(ptr, len) };
+        // 末尾に NULL 文字が付加されているので必ず生成できる
+        WStr::from_wide_with_nul(wide).unwrap()
+    }
+
+    /// 追加された文字列の数
+    pub fn len(&self) -> usize {
+        self.strings.borrow().len()
+    }
+
+    /// 文字列が追加されていないか
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// wstr! で使用する、NULL 文字を含む UTF-16 の長さ
+#[doc(hidden)]
+pub const fn wide_len(string: &str) -> usize {
+    let bytes = string.as_bytes();
+    let mut i = 0;
+    let mut len = 1;
+    while i < bytes.len() {
+        let b = bytes[i];
+        assert!(b != 0, "wstr! cannot contain a nul character");
+        // 4 バイトの文字はサロゲートペアになる
+        let (width, units) = if b < 0x80 { (1, 1) } else if b < 0xE0 { (2, 1) } else if b < 0xF0 { (3, 1) } else { (4, 2) };
+        i += width;
+        len += units;
+    }
+
+    len
+}
+
+/// wstr! で使用する、コンパイル時の UTF-16 への変換
+#[doc(hidden)]
+pub const fn encode_wide<const N: usize>(string: &str) -> [u16; N] {
+    let bytes = string.as_bytes();
+    let mut wide = [0; N];
+    let mut i = 0;
+    let mut j = 0;
+    while i < bytes.len() {
+        let b = bytes[i] as u32;
+        let (c, width) = if b < 0x80 {
+            (b, 1)
+        } else if b < 0xE0 {
+            ((b & 0x1F) << 6 | (bytes[i + 1] as u32 & 0x3F), 2)
+        } else if b < 0xF0 {
+            ((b & 0x0F) << 12 | (bytes[i + 1] as u32 & 0x3F) << 6 | (bytes[i + 2] as u32 & 0x3F), 3)
+        } else {
+            ((b & 0x07) << 18 | (bytes[i + 1] as u32 & 0x3F) << 12 | (bytes[i + 2] as u32 & 0x3F) << 6 | (bytes[i + 3] as u32 & 0x3F), 4)
+        };
+        if c >= 0x10000 {
+            let c = c - 0x10000;
+            wide[j] = 0xD800 | (c >> 10) as u16;
+            wide[j + 1] = 0xDC00 | (c & 0x3FF) as u16;
+            j += 2;
+        } else {
+            wide[j] = c as u16;
+            j += 1;
+        }
+        i += width;
+    }
+
+    wide
+}
+
+/// 文字列リテラルをコンパイル時に NULL 終端な UTF-16 に変換し、`WStr<'static>` を生成します
+///
+/// ```ignore
+/// let name: WideStringPtr = wstr!("Example").into();
+/// ```
+#[macro_export]
+macro_rules! wstr {
+    ($string: expr) => {{
+        const WIDE: &[u16] = &$crate::win32::encode_wide::<{ $crate::win32::wide_len($string) }>($string);
+        const WSTR: $crate::win32::WStr<'static> = unsafe { $crate::win32::WStr::from_wide_with_nul_unchecked(WIDE) };
+        WSTR
+    }};
+}
+
 #[inline]
 pub(crate) fn make_lparam(l: u16, h: u16) -> LPARAM {
     LPARAM(
@@ -225,3 +545,84 @@ pub(crate) fn make_long(a: u16, b: u16) -> u32 {
     let b = ((b as usize) & 0xffff) as u32;
     a | b << 16
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wstr_is_nul_terminated_utf16() {
+        const NAME: WStr<'static> = wstr!("番組 🎵 TV");
+        let expected: Vec<u16> = "番組 🎵 TV".encode_utf16().chain(Some(0)).collect();
+        assert_eq!(NAME.as_wide_with_nul(), expected.as_slice());
+        assert_eq!((NAME.len(), NAME.to_string().as_str()), (expected.len() - 1, "番組 🎵 TV"));
+        assert!(wstr!("").is_empty());
+
+        // 最初の NULL 文字までを文字列とし、長さは指定された範囲内で確認する
+        let wide = [0x41, 0x42, 0, 0x43, 0];
+        assert_eq!(WStr::from_wide_with_nul(&wide).map(|s| s.as_wide()), Some(&wide[..2]));
+        assert!(WStr::from_wide_with_nul(&wide[..2]).is_none());
+        let ptr = WideStringPtr(NonNull::new(wide.as_ptr() as *mut u16));
+        assert_eq!(unsafe { ptr.as_wstr(3) }.map(|s| s.len()), Some(2));
+        assert!(unsafe { ptr.as_wstr(2) }.is_none());
+        assert!(unsafe { WideStringPtr::default().as_wstr(5) }.is_none());
+    }
+
+    #[test]
+    fn slice_into_wide_string_truncates_at_nul() {
+        // 最初の NULL 文字までを複製し、NULL 文字を 1 つだけ付加する
+        let wide: &[u16] = &[0x41, 0xD800, 0, 0x42, 0];
+        assert_eq!(wide.into_wide_string().0, vec![0x41, 0xD800, 0]);
+        // NULL 文字がない場合は全体を複製する
+        assert_eq!(wide[..2].into_wide_string().0, vec![0x41, 0xD800, 0]);
+        assert_eq!(wide[2..3].into_wide_string().0, vec![0]);
+        assert_eq!(Vec::<u16>::new().into_wide_string().0, vec![0]);
+    }
+
+    #[test]
+    fn fixed_wide_string_of_zero_length() {
+        let mut string = FixedWideString::<0>::default();
+        assert!(string.as_wide().is_empty());
+        // NULL 文字も格納できないので常に失敗する
+        assert!(!string.set("a"));
+        assert!(!string.set(""));
+        assert!(string.as_wide().is_empty());
+        assert_eq!(FixedWideString::<0>::new("abc").into_string(), "");
+    }
+
+    #[test]
+    fn read_string_is_bounded() {
+        // NULL 文字が MAX_WIDE_STRING_LENGTH 文字以内にない場合は終端されていないものとして扱う
+        let mut wide = vec![0x41; MAX_WIDE_STRING_LENGTH + 1];
+        let ptr = WideStringPtr(NonNull::new(wide.as_mut_ptr()));
+        assert!(unsafe { ptr.get_length() }.is_none());
+        assert!(ptr.read_string().is_none());
+
+        wide[MAX_WIDE_STRING_LENGTH] = 0;
+        let ptr = WideStringPtr(NonNull::new(wide.as_mut_ptr()));
+        assert_eq!(unsafe { ptr.get_length() }, Some(MAX_WIDE_STRING_LENGTH));
+        assert_eq!(ptr.read_string().map(|s| s.len()), Some(MAX_WIDE_STRING_LENGTH));
+        assert!(WideStringPtr::default().read_string().is_none());
+    }
+
+    #[test]
+    fn wide_string_buffer_keeps_strings() {
+        let strings = WideStringBuffer::new();
+        assert!(strings.is_empty());
+
+        let style = strings.push("panel.tab");
+        // 後から追加しても先に借用した文字列は移動しない
+        let texts: Vec<WStr> = (0..100).map(|i| strings.push(&format!("番組{}", i))).collect();
+        assert_eq!(strings.len(), 101);
+        assert_eq!(style.as_ptr().read_string().as_deref(), Some("panel.tab"));
+        assert_eq!(texts[99].as_ptr().read_string().as_deref(), Some("番組99"));
+
+        // 途中の NULL 文字で終端される
+        let truncated = strings.push("a\0b");
+        assert_eq!(truncated.as_wide_with_nul(), &[0x61, 0]);
+
+        let leaked = WStr::leak("TVTest\0Plugin");
+        assert_eq!(leaked.to_string(), "TVTest");
+        assert_eq!(WideStringPtr::from(leaked).read_string().as_deref(), Some("TVTest"));
+    }
+}
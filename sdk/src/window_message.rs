@@ -0,0 +1,158 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use windows::Win32::Foundation::{BOOL, HWND, LPARAM, LRESULT, WPARAM};
+use crate::ClientData;
+use crate::message::Message;
+use crate::plugin::PluginParam;
+
+/// ウィンドウメッセージコールバック関数
+/// メッセージを処理した場合は TRUE を返し、result に戻り値を設定します
+pub type WindowMessageCallbackFunc = unsafe extern "system" fn(
+    hwnd: HWND,
+    message: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+    result: *mut LRESULT,
+    client_data: ClientData,
+) -> BOOL;
+
+/// ウィンドウメッセージを処理するクロージャ
+/// メッセージを処理した場合は Some(戻り値) を返します
+pub type WindowMessageHandler = dyn Fn(HWND, u32, WPARAM, LPARAM) -> Option<LRESULT>;
+
+/// 現在 TVTest に設定されているハンドラ
+static CURRENT_HANDLER: AtomicPtr<Box<WindowMessageHandler>> = AtomicPtr::new(ptr::null_mut());
+
+/// メインウィンドウのメッセージのフック
+/// Drop 時にコールバックの設定を解除します
+/// 一つのプラグインで設定できるコールバックは一つだけなので、新しいフックを設定すると古いフックは呼ばれなくなります
+pub struct WindowMessageHook {
+    param: Arc<PluginParam>,
+    handler: *mut Box<WindowMessageHandler>,
+}
+
+impl WindowMessageHook {
+    pub(crate) fn register(param: Arc<PluginParam>, handler: Box<WindowMessageHandler>) -> Option<Self> {
+        let handler = Box::into_raw(Box::new(handler));
+        let callback = window_message_callback as WindowMessageCallbackFunc;
+        let result = param.send_message_bool(Message::SetWindowMessageCallback, LPARAM(callback as usize as isize), LPARAM(handler as isize));
+
+        if result {
+            CURRENT_HANDLER.store(handler, Ordering::SeqCst);
+
+            WindowMessageHook {
+                param,
+                handler,
+            }.into()
+        } else {
+            drop(unsafe { Box::from_raw(handler) });
+
+            None
+        }
+    }
+}
+
+impl Drop for WindowMessageHook {
+    fn drop(&mut self) {
+        // 後から設定された別のフックは解除しない
+        if CURRENT_HANDLER.compare_exchange(self.handler, ptr::null_mut(), Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+            self.param.send_message(Message::SetWindowMessageCallback, LPARAM(0), LPARAM(0));
+        }
+
+        drop(unsafe { Box::from_raw(self.handler) });
+    }
+}
+
+unsafe extern "system" fn window_message_callback(
+    hwnd: HWND,
+    message: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+    result: *mut LRESULT,
+    client_data: ClientData,
+) -> BOOL {
+    let handler = match client_data {
+        Some(p) => &*(p.as_ptr() as *const Box<WindowMessageHandler>),
+        None => return BOOL(0),
+    };
+
+    // パニックした場合はメッセージを処理しなかったものとして扱う
+    match catch_unwind(AssertUnwindSafe(|| handler(hwnd, message, wparam, lparam))) {
+        Ok(Some(value)) => {
+            if !result.is_null() {
+                *result = value;
+            }
+            BOOL(1)
+        },
+        _ => BOOL(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::ptr::NonNull;
+    use std::rc::Rc;
+    use windows::Win32::Foundation::{LRESULT, WPARAM};
+    use crate::mock::MockHost;
+    use super::*;
+
+    const WM_USER: u32 = 0x0400;
+
+    unsafe fn dispatch(host: &MockHost, message: u32) -> (bool, LRESULT) {
+        let (callback, client_data) = *host.sent(Message::SetWindowMessageCallback).last().unwrap();
+        let callback: WindowMessageCallbackFunc = std::mem::transmute(callback.0);
+        let mut result = LRESULT(0);
+        let handled = callback(HWND(1), message, WPARAM(2), LPARAM(3), &mut result, NonNull::new(client_data.0 as *mut _));
+
+        (handled.as_bool(), result)
+    }
+
+    #[test]
+    fn hook_lifecycle() {
+        let host = MockHost::new();
+        host.on(Message::SetWindowMessageCallback, |_, _| LRESULT(1));
+        let api = host.api();
+
+        let calls = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&calls);
+        let hook = api.set_window_message_callback(move |hwnd, message, wparam, lparam| {
+            counter.set(counter.get() + 1);
+            assert_eq!((hwnd, wparam, lparam), (HWND(1), WPARAM(2), LPARAM(3)));
+
+            match message {
+                WM_USER => Some(LRESULT(42)),
+                0 => panic!("handler panicked"),
+                _ => None,
+            }
+        }).unwrap();
+
+        unsafe {
+            assert_eq!(dispatch(&host, WM_USER), (true, LRESULT(42)));
+            assert_eq!(dispatch(&host, WM_USER + 1), (false, LRESULT(0)));
+            // パニックはホストに伝播しない
+            assert_eq!(dispatch(&host, 0), (false, LRESULT(0)));
+        }
+        assert_eq!(calls.get(), 3);
+
+        // 新しいフックを設定した後に古いフックを破棄しても、新しいフックは解除されない
+        let newer = api.set_window_message_callback(|_, _, _, _| Some(LRESULT(7))).unwrap();
+        drop(hook);
+        assert_eq!(host.sent(Message::SetWindowMessageCallback).len(), 2);
+        unsafe {
+            assert_eq!(dispatch(&host, WM_USER), (true, LRESULT(7)));
+        }
+
+        drop(newer);
+        assert_eq!(host.sent(Message::SetWindowMessageCallback).last(), Some(&(LPARAM(0), LPARAM(0))));
+    }
+
+    #[test]
+    fn register_failure() {
+        let host = MockHost::new();
+
+        assert!(host.api().set_window_message_callback(|_, _, _, _| None).is_none());
+    }
+}